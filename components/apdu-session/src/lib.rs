@@ -0,0 +1,119 @@
+#![no_std]
+//! Session state of the APDU apps, on top of `apdu_dispatch`.
+//!
+//! A session ends when the contact card is powered off or on, or the
+//! contactless card leaves the field or is deselected.  Like a real card, the
+//! device then forgets about the selected app: `Session::reset` deselects just
+//! that app, which gets no further commands until it is selected again.
+//!
+//! The dispatcher keeps its own, private idea of the selected app, so the apps
+//! are wrapped in `Session::dispatch` to follow SELECT and deselection.
+
+use core::cell::Cell;
+
+use apdu_dispatch::{app, response, Command, command::SIZE as CommandSize, response::SIZE as ResponseSize};
+use heapless::Vec;
+use iso7816::Status;
+
+/// The most apps a session can track.
+pub const MAX_APPS: usize = 8;
+
+#[derive(Default)]
+pub struct Session {
+    // index of the selected app
+    selected: Cell<Option<usize>>,
+    // index of the app deselected by a reset, the dispatcher may still route commands to it
+    stale: Cell<Option<usize>>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Index of the selected app, if any.
+    pub fn selected(&self) -> Option<usize> {
+        self.selected.get()
+    }
+
+    /// Calls `f`, usually `ApduDispatch::poll`, with the `apps`, tracking which one is selected.
+    ///
+    /// The `apps` must be passed in the same order to every call and to `reset`.
+    pub fn dispatch<F, T>(&mut self, apps: &mut [&mut dyn app::App<CommandSize, ResponseSize>], f: F) -> T
+    where
+        F: FnOnce(&mut [&mut dyn app::App<CommandSize, ResponseSize>]) -> T,
+    {
+        assert!(apps.len() <= MAX_APPS, "too many apps");
+        let session = &*self;
+        let mut tracked: Vec<Tracked<'_>, MAX_APPS> = apps
+            .iter_mut()
+            .enumerate()
+            .map(|(index, inner)| Tracked { inner: &mut **inner, index, session })
+            .collect();
+        let mut apps: Vec<&mut dyn app::App<CommandSize, ResponseSize>, MAX_APPS> = tracked
+            .iter_mut()
+            .map(|tracked| tracked as &mut dyn app::App<CommandSize, ResponseSize>)
+            .collect();
+        f(&mut apps)
+    }
+
+    /// Ends the session: deselects the selected app, if any, and answers further
+    /// commands for it with `NotFound` until an app is selected.
+    pub fn reset(&mut self, apps: &mut [&mut dyn app::App<CommandSize, ResponseSize>]) {
+        if let Some(index) = self.selected.take() {
+            if let Some(app) = apps.get_mut(index) {
+                app.deselect();
+            }
+            self.stale.set(Some(index));
+        }
+    }
+}
+
+struct Tracked<'a> {
+    inner: &'a mut dyn app::App<CommandSize, ResponseSize>,
+    index: usize,
+    session: &'a Session,
+}
+
+impl Tracked<'_> {
+    fn is_stale(&self) -> bool {
+        self.session.stale.get() == Some(self.index)
+    }
+}
+
+impl iso7816::App for Tracked<'_> {
+    fn aid(&self) -> iso7816::Aid {
+        self.inner.aid()
+    }
+}
+
+impl app::App<CommandSize, ResponseSize> for Tracked<'_> {
+    fn select(&mut self, apdu: &Command, reply: &mut response::Data) -> app::Result {
+        let result = self.inner.select(apdu, reply);
+        if result.is_ok() {
+            self.session.selected.set(Some(self.index));
+            self.session.stale.set(None);
+        }
+        result
+    }
+
+    fn deselect(&mut self) {
+        if self.is_stale() {
+            // deselected by the reset already
+            self.session.stale.set(None);
+            return;
+        }
+        if self.session.selected.get() == Some(self.index) {
+            self.session.selected.set(None);
+        }
+        self.inner.deselect();
+    }
+
+    fn call(&mut self, interface: app::Interface, apdu: &Command, reply: &mut response::Data) -> app::Result {
+        if self.is_stale() {
+            // no app is selected after the reset
+            return Err(Status::NotFound);
+        }
+        self.inner.call(interface, apdu, reply)
+    }
+}
@@ -0,0 +1,109 @@
+use apdu_dispatch::{
+    app, dispatch::ApduDispatch, interchanges, response, Command, command::SIZE as CommandSize,
+    response::SIZE as ResponseSize,
+};
+use apdu_session::Session;
+use interchange::{Interchange, Requester};
+
+struct TestApp {
+    aid: [u8; 5],
+    calls: usize,
+    deselects: usize,
+}
+
+impl TestApp {
+    fn new(last: u8) -> Self {
+        Self { aid: [0xF0, 0x00, 0x00, 0x00, last], calls: 0, deselects: 0 }
+    }
+
+    fn select_apdu(&self) -> Vec<u8> {
+        let mut apdu = vec![0x00, 0xA4, 0x04, 0x00, self.aid.len() as u8];
+        apdu.extend_from_slice(&self.aid);
+        apdu
+    }
+}
+
+impl iso7816::App for TestApp {
+    fn aid(&self) -> iso7816::Aid {
+        iso7816::Aid::new(&self.aid)
+    }
+}
+
+impl app::App<CommandSize, ResponseSize> for TestApp {
+    fn select(&mut self, _apdu: &Command, _reply: &mut response::Data) -> app::Result {
+        Ok(())
+    }
+
+    fn deselect(&mut self) {
+        self.deselects += 1;
+    }
+
+    fn call(&mut self, _interface: app::Interface, _apdu: &Command, reply: &mut response::Data) -> app::Result {
+        self.calls += 1;
+        reply.push(self.aid[4]).unwrap();
+        Ok(())
+    }
+}
+
+const GET_DATA: &[u8] = &[0x00, 0xCA, 0x00, 0x00];
+
+fn transceive(
+    requester: &mut Requester<interchanges::Contact>,
+    dispatch: &mut ApduDispatch,
+    session: &mut Session,
+    first: &mut TestApp,
+    second: &mut TestApp,
+    apdu: &[u8],
+) -> Vec<u8> {
+    requester.request(&interchanges::Data::from_slice(apdu).unwrap()).ok().unwrap();
+    session.dispatch(&mut [first, second], |apps| dispatch.poll(apps));
+    requester.take_response().unwrap().to_vec()
+}
+
+#[test]
+fn reset_deselects_the_selected_app() {
+    let (mut requester, contact) = interchanges::Contact::claim().unwrap();
+    let (_, contactless) = interchanges::Contactless::claim().unwrap();
+    let mut dispatch = ApduDispatch::new(contact, contactless);
+    let mut session = Session::new();
+    let mut first = TestApp::new(1);
+    let mut second = TestApp::new(2);
+
+    let select = first.select_apdu();
+    let response = transceive(&mut requester, &mut dispatch, &mut session, &mut first, &mut second, &select);
+    assert_eq!(response, [0x90, 0x00]);
+    assert_eq!(session.selected(), Some(0));
+    let response = transceive(&mut requester, &mut dispatch, &mut session, &mut first, &mut second, GET_DATA);
+    assert_eq!(response, [0x01, 0x90, 0x00]);
+
+    // only the selected app is deselected
+    session.reset(&mut [&mut first, &mut second]);
+    assert_eq!((first.deselects, second.deselects), (1, 0));
+    assert_eq!(session.selected(), None);
+
+    // and no longer gets commands, though the dispatcher still routes them to it
+    let response = transceive(&mut requester, &mut dispatch, &mut session, &mut first, &mut second, GET_DATA);
+    assert_eq!(response, [0x6A, 0x82]);
+    assert_eq!(first.calls, 1);
+
+    // selecting another app does not deselect the first one twice
+    let select = second.select_apdu();
+    let response = transceive(&mut requester, &mut dispatch, &mut session, &mut first, &mut second, &select);
+    assert_eq!(response, [0x90, 0x00]);
+    assert_eq!((first.deselects, second.deselects), (1, 0));
+    assert_eq!(session.selected(), Some(1));
+    let response = transceive(&mut requester, &mut dispatch, &mut session, &mut first, &mut second, GET_DATA);
+    assert_eq!(response, [0x02, 0x90, 0x00]);
+
+    // a reset without a selected app is a no-op
+    session.reset(&mut [&mut first, &mut second]);
+    session.reset(&mut [&mut first, &mut second]);
+    assert_eq!((first.deselects, second.deselects), (1, 1));
+
+    // selecting the same app again starts a new session with it
+    let response = transceive(&mut requester, &mut dispatch, &mut session, &mut first, &mut second, &select);
+    assert_eq!(response, [0x90, 0x00]);
+    let response = transceive(&mut requester, &mut dispatch, &mut session, &mut first, &mut second, GET_DATA);
+    assert_eq!(response, [0x02, 0x90, 0x00]);
+    assert_eq!(second.calls, 2);
+}
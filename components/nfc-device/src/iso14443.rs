@@ -23,80 +23,155 @@ pub enum Iso14443Status {
 // Max iso14443 frame is 256 bytes
 type Iso14443Frame = Vec<u8, 256>;
 
+// FSD bounds, cf. ISO 14443-4, 5.2.3: FSDI 0 is 16 bytes, larger FSD are capped to our frames
+const MIN_FRAME_SIZE: usize = 16;
+const MAX_FRAME_SIZE: usize = 256;
+// CRC_A, added by the device
+const CRC_LENGTH: usize = 2;
+
 #[derive(Clone, PartialEq)]
 enum Iso14443State {
     Receiving,
-    /// last_frame_transmitted, remaining_bytes_to_transmit.
-    Transmitting(core::ops::Range<usize>, core::ops::Range<usize>),
+    /// The PCD chains the command.
+    ReceivingChain,
+    /// remaining_bytes_to_transmit.
+    Transmitting(core::ops::Range<usize>),
 }
 
+// Protocol control byte, cf. ISO 14443-4, 7.1.1.1
+const PCB_BLOCK_NUM: u8 = 0x01;
+const PCB_NAD: u8 = 0x04;
+const PCB_CID: u8 = 0x08;
+// I-blocks
+const PCB_CHAINING: u8 = 0x10;
+// R-blocks
+const PCB_NAK: u8 = 0x10;
+
+const I_BLOCK: u8 = 0x02;
+const R_BLOCK_ACK: u8 = 0xa2;
+const S_BLOCK_DESELECT: u8 = 0xc2;
+const S_BLOCK_WTX: u8 = 0xf2;
+
+// the power level indication (b8, b7) of the CID byte is not supported
+const CID_MASK: u8 = 0x0f;
+// the power level indication (b8, b7) of the WTX INF byte is not supported
+const WTXM_MASK: u8 = 0x3f;
+const MAX_WTXM: u8 = 59;
+
 type Ack = bool;
 type Chaining = bool;
 type BlockNum = bool;
 type Offset = usize;
-type WtxGranted = bool;
 type Nad = Option<u8>;
 type Cid = Option<u8>;
 
-#[derive(Copy,Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Supervisory {
+    Deselect,
+    /// Response to our S(WTX) request, with the granted multiplier.
+    Wtx(u8),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 enum Block {
     IBlock(BlockNum, Nad, Cid, Chaining, Offset),
-    RBlock(BlockNum, Cid, Ack, Offset),
-    SBlock(Cid, WtxGranted, ),
+    RBlock(BlockNum, Cid, Ack),
+    SBlock(Cid, Supervisory),
 }
 
 impl Block {
-    fn new(frame: &[u8]) -> Block {
-        let header = frame[0];
+    /// Parses a frame from the PCD (without CRC), `None` if it is not a valid block.
+    fn new(frame: &[u8]) -> Option<Block> {
+        let header = *frame.first()?;
 
-        let block_num = (header & 1) != 0;
-        let flag = (header & 0x10) != 0;
+        let block_num = (header & PCB_BLOCK_NUM) != 0;
         let mut offset = 1;
 
         // CID included
-        let cid = if (header & 0x08) != 0 {
+        let cid = if (header & PCB_CID) != 0 {
             offset += 1;
-            Some(frame[1])
+            Some(*frame.get(1)? & CID_MASK)
         } else {
             None
         };
 
-        if (header & 0xc2) == 0x02 {
-
+        if (header & 0xe2) == I_BLOCK {
             // NAD included
-            let nad = if (header & 0x4) != 0 {
+            let nad = if (header & PCB_NAD) != 0 {
                 offset += 1;
-                if cid.is_some() {
-                    Some(frame[2])
-                } else {
-                    Some(frame[1])
-                }
+                Some(*frame.get(offset - 1)?)
             } else {
                 None
             };
-            Block::IBlock(block_num, nad, cid, flag, offset)
-        } else if (header & 0xe2) == 0xa2 {
-                                    // Ack or Nack
-            Block::RBlock(block_num, cid, !flag, offset)
+            Some(Block::IBlock(block_num, nad, cid, (header & PCB_CHAINING) != 0, offset))
+        } else if (header & 0xe6) == R_BLOCK_ACK {
+            // no INF field
+            if frame.len() != offset {
+                return None;
+            }
+            // Ack or Nack
+            Some(Block::RBlock(block_num, cid, (header & PCB_NAK) == 0))
+        } else if (header & 0xf7) == S_BLOCK_DESELECT {
+            if frame.len() != offset {
+                return None;
+            }
+            Some(Block::SBlock(cid, Supervisory::Deselect))
+        } else if (header & 0xf7) == S_BLOCK_WTX {
+            // INF field is the WTXM
+            if frame.len() != offset + 1 {
+                return None;
+            }
+            let wtxm = frame[offset] & WTXM_MASK;
+            if wtxm == 0 || wtxm > MAX_WTXM {
+                return None;
+            }
+            Some(Block::SBlock(cid, Supervisory::Wtx(wtxm)))
         } else {
-            Block::SBlock(cid, (0x30 & header) == 0x30)
+            // RFU
+            None
+        }
+    }
+
+    fn cid(&self) -> Cid {
+        match *self {
+            Block::IBlock(_, _, cid, _, _) => cid,
+            Block::RBlock(_, cid, _) => cid,
+            Block::SBlock(cid, _) => cid,
         }
     }
 }
 
-/// Iso14443 device follows related rules for PICC in iso14443-4.
-/// Rules C - E and rules 9 - 13.
+/// Iso14443 device follows the rules for the PICC of the block transmission
+/// protocol in iso14443-4, 7.5.4: rules 1 - 3, 9 - 13 and C - E.
+///
+/// Activation (RATS) and the CRC are left to the device.  Invalid blocks are
+/// ignored, the PCD recovers with an R(NAK).  Responses are chained for the FSD
+/// of the device, `nfc::Device::frame_size`.
 pub struct Iso14443<DEV: nfc::Device> {
     device: DEV,
 
     state: Iso14443State,
 
+    // CID used by the PCD, echoed in our blocks
     cid: Option<u8>,
+    // NAD of the current command, echoed in the first block of the response
+    nad: Option<u8>,
 
     // Current block number for PICC
     block_num: bool,
     // Used to see if wtx was accepted or not
     wtx_requested: bool,
+    // The chained command does not fit the buffer
+    overflow: bool,
+    // Field loss or DESELECT since the last call of `did_reset_session`
+    session_reset: bool,
+    // The app still works on a withdrawn command, its response is dropped once it arrives
+    stale: bool,
+    // The command in the buffer waits for the app to finish the withdrawn one
+    queued: bool,
+
+    // Retransmitted on request of the PCD (Rule 11)
+    last_block: Iso14443Frame,
 
     buffer: interchanges::Data,
 
@@ -112,66 +187,86 @@ where
             device: device,
             state: Iso14443State::Receiving,
             cid: None,
+            nad: None,
 
             wtx_requested: false,
+            overflow: false,
+            session_reset: false,
+            stale: false,
+            queued: false,
             block_num: true,
 
+            last_block: Vec::new(),
+
             buffer: Vec::new(),
 
             interchange: interchange,
         }
     }
 
-    fn ack(&mut self) {
-        let mut packet = [0u8; 3];
-        let mut length = 1;
-        packet[0] = 0xA2u8 | (self.block_num as u8);
+    // PCB, and the CID if the PCD uses one
+    fn prologue(&self, pcb: u8) -> Iso14443Frame {
+        let mut frame = Iso14443Frame::new();
+        frame.push(pcb).ok();
         if let Some(cid) = self.cid {
-            packet[0] |= 0x08;
-            packet[1] = cid;
-            length += 1;
+            frame[0] |= PCB_CID;
+            frame.push(cid).ok();
         }
+        frame
+    }
 
-        self.device.send(
-            & packet[0 .. length]
-        ).ok();
+    fn ack(&mut self) {
+        let frame = self.prologue(R_BLOCK_ACK | (self.block_num as u8));
+        self.send_frame(&frame).ok();
     }
 
     fn send_wtx(&mut self) {
         // Rule 9. The PICC is allowed to send an S(WTX) block instead of an I-block or an R(ACK) block.
-        match self.cid {
-            Some(cid) => {
-                self.device.send(
-                    &[0xfa, cid, 0x01]
-                ).ok();
-            }
-            _ => {
-                self.device.send(
-                    &[0xf2, 0x01]
-                ).ok();
-            }
-        }
+        let mut frame = self.prologue(S_BLOCK_WTX);
+        frame.push(0x01).ok();
+        self.send_frame(&frame).ok();
     }
-    // IBlock(BlockNum, Nad, Cid, Chaining, ),
-    // RBlock(BlockNum, Cid, Ack, ),
-    // SBlock(Cid, WtxGranted, ),
+
+    // IBlock(BlockNum, Nad, Cid, Chaining, Offset),
+    // RBlock(BlockNum, Cid, Ack),
+    // SBlock(Cid, Supervisory),
     fn handle_block(&mut self, packet: &[u8]) -> Result<(), SourceError> {
-        let block_header = Block::new(packet);
-        match block_header {
-            Block::IBlock(_block_num, _nad, _cid, chaining, offset) => {
+        let block = match Block::new(packet) {
+            Some(block) => block,
+            None => {
+                info!("Invalid block, ignored.");
+                return Err(SourceError::NoActivity);
+            }
+        };
+        self.cid = block.cid();
+
+        match block {
+            Block::IBlock(_block_num, nad, _cid, chaining, offset) => {
 
-                if self.state != Iso14443State::Receiving {
+                if self.state != Iso14443State::ReceivingChain {
+                    // a new command, the PCD is no longer interested in the last one
                     self.buffer.clear();
+                    self.overflow = false;
+                    self.cancel_request();
+                    self.nad = nad;
+                }
+                self.state = match chaining {
+                    true => Iso14443State::ReceivingChain,
+                    false => Iso14443State::Receiving,
+                };
+
+                if self.buffer.extend_from_slice(& packet[offset .. ]).is_err() {
+                    info!("Command too long, dropped.");
+                    self.overflow = true;
                 }
-                self.state = Iso14443State::Receiving;
-
-                self.buffer.extend_from_slice(& packet[offset .. ]).ok();
 
                 // Rule D. When an I-block is received (independent of its block number),
                 // the PICC shall toggle its block number before sending a block.
                 self.block_num = !self.block_num;
 
                 if chaining {
+                    // Rule 2. When an I-block indicating chaining is received,
+                    // the block shall be acknowledged by an R(ACK) block.
                     self.ack();
                     Err(SourceError::NoActivity)
                 } else {
@@ -182,96 +277,72 @@ where
                 }
 
             }
-            Block::RBlock(block_num, _cid, ack, _offset) => {
+            Block::RBlock(block_num, _cid, ack) => {
 
-                // Rule 11. When an R(ACK) or an R(NAK) block is received,
-                // if its block number is equal to the PICC’s current block
-                // number, the last block shall be re-transmitted.
                 if block_num == self.block_num {
-                    match self.state.clone() {
-                        Iso14443State::Transmitting(last_frame_range, _remaining_data_range) => {
-                            info!("Retransmission requested..");
-                            self.send_frame(
-                                &Vec::from_slice(
-                                    &self.buffer[last_frame_range]
-                                ).unwrap()
-                            ).ok();
-                        }
-                        _ => {
-                            info!("No recent transmissions! NAK");
-
-                        }
+                    // Rule 11. When an R(ACK) or an R(NAK) block is received,
+                    // if its block number is equal to the PICC’s current block
+                    // number, the last block shall be re-transmitted.
+                    if self.last_block.is_empty() {
+                        info!("No recent transmissions!");
+                    } else {
+                        info!("Retransmission requested..");
+                        let last_block = self.last_block.clone();
+                        self.send_frame(&last_block).ok();
                     }
-                    return Err(SourceError::NoActivity);
                 } else if !ack {
                     // Rule 12. When an R(NAK) block is received,
                     // if its block number is not equal to the PICC’s
                     // current block number, an R(ACK) block shall be sent.
                     info!("pong");
                     self.ack();
-                    return Err(SourceError::NoActivity);
                 } else {
 
                     // Rule 13. When an R(ACK) block is received,
                     // if its block number is not equal to the PICC’s current block number,
                     // and the PICC is in chaining, chaining shall be continued.
-
                     match self.state.clone() {
-                        Iso14443State::Transmitting(_last_frame_range, remaining_data_range) => {
-                                // Rule E. When an R(ACK) block with a block number not equal
-                                // to the current PICC’s block number is received, the
-                                // PICC shall toggle its block number before sending a block.
-                                self.block_num = !self.block_num;
-
-                                if remaining_data_range.len() == 0 {
-                                    info!("Error, recieved ack when this is no more data.");
-                                    self.ack();
-                                    self.reset_state();
-                                    return Err(SourceError::NoActivity);
-                                }
-                                let msg = &self.buffer[remaining_data_range.clone()];
-                                let (next_frame, data_used) = self.construct_iblock(msg);
-                                self.send_frame(&next_frame).ok();
-                                if data_used != remaining_data_range.len() {
-                                    info!("Next frame");
-                                    self.state = Iso14443State::Transmitting(
-                                        remaining_data_range.start .. remaining_data_range.start + data_used,
-                                        remaining_data_range.start + data_used .. self.buffer.len(),
-                                    )
-                                } else {
-                                    info!("Last frame sent!");
-                                    self.state = Iso14443State::Transmitting(
-                                        remaining_data_range.start .. remaining_data_range.start + data_used,
-                                        self.buffer.len() .. self.buffer.len()
-                                    )
-                                }
-
+                        Iso14443State::Transmitting(remaining_data_range) if !remaining_data_range.is_empty() => {
+                            // Rule E. When an R(ACK) block with a block number not equal
+                            // to the current PICC’s block number is received, the
+                            // PICC shall toggle its block number before sending a block.
+                            self.block_num = !self.block_num;
+
+                            let msg = &self.buffer[remaining_data_range.clone()];
+                            let (next_frame, data_used) = self.construct_iblock(msg, None);
+                            self.send_frame(&next_frame).ok();
+                            if data_used != remaining_data_range.len() {
+                                info!("Next frame");
+                            } else {
+                                info!("Last frame sent!");
+                            }
+                            self.state = Iso14443State::Transmitting(
+                                remaining_data_range.start + data_used .. self.buffer.len(),
+                            );
                         }
                         _ => {
-                            // (None, Iso14443State::Idle)
-                            info!("Unexpected Rblock ack");
-                            self.ack();
+                            info!("Unexpected Rblock ack, not chaining.");
                         }
                     };
 
                 }
                 Err(SourceError::NoActivity)
             }
-            Block::SBlock(_cid, wtxgranted) => {
-                if wtxgranted {
-                    if self.wtx_requested {
-                        info!("wtx accepted");
-                    } else {
-                        info!("unsolicited wtx");
-                    }
-                    self.wtx_requested = false;
+            Block::SBlock(_cid, Supervisory::Wtx(_wtxm)) => {
+                // Rule 3. S-blocks are only used in pairs.
+                if self.wtx_requested {
+                    info!("wtx accepted");
                 } else {
-                    info!("Deselected.");
-                    self.device.send(
-                        &[0xc2]
-                    ).ok();
-                    self.reset_state();
+                    info!("unsolicited wtx, ignored");
                 }
+                self.wtx_requested = false;
+                Err(SourceError::NoActivity)
+            }
+            Block::SBlock(_cid, Supervisory::Deselect) => {
+                info!("Deselected.");
+                let frame = self.prologue(S_BLOCK_DESELECT);
+                self.send_frame(&frame).ok();
+                self.reset_state();
                 Err(SourceError::NoActivity)
             }
         }
@@ -281,43 +352,93 @@ where
         func(&mut self.device);
     }
 
-    fn construct_iblock(&self, data: &[u8]) -> (Iso14443Frame, usize) {
+    fn construct_iblock(&self, data: &[u8], nad: Option<u8>) -> (Iso14443Frame, usize) {
         // iblock header
-        let mut frame = Iso14443Frame::new();
-        frame.push(0).ok();
-        let mut header_length = 1;
+        let mut frame = self.prologue(I_BLOCK | (self.block_num as u8));
 
-        frame[0] = 0x02u8 | (self.block_num as u8);
-
-        if let Some(cid) = self.cid {
-            frame.push(cid).ok();
-            frame[0]|= 0x08;
-            header_length += 1;
+        // only in the first block of a chain
+        if let Some(nad) = nad {
+            frame[0] |= PCB_NAD;
+            frame.push(nad).ok();
         }
+        let header_length = frame.len();
 
-        // minus 2 to leave room for crc
-        let frame_size: usize = self.device.frame_size() - 2;
-        let payload_len = core::cmp::min(frame_size - header_length, data.len());
+        let payload_len = core::cmp::min(self.max_frame_length() - header_length, data.len());
 
         frame.extend_from_slice(&data[0 .. payload_len]).ok();
 
         if payload_len != data.len() {
             // set chaining bit.
-            frame[0] |= 0x10;
+            frame[0] |= PCB_CHAINING;
         }
 
         (frame, payload_len)
     }
 
+    // The longest block the PCD accepts (FSD), leaving room for the CRC.
+    fn max_frame_length(&self) -> usize {
+        let frame_size = self.device.frame_size().clamp(MIN_FRAME_SIZE, MAX_FRAME_SIZE);
+        frame_size - CRC_LENGTH
+    }
+
+    // Withdraws the command from the app, so a late response is never sent
+    // unsolicited (Rule 1), e.g. in the next session.
+    fn cancel_request(&mut self) {
+        self.queued = false;
+        // the app may have answered already, before we polled it
+        if self.interchange.take_response().is_some() {
+            self.stale = false;
+            return;
+        }
+        match self.interchange.state() {
+            interchange::State::Requested => {
+                info!("dropping the command of the app");
+                self.interchange.cancel().ok();
+            }
+            interchange::State::BuildingResponse => {
+                // the dispatcher does not acknowledge cancellations, let the app finish
+                info!("dropping the response of the app");
+                self.stale = true;
+            }
+            _ => {}
+        }
+    }
+
+    // Drops the response to a withdrawn command once the app is done,
+    // and passes on the command that waited for it.
+    fn drop_stale_response(&mut self) {
+        if self.stale && self.interchange.state() == interchange::State::Responded {
+            info!("late response dropped");
+            self.interchange.take_response();
+            self.stale = false;
+            if self.queued {
+                self.queued = false;
+                self.request_command().ok();
+            }
+        }
+    }
+
     fn reset_state(&mut self) {
         self.buffer.clear();
+        self.overflow = false;
+        self.last_block.clear();
         self.state = Iso14443State::Receiving;
         self.cid = None;
+        self.nad = None;
+        self.wtx_requested = false;
+        self.cancel_request();
         // Rule C. The PICC block number shall be initialized to 1 at activation.
         self.block_num = true;
+        self.session_reset = true;
         info!("state reset.");
     }
 
+    /// Whether the card was reactivated or deselected since the last call,
+    /// so the apps should forget the selected app, as after a power cycle.
+    pub fn did_reset_session(&mut self) -> bool {
+        core::mem::replace(&mut self.session_reset, false)
+    }
+
     /// Read APDU into given buffer.  Return length of APDU on success.
     fn check_for_apdu(&mut self) -> Result<(), SourceError> {
         let mut packet = MaybeUninit::<[u8; 256]>::uninit();
@@ -342,9 +463,6 @@ where
             }
         };
 
-
-        assert!(packet_len > 0);
-
         // let packet = &self.packet;
         self.handle_block(&packet[.. packet_len as usize])?;
 
@@ -352,22 +470,34 @@ where
         debug!("{}", hex_str!(&self.buffer, sep:""));
         // logging::dump_hex(packet, l as usize);
 
+        if self.stale && !self.overflow {
+            // the app is still busy with a withdrawn command, waiting time extensions
+            // keep the PCD around until it is done
+            info!("command queued");
+            self.queued = true;
+            return Ok(());
+        }
+        self.request_command()
+    }
+
+    // Passes the command in the buffer on to the app.
+    fn request_command(&mut self) -> Result<(), SourceError> {
         let command = interchanges::Data::from_slice(&self.buffer);
         self.buffer.clear();
-        if command.is_ok() {
+        if command.is_ok() && !self.overflow {
             if self.interchange.request(
                 command.as_ref().unwrap()
             ).is_ok() {
                 Ok(())
             } else {
-                // Would be better to try canceling and taking on this apdu.
                 info!("Had to drop most recent Apdu!");
                 Err(SourceError::NoActivity)
             }
         } else {
             let (frame, _) = self.construct_iblock(
                 // UnspecifiedCheckingError
-                &[0x6F, 0x00]
+                &[0x6F, 0x00],
+                self.nad.take(),
             );
 
             self.send_frame( &frame )?;
@@ -376,10 +506,11 @@ where
     }
 
     pub fn is_ready_to_transmit(&self) -> bool {
-        self.interchange.state() == interchange::State::Responded
+        !self.stale && self.interchange.state() == interchange::State::Responded
     }
 
     pub fn poll(&mut self) -> Iso14443Status {
+        self.drop_stale_response();
         if interchange::State::Responded == self.interchange.state() {
 
             // important to wait on wtx reply from the reader.
@@ -403,23 +534,19 @@ where
 
             if let Some(msg) = self.interchange.take_response() {
                 let msg = msg.clone();
-                // if let Some(last_iblock_recv) = self.last_iblock_recv {
-                    info!("send!");
-                    let (frame, data_used) = self.construct_iblock(&msg);
-                    self.send_frame(
-                        &frame
-                    ).ok();
-                    if data_used != msg.len() {
-                        info!("chaining response!");
-                        self.buffer = msg;
-                        self.state = Iso14443State::Transmitting(
-                            0 .. data_used,
-                            data_used .. self.buffer.len()
-                        );
-                    }
-                // } else {
-                    // info!("session was dropped! dropping response.");
-                // }
+                info!("send!");
+                let nad = self.nad.take();
+                let (frame, data_used) = self.construct_iblock(&msg, nad);
+                self.send_frame(
+                    &frame
+                ).ok();
+                if data_used != msg.len() {
+                    info!("chaining response!");
+                    self.buffer = msg;
+                    self.state = Iso14443State::Transmitting(
+                        data_used .. self.buffer.len()
+                    );
+                }
             }
             Iso14443Status::Idle
         } else {
@@ -433,6 +560,7 @@ where
     }
 
     pub fn poll_wait_extensions(&mut self) -> Iso14443Status {
+        self.drop_stale_response();
 
         if self.wtx_requested {
             info!("warning: still awaiting wtx response.");
@@ -457,9 +585,11 @@ where
 
     }
 
-    /// Write response code + APDU
+    /// Write response code + APDU, and keep the block for retransmissions
     fn send_frame(&mut self, buffer: &Iso14443Frame) -> Result<(), SourceError>
     {
+        self.last_block = buffer.clone();
+
         let r = self.device.send( buffer );
         if !r.is_ok() {
             // o!("FM11 not okay!");
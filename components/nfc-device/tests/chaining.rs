@@ -0,0 +1,41 @@
+mod common;
+
+use common::*;
+use common::reader::*;
+use nfc_device::Iso14443;
+
+#[test]
+fn chaining() {
+    let (requester, mut responder) = claim();
+    // 13 bytes of INF per block
+    let reader = Reader::new(16, 16);
+    let mut iso14443 = Iso14443::new(reader.clone(), requester);
+    reader.activate(Activation::WithFirstFrame);
+
+    let command: Vec<u8> = (0..20).collect();
+    let response: Vec<u8> = (100..120).collect();
+    let app = |received: &[u8]| {
+        assert_eq!(received, &command[..]);
+        response.clone()
+    };
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &command, 0, app), response);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd([&[0x12], &command[..13]].concat()),
+        Frame::Picc(vec![0xA2]),
+        Frame::Pcd([&[0x03], &command[13..]].concat()),
+        Frame::Picc([&[0x13], &response[..13]].concat()),
+        Frame::Pcd(vec![0xA2]),
+        Frame::Picc([&[0x02], &response[13..]].concat()),
+    ]);
+
+    // long commands and responses, the reader checks each block
+    for length in [1, 12, 13, 14, 26, 27, 200, 1000] {
+        let command: Vec<u8> = (0..length).map(|i| i as u8).collect();
+        let response: Vec<u8> = command.iter().rev().cloned().collect();
+        let app = |received: &[u8]| {
+            assert_eq!(received, &command[..]);
+            response.clone()
+        };
+        assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &command, 0, app), response);
+    }
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use common::*;
+use common::reader::*;
+use nfc_device::Iso14443;
+
+#[test]
+fn cid_and_nad_are_echoed() {
+    let (requester, mut responder) = claim();
+    // 27 bytes of INF per block with CID and NAD
+    let reader = Reader::new(32, 32).with_cid(1).with_nad(0x12);
+    let mut iso14443 = Iso14443::new(reader.clone(), requester);
+    let app = |_: &[u8]| vec![0x90, 0x00];
+
+    reader.activate(Activation::WithFirstFrame);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &[0x00, 0xA4], 0, app), [0x90, 0x00]);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd(vec![0x0E, 0x01, 0x12, 0x00, 0xA4]),
+        Frame::Picc(vec![0x0E, 0x01, 0x12, 0x90, 0x00]),
+    ]);
+
+    // R-blocks: the response is retransmitted, the lost command acknowledged
+    reader.lose_picc_frame(0);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &[0x00, 0xB0], 0, app), [0x90, 0x00]);
+    reader.lose_pcd_frame(0);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &[0x00, 0xC0], 0, app), [0x90, 0x00]);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd(vec![0x0F, 0x01, 0x12, 0x00, 0xB0]),
+        Frame::LostPicc(vec![0x0F, 0x01, 0x12, 0x90, 0x00]),
+        Frame::Pcd(vec![0xBB, 0x01]),
+        Frame::Picc(vec![0x0F, 0x01, 0x12, 0x90, 0x00]),
+        Frame::LostPcd(vec![0x0E, 0x01, 0x12, 0x00, 0xC0]),
+        Frame::Pcd(vec![0xBA, 0x01]),
+        Frame::Picc(vec![0xAB, 0x01]),
+        Frame::Pcd(vec![0x0E, 0x01, 0x12, 0x00, 0xC0]),
+        Frame::Picc(vec![0x0E, 0x01, 0x12, 0x90, 0x00]),
+    ]);
+
+    // the NAD is only in the first block of a chain
+    let command: Vec<u8> = (0..30).collect();
+    let response: Vec<u8> = (0..40).collect();
+    let chained = |received: &[u8]| {
+        assert_eq!(received, &command[..]);
+        response.clone()
+    };
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &command, 0, chained), response);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd([&[0x1F, 0x01, 0x12], &command[..27]].concat()),
+        Frame::Picc(vec![0xAB, 0x01]),
+        Frame::Pcd([&[0x0A, 0x01], &command[27..]].concat()),
+        Frame::Picc([&[0x1E, 0x01, 0x12], &response[..27]].concat()),
+        Frame::Pcd(vec![0xAB, 0x01]),
+        Frame::Picc([&[0x0B, 0x01], &response[27..]].concat()),
+    ]);
+
+    // S-blocks
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &[0x00, 0xB0], 1, app), [0x90, 0x00]);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd(vec![0x0E, 0x01, 0x12, 0x00, 0xB0]),
+        Frame::Picc(vec![0xFA, 0x01, 0x01]),
+        Frame::Pcd(vec![0xFA, 0x01, 0x01]),
+        Frame::Picc(vec![0x0E, 0x01, 0x12, 0x90, 0x00]),
+    ]);
+
+    // the PCD stops using the CID and the NAD
+    reader.set_cid(None);
+    reader.set_nad(None);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &[0x00, 0xC0], 0, app), [0x90, 0x00]);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd(vec![0x03, 0x00, 0xC0]),
+        Frame::Picc(vec![0x03, 0x90, 0x00]),
+    ]);
+}
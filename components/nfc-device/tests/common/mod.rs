@@ -0,0 +1,20 @@
+//! The tests drive `Iso14443` with the simulated PCD of `reader`, frames are
+//! without CRC, as for the `nfc::Device` of the chip.
+
+#![allow(dead_code)]
+
+pub mod reader;
+
+use apdu_dispatch::interchanges::{Contactless, Data};
+use interchange::{Interchange, Requester, Responder};
+
+/// Claims the interchange, which is only possible once per test binary.
+pub fn claim() -> (Requester<Contactless>, Responder<Contactless>) {
+    Contactless::claim().unwrap()
+}
+
+/// The app takes the command, and answers it.
+pub fn respond(responder: &mut Responder<Contactless>, command: &[u8], response: &[u8]) {
+    assert_eq!(&responder.take_request().expect("no command")[..], command);
+    responder.respond(&Data::from_slice(response).unwrap()).ok().unwrap();
+}
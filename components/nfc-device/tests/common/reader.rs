@@ -0,0 +1,471 @@
+//! A simulated PCD (reader), following the rules for the PCD of the block
+//! transmission protocol in ISO 14443-4, 7.5.4: rules 1 - 8, A and B.
+//!
+//! It implements `nfc::Device`: it chains commands for the FSC, acknowledges
+//! chained responses and S(WTX) requests, and recovers from lost frames.  Every
+//! frame of the PICC is checked against the standard, the test panics on the
+//! first violation.  Activation (RATS) is done by the chip, the PICC only learns
+//! about new sessions.  Frames can be lost on purpose, in both directions.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use apdu_dispatch::interchanges::{Contactless, Data};
+use interchange::Responder;
+use nfc_device::{traits::nfc, Iso14443};
+
+/// The PCB of a block, as coded in ISO 14443-4, 7.1.1.1.
+///
+/// b8 and b7 tell the block type, b2 is always set and b4 announces a CID.
+/// Written from the standard rather than shared with the PICC, so the tests
+/// don't take its coding for granted.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Pcb {
+    I { block_num: bool, chaining: bool, nad: bool },
+    R { block_num: bool, nak: bool },
+    Deselect,
+    Wtx,
+}
+
+impl Pcb {
+    pub fn encode(self, cid: bool) -> u8 {
+        let bits = match self {
+            // 0 0 0 chaining cid nad 1 block_num
+            Pcb::I { block_num, chaining, nad } => ((chaining as u8) << 4) | ((nad as u8) << 2) | block_num as u8,
+            // 1 0 1 nak cid 0 1 block_num
+            Pcb::R { block_num, nak } => 0b1010_0000 | ((nak as u8) << 4) | block_num as u8,
+            // 1 1 0 0 cid 0 1 0
+            Pcb::Deselect => 0b1100_0000,
+            // 1 1 1 1 cid 0 1 0
+            Pcb::Wtx => 0b1111_0000,
+        };
+        bits | ((cid as u8) << 3) | 0b10
+    }
+
+    /// The PCB and whether a CID follows, `None` if the coding is RFU.
+    pub fn decode(pcb: u8) -> Option<(Self, bool)> {
+        // b1 - b8
+        let bit = |n: u8| (pcb >> (n - 1)) & 1 != 0;
+        if !bit(2) {
+            return None;
+        }
+        let decoded = match (bit(8), bit(7), bit(6), bit(5)) {
+            (false, false, false, chaining) => Pcb::I { block_num: bit(1), chaining, nad: bit(3) },
+            (true, false, true, nak) if !bit(3) => Pcb::R { block_num: bit(1), nak },
+            (true, true, false, false) if !bit(3) && !bit(1) => Pcb::Deselect,
+            (true, true, true, true) if !bit(3) && !bit(1) => Pcb::Wtx,
+            _ => return None,
+        };
+        Some((decoded, bit(4)))
+    }
+}
+
+/// A frame on the air, without CRC.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Frame {
+    Pcd(Vec<u8>),
+    Picc(Vec<u8>),
+    /// Sent by the PCD, never received by the PICC.
+    LostPcd(Vec<u8>),
+    /// Sent by the PICC, never received by the PCD.
+    LostPicc(Vec<u8>),
+}
+
+/// How the chip tells the PICC about the activation.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Activation {
+    /// `nfc::Error::NewSession`, before the first frame.
+    Separate,
+    /// `nfc::State::NewSession` with the first frame.
+    WithFirstFrame,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Phase {
+    Idle,
+    /// Chaining the command.
+    Sending,
+    Receiving,
+    Deselecting,
+}
+
+struct State {
+    fsd: usize,
+    fsc: usize,
+    cid: Option<u8>,
+    nad: Option<u8>,
+
+    field: bool,
+    activation: Option<Activation>,
+    // Rule A. The PCD block number shall be initialized to 0 for each activated PICC.
+    block_num: bool,
+    phase: Phase,
+
+    command: Vec<u8>,
+    sent: usize,
+    // Rule 6
+    last_i_block: Vec<u8>,
+    response: Vec<u8>,
+    complete: Option<Vec<u8>>,
+
+    // frames for the PICC
+    air: VecDeque<Vec<u8>>,
+    // Rule 1: the PICC only sends in response to a frame
+    picc_may_send: bool,
+    // frames are counted per direction, the listed ones are lost
+    pcd_frames: usize,
+    picc_frames: usize,
+    lost_pcd: Vec<usize>,
+    lost_picc: Vec<usize>,
+    transcript: Vec<Frame>,
+}
+
+/// Cheap handle, keep a clone when handing it to `Iso14443`.
+#[derive(Clone)]
+pub struct Reader {
+    state: Rc<RefCell<State>>,
+}
+
+impl Reader {
+    /// A reader with the given FSD and FSC (including the CRC), the field is off.
+    pub fn new(fsd: usize, fsc: usize) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(State {
+                fsd,
+                fsc,
+                cid: None,
+                nad: None,
+                field: false,
+                activation: None,
+                block_num: false,
+                phase: Phase::Idle,
+                command: Vec::new(),
+                sent: 0,
+                last_i_block: Vec::new(),
+                response: Vec::new(),
+                complete: None,
+                air: VecDeque::new(),
+                picc_may_send: false,
+                pcd_frames: 0,
+                picc_frames: 0,
+                lost_pcd: Vec::new(),
+                lost_picc: Vec::new(),
+                transcript: Vec::new(),
+            })),
+        }
+    }
+
+    /// Address the PICC with a CID.
+    pub fn with_cid(self, cid: u8) -> Self {
+        self.set_cid(Some(cid));
+        self
+    }
+
+    /// Send a NAD in the first block of each command.
+    pub fn with_nad(self, nad: u8) -> Self {
+        self.set_nad(Some(nad));
+        self
+    }
+
+    /// FSD and FSC of the next session, as exchanged in RATS and ATS.
+    pub fn set_frame_sizes(&self, fsd: usize, fsc: usize) {
+        let mut state = self.state.borrow_mut();
+        state.fsd = fsd;
+        state.fsc = fsc;
+    }
+
+    pub fn set_cid(&self, cid: Option<u8>) {
+        self.state.borrow_mut().cid = cid;
+    }
+
+    pub fn set_nad(&self, nad: Option<u8>) {
+        self.state.borrow_mut().nad = nad;
+    }
+
+    /// Switches the field on, and activates the PICC.
+    pub fn activate(&self, activation: Activation) {
+        let mut state = self.state.borrow_mut();
+        state.field = true;
+        state.activation = Some(activation);
+        state.block_num = false;
+        state.phase = Phase::Idle;
+    }
+
+    /// The PICC is out of the field: whatever is on the air is gone.
+    pub fn field_off(&self) {
+        let mut state = self.state.borrow_mut();
+        state.field = false;
+        state.activation = None;
+        state.phase = Phase::Idle;
+        state.air.clear();
+        state.picc_may_send = false;
+        state.response.clear();
+    }
+
+    /// Starts sending the command, chained for the FSC.
+    pub fn send_command(&self, command: &[u8]) {
+        let mut state = self.state.borrow_mut();
+        assert!(state.field, "field is off");
+        assert_eq!(state.phase, Phase::Idle, "PCD is busy");
+        state.command = command.to_vec();
+        state.sent = 0;
+        state.response.clear();
+        state.phase = Phase::Sending;
+        state.send_next_block();
+    }
+
+    pub fn deselect(&self) {
+        let mut state = self.state.borrow_mut();
+        state.phase = Phase::Deselecting;
+        let frame = state.prologue(Pcb::Deselect);
+        state.send(frame);
+    }
+
+    /// Sends a frame as is, e.g. an invalid one, which the PICC must not answer.
+    pub fn send_frame(&self, frame: &[u8]) {
+        self.state.borrow_mut().send(frame.to_vec());
+    }
+
+    pub fn is_deselected(&self) -> bool {
+        let state = self.state.borrow();
+        state.phase == Phase::Idle && state.air.is_empty() && !state.picc_may_send
+    }
+
+    /// The `nth` next frame of the PCD is lost, counting from 0.
+    pub fn lose_pcd_frame(&self, nth: usize) {
+        let mut state = self.state.borrow_mut();
+        let frame = state.pcd_frames + nth;
+        state.lost_pcd.push(frame);
+    }
+
+    /// The `nth` next frame of the PICC is lost, counting from 0.
+    pub fn lose_picc_frame(&self, nth: usize) {
+        let mut state = self.state.borrow_mut();
+        let frame = state.picc_frames + nth;
+        state.lost_picc.push(frame);
+    }
+
+    /// The response to the last command, once complete.
+    pub fn take_response(&self) -> Option<Vec<u8>> {
+        self.state.borrow_mut().complete.take()
+    }
+
+    /// The frames on the air since the last call.
+    pub fn take_transcript(&self) -> Vec<Frame> {
+        core::mem::take(&mut self.state.borrow_mut().transcript)
+    }
+}
+
+impl State {
+    fn prologue(&self, pcb: Pcb) -> Vec<u8> {
+        match self.cid {
+            Some(cid) => vec![pcb.encode(true), cid],
+            None => vec![pcb.encode(false)],
+        }
+    }
+
+    fn send(&mut self, frame: Vec<u8>) {
+        self.pcd_frames += 1;
+        if self.lost_pcd.contains(&(self.pcd_frames - 1)) {
+            self.transcript.push(Frame::LostPcd(frame));
+            self.timeout();
+        } else {
+            self.transcript.push(Frame::Pcd(frame.clone()));
+            self.air.push_back(frame);
+        }
+    }
+
+    fn send_next_block(&mut self) {
+        // the NAD is only in the first block
+        let nad = if self.sent == 0 { self.nad } else { None };
+        let header = 1 + self.cid.is_some() as usize + nad.is_some() as usize;
+        let size = (self.fsc - 2 - header).min(self.command.len() - self.sent);
+        let chaining = self.sent + size < self.command.len();
+        let mut frame = self.prologue(Pcb::I { block_num: self.block_num, chaining, nad: nad.is_some() });
+        frame.extend(nad);
+        frame.extend_from_slice(&self.command[self.sent..][..size]);
+        self.sent += size;
+        if !chaining {
+            self.phase = Phase::Receiving;
+        }
+        self.last_i_block = frame.clone();
+        self.send(frame);
+    }
+
+    // No valid block within FWT.
+    fn timeout(&mut self) {
+        let pcb = match self.phase {
+            // Rule 8. If the S(DESELECT) request is not answered by an error-free
+            // S(DESELECT) response the S(DESELECT) request may be re-transmitted.
+            Phase::Deselecting => Pcb::Deselect,
+            // Rule 5. In the case of PICC chaining, when an invalid block is received
+            // or a FWT time-out occurs, an R(ACK) block shall be sent.
+            Phase::Receiving if !self.response.is_empty() => Pcb::R { block_num: self.block_num, nak: false },
+            // Rule 4. When an invalid block is received or a FWT time-out occurs,
+            // an R(NAK) block shall be sent.
+            _ => Pcb::R { block_num: self.block_num, nak: true },
+        };
+        let frame = self.prologue(pcb);
+        self.send(frame);
+    }
+
+    fn receive(&mut self, frame: &[u8]) {
+        assert!(frame.len() + 2 <= self.fsd, "frame of {} bytes exceeds FSD {}", frame.len(), self.fsd);
+        let (pcb, has_cid) = Pcb::decode(frame[0]).unwrap_or_else(|| panic!("invalid PCB {:02X}", frame[0]));
+
+        // CID
+        let mut offset = 1;
+        match self.cid {
+            Some(cid) => {
+                assert!(has_cid, "CID missing");
+                assert_eq!(frame[1], cid, "wrong CID");
+                offset += 1;
+            }
+            None => assert!(!has_cid, "unexpected CID"),
+        }
+
+        match pcb {
+            Pcb::I { block_num, chaining, nad } => {
+                assert_eq!(self.phase, Phase::Receiving, "I-block while not receiving");
+                // Rule B. When an I-block or an R(ACK) block with a block number equal to the
+                // current block number is received, the PCD shall toggle the current block number.
+                assert_eq!(block_num, self.block_num, "I-block with wrong block number");
+                self.block_num = !self.block_num;
+
+                // NAD only in the first block of the response
+                match (self.nad, self.response.is_empty()) {
+                    (Some(expected), true) => {
+                        assert!(nad, "NAD missing");
+                        assert_eq!(frame[offset], expected, "wrong NAD");
+                        offset += 1;
+                    }
+                    _ => assert!(!nad, "unexpected NAD"),
+                }
+                // the response may be empty, keep track of the PICC chaining
+                self.response.extend_from_slice(&frame[offset..]);
+
+                if chaining {
+                    assert!(frame.len() > offset, "empty chained block");
+                    let ack = self.prologue(Pcb::R { block_num: self.block_num, nak: false });
+                    self.send(ack);
+                } else {
+                    self.complete = Some(core::mem::take(&mut self.response));
+                    self.phase = Phase::Idle;
+                }
+            }
+            Pcb::R { block_num, nak } => {
+                assert!(!nak, "R(NAK) from the PICC");
+                assert_eq!(frame.len(), offset, "R-block with INF");
+                if block_num == self.block_num {
+                    // Rule 7. When an R(ACK) block is received, if its block number is equal
+                    // to the PCD’s current block number, chaining shall be continued.
+                    assert_eq!(self.phase, Phase::Sending, "R(ACK) while not chaining");
+                    self.block_num = !self.block_num;
+                    self.send_next_block();
+                } else {
+                    // Rule 6. When an R(ACK) block is received, if its block number is not equal
+                    // to the PCD’s current block number, the last I-block shall be re-transmitted.
+                    assert!(matches!(self.phase, Phase::Sending | Phase::Receiving), "R(ACK) out of a command");
+                    let frame = self.last_i_block.clone();
+                    self.send(frame);
+                }
+            }
+            Pcb::Wtx => {
+                assert!(matches!(self.phase, Phase::Receiving), "S(WTX) out of a command");
+                assert_eq!(frame.len(), offset + 1, "S(WTX) without WTXM");
+                let wtxm = frame[offset];
+                assert!((1..=59).contains(&wtxm), "invalid WTXM {}", wtxm);
+                // Rule 3. An S(…) request block shall always be followed by an S(…) response block.
+                let mut response = self.prologue(Pcb::Wtx);
+                response.push(wtxm);
+                self.send(response);
+            }
+            Pcb::Deselect => {
+                assert_eq!(self.phase, Phase::Deselecting, "S(DESELECT) without request");
+                assert_eq!(frame.len(), offset, "S(DESELECT) with INF");
+                self.phase = Phase::Idle;
+            }
+        }
+    }
+}
+
+impl nfc::Device for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<nfc::State, nfc::Error> {
+        let mut state = self.state.borrow_mut();
+        if state.activation == Some(Activation::Separate) {
+            state.activation = None;
+            return Err(nfc::Error::NewSession);
+        }
+        let frame = match state.air.pop_front() {
+            Some(frame) => frame,
+            None => return Err(nfc::Error::NoActivity),
+        };
+        state.picc_may_send = true;
+        buf[..frame.len()].copy_from_slice(&frame);
+        match state.activation.take() {
+            Some(_) => Ok(nfc::State::NewSession(frame.len() as u8)),
+            None => Ok(nfc::State::Continue(frame.len() as u8)),
+        }
+    }
+
+    fn send(&mut self, buf: &[u8]) -> Result<(), nfc::Error> {
+        let mut state = self.state.borrow_mut();
+        if !state.field {
+            return Err(nfc::Error::NoActivity);
+        }
+        // Rule 1. The first block shall be sent by the PCD.
+        assert!(state.picc_may_send, "unsolicited frame {:02X?}", buf);
+        state.picc_may_send = false;
+
+        state.picc_frames += 1;
+        if state.lost_picc.contains(&(state.picc_frames - 1)) {
+            state.transcript.push(Frame::LostPicc(buf.to_vec()));
+            state.timeout();
+        } else {
+            state.transcript.push(Frame::Picc(buf.to_vec()));
+            state.receive(buf);
+        }
+        Ok(())
+    }
+
+    fn frame_size(&self) -> usize {
+        self.state.borrow().fsd
+    }
+}
+
+/// Polls the PICC until the reader has the response to the command.
+///
+/// The app answers with `app`, after `extensions` waiting time extensions.
+pub fn transceive(
+    reader: &Reader,
+    iso14443: &mut Iso14443<Reader>,
+    responder: &mut Responder<Contactless>,
+    command: &[u8],
+    extensions: usize,
+    app: impl Fn(&[u8]) -> Vec<u8>,
+) -> Vec<u8> {
+    reader.send_command(command);
+    let mut pending = None;
+    let mut extensions = extensions;
+    for _ in 0..1000 {
+        iso14443.poll();
+        if let Some(response) = reader.take_response() {
+            return response;
+        }
+        if let Some(request) = responder.take_request() {
+            assert!(pending.is_none(), "two commands at once");
+            pending = Some(request);
+        }
+        if let Some(request) = pending.as_ref() {
+            if extensions > 0 {
+                iso14443.poll_wait_extensions();
+                extensions -= 1;
+            } else {
+                responder.respond(&Data::from_slice(&app(request)).unwrap()).ok().unwrap();
+                pending = None;
+            }
+        }
+    }
+    panic!("no response");
+}
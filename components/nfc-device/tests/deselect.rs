@@ -0,0 +1,69 @@
+mod common;
+
+use apdu_dispatch::interchanges::Data;
+use common::*;
+use common::reader::*;
+use nfc_device::{Iso14443, Iso14443Status};
+
+#[test]
+fn deselect() {
+    let (requester, mut responder) = claim();
+    let reader = Reader::new(64, 64);
+    let mut iso14443 = Iso14443::new(reader.clone(), requester);
+    let app = |_: &[u8]| vec![0x90, 0x00];
+
+    // Rule C: the PICC starts with block number 1, toggled by the I-block
+    reader.activate(Activation::WithFirstFrame);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &[0x00, 0xA4], 0, app), [0x90, 0x00]);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd(vec![0x02, 0x00, 0xA4]),
+        Frame::Picc(vec![0x02, 0x90, 0x00]),
+    ]);
+
+    // the app is still busy with the next command when the PCD deselects
+    reader.send_command(&[0x00, 0xB0]);
+    assert!(matches!(iso14443.poll(), Iso14443Status::ReceivedData(_)));
+    assert_eq!(&responder.take_request().unwrap()[..], &[0x00, 0xB0]);
+    reader.deselect();
+    iso14443.poll();
+    assert!(reader.is_deselected());
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd(vec![0x03, 0x00, 0xB0]),
+        Frame::Pcd(vec![0xC2]),
+        Frame::Picc(vec![0xC2]),
+    ]);
+
+    // the session is torn down: the app finishes the command, its response is dropped
+    assert_eq!(responder.state(), interchange::State::BuildingResponse);
+    responder.respond(&Data::from_slice(&[0x90, 0x00]).unwrap()).ok().unwrap();
+    assert!(matches!(iso14443.poll_wait_extensions(), Iso14443Status::Idle));
+    assert!(reader.take_transcript().is_empty());
+
+    // and the block number starts over in the next session
+    reader.activate(Activation::WithFirstFrame);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &[0x00, 0xC0], 0, |_| vec![0x6A, 0x82]), [0x6A, 0x82]);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd(vec![0x02, 0x00, 0xC0]),
+        Frame::Picc(vec![0x02, 0x6A, 0x82]),
+    ]);
+
+    // Rule 8: the response of S(DESELECT) echoes the CID, and is lost,
+    // the repeated S(DESELECT) is answered again
+    reader.set_cid(Some(1));
+    reader.lose_picc_frame(0);
+    reader.deselect();
+    iso14443.poll();
+    iso14443.poll();
+    assert!(reader.is_deselected());
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd(vec![0xCA, 0x01]),
+        Frame::LostPicc(vec![0xCA, 0x01]),
+        Frame::Pcd(vec![0xCA, 0x01]),
+        Frame::Picc(vec![0xCA, 0x01]),
+    ]);
+
+    // nothing to retransmit after deselection
+    reader.send_frame(&[0xB3]);
+    assert!(matches!(iso14443.poll(), Iso14443Status::Idle));
+    assert_eq!(reader.take_transcript(), [Frame::Pcd(vec![0xB3])]);
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use apdu_dispatch::interchanges::Data;
+use common::*;
+use common::reader::*;
+use nfc_device::Iso14443;
+
+// FSDI / FSCI 0 - 8
+const FRAME_SIZES: [usize; 9] = [16, 24, 32, 40, 48, 64, 96, 128, 256];
+
+fn is_chained_iblock(frame: &[u8]) -> bool {
+    matches!(Pcb::decode(frame[0]), Some((Pcb::I { chaining: true, .. }, _)))
+}
+
+#[test]
+fn frame_sizes() {
+    let (requester, mut responder) = claim();
+    let reader = Reader::new(256, 256);
+    let mut iso14443 = Iso14443::new(reader.clone(), requester);
+
+    let command: Vec<u8> = (0..600).map(|i| i as u8).collect();
+    let response: Vec<u8> = command.iter().rev().cloned().collect();
+    let app = |received: &[u8]| {
+        assert_eq!(received, &command[..]);
+        response.clone()
+    };
+
+    for (cid, nad) in [(None, None), (Some(1), None), (Some(2), Some(0x12))] {
+        reader.set_cid(cid);
+        reader.set_nad(nad);
+        for fsd in FRAME_SIZES {
+            for fsc in FRAME_SIZES {
+                reader.set_frame_sizes(fsd, fsc);
+                reader.activate(Activation::WithFirstFrame);
+                assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &command, 0, app), response);
+
+                // the reader checks the FSD, chained blocks also make full use of it
+                for frame in reader.take_transcript() {
+                    if let Frame::Picc(frame) = frame {
+                        if is_chained_iblock(&frame) {
+                            assert_eq!(frame.len() + 2, fsd);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // a larger FSD is capped to our frames
+    reader.set_frame_sizes(1024, 256);
+    reader.activate(Activation::Separate);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &command, 0, app), response);
+    for frame in reader.take_transcript() {
+        if let Frame::Picc(frame) = frame {
+            assert!(frame.len() <= 254);
+            if is_chained_iblock(&frame) {
+                assert_eq!(frame.len(), 254);
+            }
+        }
+    }
+
+    // a chained command longer than the interchange is refused, the app never sees it
+    let too_long = vec![0x55; Data::new().capacity() + 1];
+    let refuse = |_: &[u8]| -> Vec<u8> { panic!("command too long") };
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &too_long, 0, refuse), [0x6F, 0x00]);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &command, 0, app), response);
+}
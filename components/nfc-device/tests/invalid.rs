@@ -0,0 +1,60 @@
+mod common;
+
+use common::*;
+use common::reader::*;
+use nfc_device::{Iso14443, Iso14443Status};
+
+#[test]
+fn invalid_blocks_are_ignored() {
+    let (requester, mut responder) = claim();
+    let reader = Reader::new(256, 256);
+    let mut iso14443 = Iso14443::new(reader.clone(), requester);
+    let app = |_: &[u8]| vec![0x90, 0x00];
+
+    reader.activate(Activation::WithFirstFrame);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &[0x00, 0xA4], 0, app), [0x90, 0x00]);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd(vec![0x02, 0x00, 0xA4]),
+        Frame::Picc(vec![0x02, 0x90, 0x00]),
+    ]);
+
+    let invalid: &[&[u8]] = &[
+        // empty
+        &[],
+        // CID announced, but missing
+        &[0x0A],
+        &[0xAA],
+        &[0xCA],
+        // NAD announced, but missing
+        &[0x06],
+        &[0x0E, 0x01],
+        // R-blocks and S(DESELECT) have no INF field
+        &[0xA2, 0x00],
+        &[0xC2, 0x00],
+        // S(WTX) needs a WTXM in 1..=59
+        &[0xF2],
+        &[0xF2, 0x00],
+        &[0xF2, 0x3C],
+        &[0xF2, 0x01, 0x00],
+        // RFU bits of the PCB
+        &[0x22, 0x00],
+        &[0x82],
+        &[0xA6],
+        &[0xC3],
+        &[0xE2],
+    ];
+    for frame in invalid {
+        reader.send_frame(frame);
+        assert!(matches!(iso14443.poll(), Iso14443Status::Idle));
+        assert_eq!(reader.take_transcript(), [Frame::Pcd(frame.to_vec())], "answered {:02X?}", frame);
+    }
+    assert!(responder.take_request().is_none());
+
+    // the block number is unchanged, and the longest frame the FSC allows goes through
+    let command = vec![0x55; 253];
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &command, 0, app), [0x90, 0x00]);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd([&[0x03], &command[..]].concat()),
+        Frame::Picc(vec![0x03, 0x90, 0x00]),
+    ]);
+}
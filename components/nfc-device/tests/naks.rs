@@ -0,0 +1,86 @@
+mod common;
+
+use common::*;
+use common::reader::*;
+use nfc_device::Iso14443;
+
+#[test]
+fn lost_frames() {
+    let (requester, mut responder) = claim();
+    // 13 bytes of INF per block
+    let reader = Reader::new(16, 16);
+    let mut iso14443 = Iso14443::new(reader.clone(), requester);
+    reader.activate(Activation::WithFirstFrame);
+    let app = |_: &[u8]| vec![0x90, 0x00];
+
+    // Rule 4: the response is lost, the PCD sends R(NAK)
+    reader.lose_picc_frame(0);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &[0x00, 0xA4], 0, app), [0x90, 0x00]);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd(vec![0x02, 0x00, 0xA4]),
+        Frame::LostPicc(vec![0x02, 0x90, 0x00]),
+        Frame::Pcd(vec![0xB2]),
+        Frame::Picc(vec![0x02, 0x90, 0x00]),
+    ]);
+
+    // the command is lost, the PICC answers R(NAK) with R(ACK), and the PCD retransmits
+    reader.lose_pcd_frame(0);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &[0x00, 0xB0], 0, app), [0x90, 0x00]);
+    assert_eq!(reader.take_transcript(), [
+        Frame::LostPcd(vec![0x03, 0x00, 0xB0]),
+        Frame::Pcd(vec![0xB3]),
+        Frame::Picc(vec![0xA2]),
+        Frame::Pcd(vec![0x03, 0x00, 0xB0]),
+        Frame::Picc(vec![0x03, 0x90, 0x00]),
+    ]);
+
+    // the R(ACK) of a chained command is lost
+    let command: Vec<u8> = (0..20).collect();
+    reader.lose_picc_frame(0);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &command, 0, app), [0x90, 0x00]);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd([&[0x12], &command[..13]].concat()),
+        Frame::LostPicc(vec![0xA2]),
+        Frame::Pcd(vec![0xB2]),
+        Frame::Picc(vec![0xA2]),
+        Frame::Pcd([&[0x03], &command[13..]].concat()),
+        Frame::Picc(vec![0x03, 0x90, 0x00]),
+    ]);
+
+    // the first block of a chained response is lost
+    let response: Vec<u8> = (0..20).collect();
+    let chained = |_: &[u8]| response.clone();
+    reader.lose_picc_frame(0);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &[0x00, 0xC0], 0, chained), response);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd(vec![0x02, 0x00, 0xC0]),
+        Frame::LostPicc([&[0x12], &response[..13]].concat()),
+        Frame::Pcd(vec![0xB2]),
+        Frame::Picc([&[0x12], &response[..13]].concat()),
+        Frame::Pcd(vec![0xA3]),
+        Frame::Picc([&[0x03], &response[13..]].concat()),
+    ]);
+
+    // Rule 5: a later block of a chained response is lost, the PCD sends R(ACK)
+    reader.lose_picc_frame(1);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &[0x00, 0xC0], 0, chained), response);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd(vec![0x02, 0x00, 0xC0]),
+        Frame::Picc([&[0x12], &response[..13]].concat()),
+        Frame::Pcd(vec![0xA3]),
+        Frame::LostPicc([&[0x03], &response[13..]].concat()),
+        Frame::Pcd(vec![0xA3]),
+        Frame::Picc([&[0x03], &response[13..]].concat()),
+    ]);
+
+    // the R(ACK) of the PCD is lost
+    reader.lose_pcd_frame(1);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &[0x00, 0xC0], 0, chained), response);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd(vec![0x02, 0x00, 0xC0]),
+        Frame::Picc([&[0x12], &response[..13]].concat()),
+        Frame::LostPcd(vec![0xA3]),
+        Frame::Pcd(vec![0xA3]),
+        Frame::Picc([&[0x03], &response[13..]].concat()),
+    ]);
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use common::*;
+use common::reader::*;
+use nfc_device::{Iso14443, Iso14443Status};
+
+#[test]
+fn retransmission() {
+    let (requester, mut responder) = claim();
+    // 13 bytes of INF per block
+    let reader = Reader::new(16, 16);
+    let mut iso14443 = Iso14443::new(reader.clone(), requester);
+    reader.activate(Activation::WithFirstFrame);
+    let app = |_: &[u8]| vec![0x90, 0x00];
+
+    // Rule 11: the last block is retransmitted on each R(NAK) with our block number
+    reader.lose_picc_frame(0);
+    reader.lose_picc_frame(1);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &[0x00, 0xA4], 0, app), [0x90, 0x00]);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd(vec![0x02, 0x00, 0xA4]),
+        Frame::LostPicc(vec![0x02, 0x90, 0x00]),
+        Frame::Pcd(vec![0xB2]),
+        Frame::LostPicc(vec![0x02, 0x90, 0x00]),
+        Frame::Pcd(vec![0xB2]),
+        Frame::Picc(vec![0x02, 0x90, 0x00]),
+    ]);
+
+    // the R(ACK) of a chained command is retransmitted too
+    let command: Vec<u8> = (0..20).collect();
+    reader.lose_picc_frame(0);
+    reader.lose_picc_frame(1);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &command, 0, app), [0x90, 0x00]);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd([&[0x13], &command[..13]].concat()),
+        Frame::LostPicc(vec![0xA3]),
+        Frame::Pcd(vec![0xB3]),
+        Frame::LostPicc(vec![0xA3]),
+        Frame::Pcd(vec![0xB3]),
+        Frame::Picc(vec![0xA3]),
+        Frame::Pcd([&[0x02], &command[13..]].concat()),
+        Frame::Picc(vec![0x02, 0x90, 0x00]),
+    ]);
+
+    // and the blocks of a chained response, on R(NAK) and on R(ACK) (Rule 5)
+    let response: Vec<u8> = (0..20).collect();
+    let chained = |_: &[u8]| response.clone();
+    reader.lose_picc_frame(0);
+    reader.lose_picc_frame(2);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &[0x00, 0xDA], 0, chained), response);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd(vec![0x03, 0x00, 0xDA]),
+        Frame::LostPicc([&[0x13], &response[..13]].concat()),
+        Frame::Pcd(vec![0xB3]),
+        Frame::Picc([&[0x13], &response[..13]].concat()),
+        Frame::Pcd(vec![0xA2]),
+        Frame::LostPicc([&[0x02], &response[13..]].concat()),
+        Frame::Pcd(vec![0xA2]),
+        Frame::Picc([&[0x02], &response[13..]].concat()),
+    ]);
+
+    // the S(WTX) response of the PCD is lost, the PICC repeats its request
+    reader.lose_pcd_frame(1);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &[0x00, 0xB0], 1, app), [0x90, 0x00]);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd(vec![0x03, 0x00, 0xB0]),
+        Frame::Picc(vec![0xF2, 0x01]),
+        Frame::LostPcd(vec![0xF2, 0x01]),
+        Frame::Pcd(vec![0xB3]),
+        Frame::Picc(vec![0xF2, 0x01]),
+        Frame::Pcd(vec![0xF2, 0x01]),
+        Frame::Picc(vec![0x03, 0x90, 0x00]),
+    ]);
+
+    // Rule 3: S(WTX) responses without request are ignored
+    reader.send_frame(&[0xF2, 0x01]);
+    assert!(matches!(iso14443.poll(), Iso14443Status::Idle));
+    assert_eq!(reader.take_transcript(), [Frame::Pcd(vec![0xF2, 0x01])]);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &[0x00, 0xC0], 0, app), [0x90, 0x00]);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd(vec![0x02, 0x00, 0xC0]),
+        Frame::Picc(vec![0x02, 0x90, 0x00]),
+    ]);
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use apdu_dispatch::interchanges::Data;
+use common::*;
+use common::reader::*;
+use nfc_device::Iso14443;
+
+fn app(command: &[u8]) -> Vec<u8> {
+    [command, &[0x90, 0x00]].concat()
+}
+
+#[test]
+fn sessions() {
+    let (requester, mut responder) = claim();
+    let reader = Reader::new(256, 256);
+    let mut iso14443 = Iso14443::new(reader.clone(), requester);
+
+    // the chip reports the activation with the first frame
+    reader.activate(Activation::WithFirstFrame);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &[0x00, 0xA4], 0, app), [0x00, 0xA4, 0x90, 0x00]);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &[0x00, 0xB0], 0, app), [0x00, 0xB0, 0x90, 0x00]);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd(vec![0x02, 0x00, 0xA4]),
+        Frame::Picc(vec![0x02, 0x00, 0xA4, 0x90, 0x00]),
+        Frame::Pcd(vec![0x03, 0x00, 0xB0]),
+        Frame::Picc(vec![0x03, 0x00, 0xB0, 0x90, 0x00]),
+    ]);
+    // the runner deselects the app of the previous session
+    assert!(iso14443.did_reset_session());
+    assert!(!iso14443.did_reset_session());
+
+    reader.deselect();
+    iso14443.poll();
+    assert!(reader.is_deselected());
+    assert_eq!(reader.take_transcript(), [Frame::Pcd(vec![0xC2]), Frame::Picc(vec![0xC2])]);
+    assert!(iso14443.did_reset_session());
+
+    // the chip reports the activation before the first frame, the block numbers start over
+    reader.activate(Activation::Separate);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &[0x00, 0xA4], 0, app), [0x00, 0xA4, 0x90, 0x00]);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd(vec![0x02, 0x00, 0xA4]),
+        Frame::Picc(vec![0x02, 0x00, 0xA4, 0x90, 0x00]),
+    ]);
+
+    // the field is lost while the app works on the command
+    reader.send_command(&[0x00, 0xB0]);
+    iso14443.poll();
+    assert_eq!(&responder.take_request().unwrap()[..], &[0x00, 0xB0]);
+    reader.field_off();
+    iso14443.poll();
+    reader.activate(Activation::Separate);
+    iso14443.poll();
+    assert!(iso14443.did_reset_session());
+    // the next command waits for the app to finish the withdrawn one, whose response is dropped
+    reader.send_command(&[0x00, 0xC0]);
+    iso14443.poll();
+    assert!(responder.take_request().is_none());
+    responder.respond(&Data::from_slice(&[0x90, 0x00]).unwrap()).ok().unwrap();
+    iso14443.poll();
+    respond(&mut responder, &[0x00, 0xC0], &[0x00, 0xC0, 0x90, 0x00]);
+    iso14443.poll();
+    assert_eq!(reader.take_response(), Some(vec![0x00, 0xC0, 0x90, 0x00]));
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd(vec![0x03, 0x00, 0xB0]),
+        Frame::Pcd(vec![0x02, 0x00, 0xC0]),
+        Frame::Picc(vec![0x02, 0x00, 0xC0, 0x90, 0x00]),
+    ]);
+
+    // the field is lost before the response is sent, it is never sent in the next session
+    reader.send_command(&[0x00, 0xB2]);
+    iso14443.poll();
+    respond(&mut responder, &[0x00, 0xB2], &[0x90, 0x00]);
+    reader.field_off();
+    iso14443.poll();
+    reader.activate(Activation::WithFirstFrame);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &[0x00, 0xB4], 0, app), [0x00, 0xB4, 0x90, 0x00]);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd(vec![0x03, 0x00, 0xB2]),
+        Frame::Pcd(vec![0x02, 0x00, 0xB4]),
+        Frame::Picc(vec![0x02, 0x00, 0xB4, 0x90, 0x00]),
+    ]);
+}
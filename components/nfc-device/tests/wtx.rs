@@ -0,0 +1,41 @@
+mod common;
+
+use common::*;
+use common::reader::*;
+use nfc_device::Iso14443;
+
+fn app(_command: &[u8]) -> Vec<u8> {
+    vec![0x90, 0x00]
+}
+
+#[test]
+fn wtx() {
+    let (requester, mut responder) = claim();
+    let reader = Reader::new(64, 64);
+    let mut iso14443 = Iso14443::new(reader.clone(), requester);
+    reader.activate(Activation::WithFirstFrame);
+
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &[0x00, 0xB0], 3, app), [0x90, 0x00]);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd(vec![0x02, 0x00, 0xB0]),
+        Frame::Picc(vec![0xF2, 0x01]),
+        Frame::Pcd(vec![0xF2, 0x01]),
+        Frame::Picc(vec![0xF2, 0x01]),
+        Frame::Pcd(vec![0xF2, 0x01]),
+        Frame::Picc(vec![0xF2, 0x01]),
+        Frame::Pcd(vec![0xF2, 0x01]),
+        Frame::Picc(vec![0x02, 0x90, 0x00]),
+    ]);
+
+    // the S(WTX) request is lost
+    reader.lose_picc_frame(0);
+    assert_eq!(transceive(&reader, &mut iso14443, &mut responder, &[0x00, 0xB0], 1, app), [0x90, 0x00]);
+    assert_eq!(reader.take_transcript(), [
+        Frame::Pcd(vec![0x03, 0x00, 0xB0]),
+        Frame::LostPicc(vec![0xF2, 0x01]),
+        Frame::Pcd(vec![0xB3]),
+        Frame::Picc(vec![0xF2, 0x01]),
+        Frame::Pcd(vec![0xF2, 0x01]),
+        Frame::Picc(vec![0x03, 0x90, 0x00]),
+    ]);
+}
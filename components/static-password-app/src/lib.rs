@@ -0,0 +1,133 @@
+//! # Static password app
+//!
+//! Types a password stored on the device with the USB keyboard, when the user
+//! touches the device outside of a user presence check.
+//!
+//! The password is set with the PUT_PASSWORD instruction, P1 being the keyboard
+//! layout of the host (0: US, 1: DE) and the data the password.  Without data,
+//! the password is removed.
+#![no_std]
+
+#[macro_use]
+extern crate delog;
+generate_macros!();
+
+use apdu_dispatch::{app, Command, response, command::SIZE as CommandSize, response::SIZE as ResponseSize};
+use interchange::Requester;
+use iso7816::{Instruction, Status};
+use littlefs2::path::PathBuf;
+use trussed::{
+    syscall, try_syscall,
+    types::{Location, Message},
+    Client as TrussedClient,
+};
+use usbd_keyboard::{
+    constants::TEXT_SIZE,
+    keymap::{self, Layout},
+    types::{KeyboardInterchange, Request, Text},
+};
+
+// next to the provisioner app, in the SoloKeys RID
+const AID: [u8; 9] = [0xA0, 0x00, 0x00, 0x08, 0x47, 0x07, 0x00, 0x00, 0x01];
+
+const PUT_PASSWORD: u8 = 0x01;
+
+// the layout byte, followed by the password
+const FILENAME: &[u8] = b"password";
+
+pub struct App<T> {
+    trussed: T,
+    keyboard: Option<Requester<KeyboardInterchange>>,
+}
+
+impl<T: TrussedClient> App<T> {
+    /// Without a keyboard, the password can be set, but is not typed.
+    pub fn new(trussed: T, keyboard: Option<Requester<KeyboardInterchange>>) -> Self {
+        Self { trussed, keyboard }
+    }
+
+    /// Types the password, if there is one and no password is being typed.
+    pub fn touched(&mut self) {
+        let keyboard = match self.keyboard.as_mut() {
+            Some(keyboard) => keyboard,
+            None => return,
+        };
+        // the last password was typed, or not
+        if let Some(Err(_error)) = keyboard.take_response() {
+            info!("typing the password failed: {:?}", _error);
+        }
+        if keyboard.state() != interchange::State::Idle {
+            info!("still typing the password");
+            return;
+        }
+
+        let file = match try_syscall!(self.trussed.read_file(Location::Internal, PathBuf::from(FILENAME))) {
+            Ok(file) => file.data,
+            Err(_) => {
+                info!("no password to type");
+                return;
+            }
+        };
+        match parse(&file) {
+            Some(request) => {
+                keyboard.request(&request).ok();
+            }
+            None => info!("stored password is invalid"),
+        }
+    }
+
+    fn put_password(&mut self, layout: u8, password: &[u8]) -> app::Result {
+        if password.is_empty() {
+            try_syscall!(self.trussed.remove_file(Location::Internal, PathBuf::from(FILENAME))).ok();
+            return Ok(());
+        }
+
+        let mut file = Message::new();
+        file.push(layout).map_err(|_| Status::WrongLength)?;
+        file.extend_from_slice(password).map_err(|_| Status::WrongLength)?;
+        // checks the layout, and that all characters can be typed with it
+        if parse(&file).is_none() {
+            return Err(Status::IncorrectDataParameter);
+        }
+        syscall!(self.trussed.write_file(Location::Internal, PathBuf::from(FILENAME), file, None));
+        Ok(())
+    }
+}
+
+// The typing request for a stored password.
+fn parse(file: &[u8]) -> Option<Request> {
+    let (&layout, password) = file.split_first()?;
+    let layout = match layout {
+        0 => Layout::Us,
+        1 => Layout::De,
+        _ => return None,
+    };
+    let password = core::str::from_utf8(password).ok()?;
+    if password.len() > TEXT_SIZE || !password.chars().all(|c| keymap::keys(layout, c).is_some()) {
+        return None;
+    }
+    let mut text = Text::new();
+    text.push_str(password).ok()?;
+    Some(Request { layout, text })
+}
+
+impl<T: TrussedClient> iso7816::App for App<T> {
+    fn aid(&self) -> iso7816::Aid {
+        iso7816::Aid::new(&AID)
+    }
+}
+
+impl<T: TrussedClient> app::App<CommandSize, ResponseSize> for App<T> {
+    fn select(&mut self, _apdu: &Command, _reply: &mut response::Data) -> app::Result {
+        Ok(())
+    }
+
+    fn deselect(&mut self) {}
+
+    fn call(&mut self, _interface: app::Interface, apdu: &Command, _reply: &mut response::Data) -> app::Result {
+        match apdu.instruction() {
+            Instruction::Unknown(PUT_PASSWORD) => self.put_password(apdu.p1, apdu.data()),
+            _ => Err(Status::InstructionNotSupportedOrInvalid),
+        }
+    }
+}
@@ -0,0 +1,327 @@
+//! In-memory `UsbBus`, to test the USB classes on the host.
+//!
+//! The test plays the host: it queues packets for the OUT endpoints of a
+//! class, and takes the packets the class wrote to its IN endpoints.
+//! `MockBus` is a cheap handle, keep a clone when handing it to the
+//! `UsbBusAllocator`.
+//!
+//! Like real hardware, an IN endpoint buffers a single packet: further writes
+//! block until the host took it.  The classes can be driven either directly
+//! (`UsbClass::endpoint_out` etc.), or via `UsbDevice::poll`, which is needed
+//! for control transfers (see `MockBus::setup`, `MockBus::control_out` and
+//! `MockBus::control_in`).
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use usb_device::{
+    bus::{PollResult, UsbBus},
+    class::UsbClass,
+    device::UsbDevice,
+    endpoint::{EndpointAddress, EndpointType},
+    Result, UsbDirection, UsbError,
+};
+
+const MAX_ENDPOINTS: usize = 16;
+
+#[derive(Default)]
+struct Endpoint {
+    // `None` if not allocated
+    ep_type: Option<EndpointType>,
+    max_packet_size: u16,
+    // OUT: queued by the host, IN: written by the device
+    packets: VecDeque<Vec<u8>>,
+    // IN: the host took a packet since the last poll
+    completed: bool,
+    stalled: bool,
+}
+
+#[derive(Default)]
+struct Endpoints {
+    out: [Endpoint; MAX_ENDPOINTS],
+    r#in: [Endpoint; MAX_ENDPOINTS],
+    reset: bool,
+    // the next packet of the control OUT endpoint is a SETUP packet
+    setup: bool,
+}
+
+impl Endpoints {
+    fn get(&mut self, addr: EndpointAddress) -> &mut Endpoint {
+        match addr.direction() {
+            UsbDirection::Out => &mut self.out[addr.index()],
+            UsbDirection::In => &mut self.r#in[addr.index()],
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct MockBus {
+    endpoints: Arc<Mutex<Endpoints>>,
+}
+
+impl MockBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Endpoints> {
+        self.endpoints.lock().unwrap()
+    }
+
+    /// The first allocated endpoint of given type and direction.
+    pub fn endpoint(&self, ep_type: EndpointType, direction: UsbDirection) -> EndpointAddress {
+        let mut endpoints = self.lock();
+        (1..MAX_ENDPOINTS)
+            .map(|index| EndpointAddress::from_parts(index, direction))
+            .find(|addr| endpoints.get(*addr).ep_type == Some(ep_type))
+            .expect("no such endpoint allocated")
+    }
+
+    /// Queue a packet for the OUT endpoint.
+    pub fn send(&self, addr: EndpointAddress, packet: &[u8]) {
+        let mut endpoints = self.lock();
+        let endpoint = endpoints.get(addr);
+        assert!(endpoint.ep_type.is_some(), "endpoint not allocated");
+        assert!(packet.len() <= endpoint.max_packet_size as usize, "packet too large");
+        endpoint.packets.push_back(packet.to_vec());
+    }
+
+    /// Queue a SETUP packet for the control endpoint, handled by the next `UsbDevice::poll`.
+    ///
+    /// For requests without data stage, see `control_out` and `control_in` otherwise.
+    pub fn setup(&self, request_type: u8, request: u8, value: u16, index: u16) {
+        self.setup_with_length(request_type, request, value, index, 0);
+    }
+
+    fn setup_with_length(&self, request_type: u8, request: u8, value: u16, index: u16, length: u16) {
+        let mut packet = vec![request_type, request];
+        packet.extend_from_slice(&value.to_le_bytes());
+        packet.extend_from_slice(&index.to_le_bytes());
+        packet.extend_from_slice(&length.to_le_bytes());
+        let endpoints = &mut *self.lock();
+        // a new control transfer replaces whatever is left of the last one
+        for endpoint in [&mut endpoints.out[0], &mut endpoints.r#in[0]] {
+            endpoint.packets.clear();
+            endpoint.stalled = false;
+        }
+        endpoints.out[0].packets.push_back(packet);
+        endpoints.setup = true;
+    }
+
+    fn control_stalled(&self) -> bool {
+        let endpoints = self.lock();
+        endpoints.out[0].stalled || endpoints.r#in[0].stalled
+    }
+
+    fn control_packet_size(&self) -> usize {
+        self.lock().out[0].max_packet_size as usize
+    }
+
+    /// Run a control transfer from host to device, with the given data stage.
+    ///
+    /// Returns false if the device stalled the request.
+    pub fn control_out(
+        &self,
+        device: &mut UsbDevice<'_, MockBus>,
+        classes: &mut [&mut dyn UsbClass<MockBus>],
+        (request_type, request, value, index): (u8, u8, u16, u16),
+        data: &[u8],
+    ) -> bool {
+        self.setup_with_length(request_type, request, value, index, data.len() as u16);
+        device.poll(classes);
+        for chunk in data.chunks(self.control_packet_size()) {
+            if self.control_stalled() {
+                return false;
+            }
+            self.lock().out[0].packets.push_back(chunk.to_vec());
+            device.poll(classes);
+        }
+        if self.control_stalled() {
+            return false;
+        }
+        // status stage
+        let accepted = self.receive(EndpointAddress::from_parts(0, UsbDirection::In)).is_some();
+        device.poll(classes);
+        accepted
+    }
+
+    /// Run a control transfer from device to host, returning its data stage.
+    ///
+    /// Returns `None` if the device stalled the request.
+    pub fn control_in(
+        &self,
+        device: &mut UsbDevice<'_, MockBus>,
+        classes: &mut [&mut dyn UsbClass<MockBus>],
+        (request_type, request, value, index): (u8, u8, u16, u16),
+        length: u16,
+    ) -> Option<Vec<u8>> {
+        self.setup_with_length(request_type, request, value, index, length);
+        device.poll(classes);
+        let mut data = Vec::new();
+        loop {
+            if self.control_stalled() {
+                return None;
+            }
+            let packet = self.receive(EndpointAddress::from_parts(0, UsbDirection::In))?;
+            data.extend_from_slice(&packet);
+            device.poll(classes);
+            if packet.len() < self.control_packet_size() || data.len() >= length as usize {
+                break;
+            }
+        }
+        // status stage
+        self.lock().out[0].packets.push_back(Vec::new());
+        device.poll(classes);
+        Some(data)
+    }
+
+    /// Take the packet the device wrote to the IN endpoint, if any.
+    ///
+    /// When driving the class directly, call `UsbClass::endpoint_in_complete` afterwards.
+    pub fn receive(&self, addr: EndpointAddress) -> Option<Vec<u8>> {
+        let mut endpoints = self.lock();
+        let endpoint = endpoints.get(addr);
+        let packet = endpoint.packets.pop_front()?;
+        endpoint.completed = true;
+        Some(packet)
+    }
+
+    /// Number of packets the host did not take from the IN endpoint yet.
+    pub fn pending(&self, addr: EndpointAddress) -> usize {
+        self.lock().get(addr).packets.len()
+    }
+
+    /// Stall or unstall the endpoint, as the host would with SET/CLEAR_FEATURE(ENDPOINT_HALT).
+    ///
+    /// Writes to a stalled IN endpoint fail with `UsbError::InvalidState`.
+    pub fn stall(&self, addr: EndpointAddress, stalled: bool) {
+        self.lock().get(addr).stalled = stalled;
+    }
+
+    /// Signal a bus reset on the next poll.
+    pub fn bus_reset(&self) {
+        self.lock().reset = true;
+    }
+}
+
+impl UsbBus for MockBus {
+    fn alloc_ep(
+        &mut self,
+        ep_dir: UsbDirection,
+        ep_addr: Option<EndpointAddress>,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        _interval: u8,
+    ) -> Result<EndpointAddress> {
+        let mut endpoints = self.lock();
+        let index = match ep_addr {
+            Some(addr) => addr.index(),
+            // endpoint 0 is the control endpoint
+            None => (1..MAX_ENDPOINTS)
+                .find(|index| endpoints.get(EndpointAddress::from_parts(*index, ep_dir)).ep_type.is_none())
+                .ok_or(UsbError::EndpointOverflow)?,
+        };
+        if index >= MAX_ENDPOINTS {
+            return Err(UsbError::InvalidEndpoint);
+        }
+        let addr = EndpointAddress::from_parts(index, ep_dir);
+        let endpoint = endpoints.get(addr);
+        if endpoint.ep_type.is_some() {
+            return Err(UsbError::InvalidEndpoint);
+        }
+        endpoint.ep_type = Some(ep_type);
+        endpoint.max_packet_size = max_packet_size;
+        Ok(addr)
+    }
+
+    fn enable(&mut self) {}
+
+    fn reset(&self) {
+        let endpoints = &mut *self.lock();
+        for endpoint in endpoints.out.iter_mut().chain(endpoints.r#in.iter_mut()) {
+            endpoint.packets.clear();
+            endpoint.completed = false;
+            endpoint.stalled = false;
+        }
+    }
+
+    fn set_device_address(&self, _addr: u8) {}
+
+    fn write(&self, ep_addr: EndpointAddress, buf: &[u8]) -> Result<usize> {
+        let mut endpoints = self.lock();
+        let endpoint = endpoints.get(ep_addr);
+        if endpoint.ep_type.is_none() {
+            return Err(UsbError::InvalidEndpoint);
+        }
+        if endpoint.stalled {
+            return Err(UsbError::InvalidState);
+        }
+        if buf.len() > endpoint.max_packet_size as usize {
+            return Err(UsbError::BufferOverflow);
+        }
+        if !endpoint.packets.is_empty() {
+            return Err(UsbError::WouldBlock);
+        }
+        endpoint.packets.push_back(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn read(&self, ep_addr: EndpointAddress, buf: &mut [u8]) -> Result<usize> {
+        let mut endpoints = self.lock();
+        let endpoint = endpoints.get(ep_addr);
+        let packet = endpoint.packets.front().ok_or(UsbError::WouldBlock)?;
+        if packet.len() > buf.len() {
+            return Err(UsbError::BufferOverflow);
+        }
+        let packet = endpoint.packets.pop_front().unwrap();
+        if ep_addr.index() == 0 {
+            endpoints.setup = false;
+        }
+        buf[..packet.len()].copy_from_slice(&packet);
+        Ok(packet.len())
+    }
+
+    fn set_stalled(&self, ep_addr: EndpointAddress, stalled: bool) {
+        self.lock().get(ep_addr).stalled = stalled;
+    }
+
+    fn is_stalled(&self, ep_addr: EndpointAddress) -> bool {
+        self.lock().get(ep_addr).stalled
+    }
+
+    fn suspend(&self) {}
+
+    fn resume(&self) {}
+
+    fn poll(&self) -> PollResult {
+        let mut endpoints = self.lock();
+        if core::mem::take(&mut endpoints.reset) {
+            return PollResult::Reset;
+        }
+
+        let (mut ep_out, mut ep_in_complete, mut ep_setup) = (0u16, 0u16, 0u16);
+        if !endpoints.out[0].packets.is_empty() {
+            if endpoints.setup {
+                ep_setup |= 1;
+            } else {
+                ep_out |= 1;
+            }
+        }
+        for index in 1..MAX_ENDPOINTS {
+            if !endpoints.out[index].packets.is_empty() {
+                ep_out |= 1 << index;
+            }
+        }
+        for index in 0..MAX_ENDPOINTS {
+            if core::mem::take(&mut endpoints.r#in[index].completed) {
+                ep_in_complete |= 1 << index;
+            }
+        }
+
+        if ep_out | ep_in_complete | ep_setup == 0 {
+            PollResult::None
+        } else {
+            PollResult::Data { ep_out, ep_in_complete, ep_setup }
+        }
+    }
+}
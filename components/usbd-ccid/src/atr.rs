@@ -0,0 +1,236 @@
+//! Answer-to-Reset, cf. ISO 7816-3, Sec. 8.2, and ISO 7816-4, Sec. 8.1.1.
+//!
+//! Handy for checking: https://smartcard-atr.apdu.fr
+
+use heapless::Vec;
+
+use crate::types::tlv;
+
+// TS and at most 32 further bytes
+pub const MAX_ATR_LENGTH: usize = 33;
+pub const MAX_HISTORICAL_BYTES: usize = 15;
+// interface bytes TAi, TBi, TCi for i = 1..=4
+const MAX_GROUPS: usize = 4;
+
+/// Interface bytes TAi, TBi, TCi of one group.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct InterfaceBytes {
+    pub ta: Option<u8>,
+    pub tb: Option<u8>,
+    pub tc: Option<u8>,
+}
+
+impl InterfaceBytes {
+    // bits 5 to 7 of T0 resp. TDi-1
+    fn indicator(&self) -> u8 {
+        ((self.ta.is_some() as u8) << 4)
+            | ((self.tb.is_some() as u8) << 5)
+            | ((self.tc.is_some() as u8) << 6)
+    }
+}
+
+/// Card capabilities (tag 7), cf. ISO 7816-4, Sec. 8.1.1.2.7.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct CardCapabilities {
+    /// First software function table (selection methods).
+    pub selection_methods: u8,
+    /// Second software function table (data coding byte).
+    pub data_coding: u8,
+    pub command_chaining: bool,
+    pub extended_lc_le: bool,
+}
+
+impl CardCapabilities {
+    fn to_bytes(&self) -> [u8; 3] {
+        [
+            self.selection_methods,
+            self.data_coding,
+            // no logical channels
+            ((self.command_chaining as u8) << 7) | ((self.extended_lc_le as u8) << 6),
+        ]
+    }
+}
+
+/// Builder for the Answer-to-Reset of a slot.
+///
+/// Interface bytes are set for the current group, `with_protocol` adds a TDi
+/// byte and starts the next group.  Historical bytes are COMPACT-TLV data
+/// objects (category indicator 0x80) in the order they are added, followed by
+/// the status indicator if any.  TCK is added unless only T=0 is indicated.
+///
+/// Panics if the ATR gets too long.
+///
+/// ```
+/// use usbd_ccid::atr::{Atr, CardCapabilities, InterfaceBytes};
+///
+/// // T=0, T=1, command chaining/extended Lc+Le/no logical channels, card issuer's data "Solo 2"
+/// // https://smartcard-atr.apdu.fr/parse?ATR=3B+8C+80+01+80+73+C0+21+C0+56+53+6F+6C+6F+20+32+A4
+/// let atr = Atr::new()
+///     .with_protocol(0)
+///     .with_protocol(1)
+///     .with_card_capabilities(CardCapabilities {
+///         selection_methods: 0xC0,
+///         data_coding: 0x21,
+///         command_chaining: true,
+///         extended_lc_le: true,
+///     })
+///     .with_card_issuers_data(b"Solo 2");
+/// assert_eq!(&atr.to_bytes(), &[
+///     0x3B, 0x8C, 0x80, 0x01, 0x80, 0x73, 0xC0, 0x21, 0xC0,
+///     0x56, 0x53, 0x6F, 0x6C, 0x6F, 0x20, 0x32, 0xA4,
+/// ]);
+///
+/// // TA1 = 0x13, TC1 = 0, T=1 with TC2 = 0 (LRC), status indicator
+/// // https://smartcard-atr.apdu.fr/parse?ATR=3B+D3+13+00+41+00+80+81+05+85
+/// let atr = Atr::new()
+///     .with_interface_bytes(InterfaceBytes { ta: Some(0x13), tb: None, tc: Some(0) })
+///     .with_protocol(1)
+///     .with_interface_bytes(InterfaceBytes { ta: None, tb: None, tc: Some(0) })
+///     .with_status_indicator(0x05, None);
+/// assert_eq!(&atr.to_bytes(), &[0x3B, 0xD3, 0x13, 0x00, 0x41, 0x00, 0x80, 0x81, 0x05, 0x85]);
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Atr {
+    interface_bytes: Vec<InterfaceBytes, MAX_GROUPS>,
+    // protocols indicated in TD1, TD2, ...
+    protocols: Vec<u8, MAX_GROUPS>,
+    // without category indicator and status indicator
+    historical_objects: Vec<u8, MAX_HISTORICAL_BYTES>,
+    status_indicator: Vec<u8, 3>,
+}
+
+impl Default for Atr {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Atr {
+    /// Minimal ATR (T=0 implied), use `with_protocol(1)` for T=1.
+    pub fn new() -> Self {
+        let mut interface_bytes = Vec::new();
+        interface_bytes.push(Default::default()).ok();
+        Self {
+            interface_bytes,
+            protocols: Vec::new(),
+            historical_objects: Vec::new(),
+            status_indicator: Vec::new(),
+        }
+    }
+
+    /// Sets TAi, TBi, TCi of the current group.
+    pub fn with_interface_bytes(mut self, interface_bytes: InterfaceBytes) -> Self {
+        if let Some(last) = self.interface_bytes.last_mut() {
+            *last = interface_bytes;
+        }
+        self
+    }
+
+    /// Adds TDi indicating the protocol, and starts the next group of interface bytes.
+    pub fn with_protocol(mut self, protocol: u8) -> Self {
+        assert!(protocol <= 0xf);
+        assert!(self.interface_bytes.len() < MAX_GROUPS, "too many interface bytes");
+        self.protocols.push(protocol).ok();
+        self.interface_bytes.push(Default::default()).ok();
+        self
+    }
+
+    /// Card service data (tag 3).
+    pub fn with_card_service_data(self, data: u8) -> Self {
+        self.with_historical_object(3, &[data])
+    }
+
+    /// Card issuer's data (tag 5), e.g. ASCII-encoded vendor or model information.
+    pub fn with_card_issuers_data(self, data: &[u8]) -> Self {
+        self.with_historical_object(5, data)
+    }
+
+    /// Pre-issuing data (tag 6).
+    pub fn with_pre_issuing_data(self, data: &[u8]) -> Self {
+        self.with_historical_object(6, data)
+    }
+
+    /// Card capabilities (tag 7).
+    pub fn with_card_capabilities(self, capabilities: CardCapabilities) -> Self {
+        self.with_historical_object(7, &capabilities.to_bytes())
+    }
+
+    /// Status indicator (tag 8): life cycle status, and optionally the status word.
+    pub fn with_status_indicator(mut self, life_cycle_status: u8, status: Option<[u8; 2]>) -> Self {
+        self.status_indicator.clear();
+        self.status_indicator.push(life_cycle_status).ok();
+        if let Some(status) = status {
+            self.status_indicator.extend_from_slice(&status).ok();
+        }
+        self.check_historical_length();
+        self
+    }
+
+    fn with_historical_object(mut self, tag: u8, value: &[u8]) -> Self {
+        assert!(
+            tlv::push_compact(&mut self.historical_objects, tag, value).is_ok(),
+            "historical bytes too long"
+        );
+        self.check_historical_length();
+        self
+    }
+
+    fn check_historical_length(&self) {
+        assert!(self.historical_bytes().is_some(), "historical bytes too long");
+    }
+
+    fn historical_bytes(&self) -> Option<Vec<u8, MAX_HISTORICAL_BYTES>> {
+        let mut bytes = Vec::new();
+        if self.historical_objects.is_empty() && self.status_indicator.is_empty() {
+            return Some(bytes);
+        }
+        // category indicator: COMPACT-TLV data objects, optional status indicator
+        bytes.push(0x80).ok();
+        bytes.extend_from_slice(&self.historical_objects).ok()?;
+        if !self.status_indicator.is_empty() {
+            tlv::push_compact(&mut bytes, 8, &self.status_indicator).ok()?;
+        }
+        Some(bytes)
+    }
+
+    // bit 8 of T0 resp. TDi-1
+    fn td_indicator(&self, group: usize) -> u8 {
+        if group < self.protocols.len() { 0x80 } else { 0 }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8, MAX_ATR_LENGTH> {
+        // checked when adding historical bytes
+        let historical_bytes = self.historical_bytes().unwrap_or_default();
+
+        let mut atr = Vec::new();
+        // TS: direct convention
+        atr.push(0x3B).ok();
+        // T0: Y1 and K, the number of historical bytes
+        atr.push(self.interface_bytes[0].indicator() | self.td_indicator(0) | historical_bytes.len() as u8).ok();
+
+        for (i, group) in self.interface_bytes.iter().enumerate() {
+            for byte in [group.ta, group.tb, group.tc].iter().flatten() {
+                atr.push(*byte).ok();
+            }
+            if let Some(protocol) = self.protocols.get(i) {
+                // TDi: Yi+1 and protocol
+                let next = self.interface_bytes[i + 1].indicator() | self.td_indicator(i + 1);
+                atr.push(next | protocol).ok();
+            }
+        }
+
+        atr.extend_from_slice(&historical_bytes).ok();
+
+        // TCK is absent if only T=0 is indicated
+        if self.protocols.iter().any(|protocol| *protocol != 0) {
+            // xor of all bytes except TS
+            let mut checksum = 0;
+            for byte in atr.iter().skip(1) {
+                checksum ^= *byte;
+            }
+            atr.push(checksum).ok();
+        }
+
+        atr
+    }
+}
@@ -2,13 +2,24 @@ use core::convert::TryFrom;
 
 use embedded_time::duration::Extensions;
 use heapless::Vec;
-use interchange::{Interchange, Requester};
+use interchange::{Interchange, Requester, Responder};
 
 use crate::{
+    atr::Atr,
     constants::*,
+    escape::EscapeInterchange,
+    time_extension::{self, HintInterchange},
     types::{
         ClassRequest,
-        packet::RawPacket,
+        IccdRequest,
+        packet::{
+            CommandType,
+            HardwareError,
+            HardwareErrorCode,
+            InterruptPacket,
+            NotifySlotChange,
+            RawPacket,
+        },
         Status,
     },
     pipe::Pipe,
@@ -17,6 +28,11 @@ use crate::{
 use usb_device::class_prelude::*;
 type Result<T> = core::result::Result<T, UsbError>;
 
+/// A USB-ICC (CCID/ICCD) reader, passing APDUs to the app over the interchange.
+///
+/// The reader only does T=1 at APDU level and has no mechanical parts, as the
+/// functional descriptor says: PC_to_RDR_T0APDU and PC_to_RDR_Mechanical are
+/// answered with bError = CMD_NOT_SUPPORTED.
 pub struct Ccid<'alloc, Bus, I, const N: usize>
 where
     Bus: 'static + UsbBus,
@@ -24,8 +40,12 @@ where
 {
     interface_number: InterfaceNumber,
     string_index: StringIndex,
-    read: EndpointOut<'alloc, Bus>,
-    // interrupt: EndpointIn<'static, Bus>,
+    mode: TransferMode,
+    // `None` for the ICCD control modes
+    read: Option<EndpointOut<'alloc, Bus>>,
+    interrupt: Option<EndpointIn<'alloc, Bus>>,
+    interrupt_outbox: Option<InterruptPacket>,
+    slot_change_pending: bool,
     pipe: Pipe<'alloc, Bus, I, N>,
 }
 
@@ -36,26 +56,194 @@ where
 {
     /// Class constructor.
     ///
-    /// The optional card issuer's data may be of length at most 13 bytes,
-    /// and allows personalizing the Answer-to-Reset, for instance by
-    /// ASCII-encoding vendor or model information.
+    /// Command APDUs of up to `N` bytes, the size of the interchange messages,
+    /// are accepted in a single XfrBlock, so hosts need not fall back to chaining.
+    ///
+    /// The Answer-to-Reset of slot 0 is built with `Atr`, which sets the protocols,
+    /// interface bytes and COMPACT-TLV historical bytes (e.g. card capabilities,
+    /// or vendor information as card issuer's data).
     pub fn new(
         allocator: &'alloc UsbBusAllocator<Bus>,
         request_pipe: Requester<I>,
-        card_issuers_data: Option<&[u8]>,
+        atr: Atr,
+    ) -> Self {
+        Self::new_with_mode(allocator, request_pipe, atr, TransferMode::Bulk)
+    }
+
+    /// Class constructor, for the given transfer mode.
+    ///
+    /// The ICCD control modes need no endpoints besides EP0: ICC_POWER_ON,
+    /// ICC_POWER_OFF, XFR_BLOCK and DATA_BLOCK are class requests, and the host
+    /// polls the status with GET_ICC_STATUS (Version A) resp. SLOT_STATUS
+    /// (Version B).  As the data stage is limited to `CONTROL_BUFFER_SIZE`,
+    /// Version A, which has no chaining, only carries short APDUs.
+    /// ICCD readers have a single slot.
+    pub fn new_with_mode(
+        allocator: &'alloc UsbBusAllocator<Bus>,
+        request_pipe: Requester<I>,
+        atr: Atr,
+        mode: TransferMode,
     ) -> Self {
-        let read = allocator.bulk(PACKET_SIZE as _);
-        let write = allocator.bulk(PACKET_SIZE as _);
-        // TODO: Add interrupt endpoint, so PC/SC does not
-        // constantly poll us with GetSlotStatus
-        //
-        // PROBLEM: We don't have enough endpoints on the peripheral :/
-        // (USBHS should have one more)
-        // let interrupt = allocator.interrupt(8 as _, 32);
-        let pipe = Pipe::new(write, request_pipe, card_issuers_data);
+        let (read, write) = match mode {
+            TransferMode::Bulk => (
+                Some(allocator.bulk(MAX_PACKET_SIZE as _)),
+                Some(allocator.bulk(MAX_PACKET_SIZE as _)),
+            ),
+            TransferMode::ControlA | TransferMode::ControlB => (None, None),
+        };
+        // NB: The interrupt endpoint is opt-in (see `with_interrupt_endpoint`),
+        // as not all peripherals have enough endpoints (LPC55 USBFS does not).
+        let pipe = Pipe::new(write, request_pipe, &atr);
         let interface_number = allocator.interface();
         let string_index = allocator.string();
-        Self { interface_number, string_index, read, /* interrupt, */ pipe }
+        Self {
+            interface_number,
+            string_index,
+            mode,
+            read,
+            interrupt: None,
+            interrupt_outbox: None,
+            slot_change_pending: false,
+            pipe,
+        }
+    }
+
+    /// Add the optional interrupt IN endpoint.
+    ///
+    /// With it, slot changes and hardware errors are signalled to the host,
+    /// so PC/SC does not need to constantly poll us with GetSlotStatus.
+    /// Only use this on peripherals with an endpoint to spare (e.g. USBHS).
+    pub fn with_interrupt_endpoint(mut self, allocator: &'alloc UsbBusAllocator<Bus>) -> Self {
+        assert!(self.mode != TransferMode::ControlA, "ICCD Version A has no interrupt endpoint");
+        self.interrupt = Some(allocator.interrupt(
+            INTERRUPT_PACKET_SIZE as _,
+            INTERRUPT_POLL_MILLISECONDS,
+        ));
+        self
+    }
+
+    /// Add another slot, with its own app interchange and Answer-to-Reset.
+    ///
+    /// The slot created by `new` is slot 0, up to `MAX_SLOTS` slots are possible.
+    /// For instance, a dedicated PIV or OpenPGP card could be exposed next to the
+    /// default apps, for middleware assuming one application per reader.
+    pub fn with_slot(mut self, request_pipe: Requester<I>, atr: Atr) -> Self {
+        assert!(self.mode == TransferMode::Bulk, "ICCD has a single slot");
+        self.pipe.add_slot(request_pipe, &atr);
+        self
+    }
+
+    /// Route PC_to_RDR_Escape commands to the given interchange,
+    /// see `escape::Dispatch` for the app side.
+    ///
+    /// Escape payloads are limited to `escape::MESSAGE_SIZE` bytes, so they fit
+    /// in a single full-speed packet.
+    pub fn with_escape_channel(mut self, requester: Requester<EscapeInterchange>) -> Self {
+        self.pipe.escape = Some(requester);
+        self
+    }
+
+    /// Set the bulk packet size for the speed the device enumerated at.
+    ///
+    /// With the `highspeed-usb` feature, the bulk endpoints take 512 byte packets,
+    /// which is assumed until told otherwise.  Call this after a bus reset, once the
+    /// peripheral knows the speed, so a high-speed capable device also works
+    /// behind full-speed hubs.  Without the feature, only full speed is possible.
+    pub fn set_bus_speed(&mut self, speed: UsbSpeed) {
+        assert!(speed.packet_size() <= MAX_PACKET_SIZE, "bulk endpoints too small, enable `highspeed-usb`");
+        self.pipe.set_packet_size(speed.packet_size());
+    }
+
+    /// Set when to request time extensions while an app is processing, and with which multiplier.
+    ///
+    /// Defaults to a time extension with multiplier 1 every second, runners should
+    /// prefer `time_extension::Policy::adaptive`, which keeps up with the BWT.
+    pub fn with_time_extension_policy(mut self, policy: time_extension::Policy) -> Self {
+        self.pipe.time_extension.policy = policy;
+        self
+    }
+
+    /// Let apps hint the expected duration of the current command,
+    /// see `time_extension::Hinter` for the app side.
+    pub fn with_time_extension_hints(mut self, responder: Responder<HintInterchange>) -> Self {
+        self.pipe.hints = Some(responder);
+        self
+    }
+
+    /// Signal card insertion or removal in the given slot to the host.
+    ///
+    /// Commands to a slot without card fail with ICC_MUTE, and an inserted card
+    /// needs to be powered on by the host.  Without the interrupt endpoint,
+    /// the host only learns about the change via GetSlotStatus.
+    /// Slots that were not added are ignored.
+    pub fn set_icc_present(&mut self, slot: usize, present: bool) {
+        let slot = match self.pipe.slots.get_mut(slot) {
+            Some(slot) => slot,
+            None => {
+                info!("no slot {}", slot);
+                return;
+            }
+        };
+        if present != slot.icc_present {
+            slot.icc_present = present;
+            slot.icc_changed = true;
+            if slot.icc_active {
+                slot.icc_active = false;
+                slot.session_reset = true;
+            }
+            self.slot_change_pending = true;
+            self.maybe_send_interrupt();
+        }
+    }
+
+    /// Signal a hardware error to the host (needs the interrupt endpoint).
+    pub fn notify_hardware_error(&mut self, code: HardwareErrorCode) {
+        if self.interrupt.is_some() {
+            // a hardware error trumps a pending slot change notification,
+            // which is re-sent afterwards
+            if self.interrupt_outbox.is_some() {
+                self.mark_slots_changed();
+            }
+            let slot = self.pipe.slot as u8;
+            self.interrupt_outbox = Some(HardwareError::new(slot, self.pipe.seq, code).into());
+            self.maybe_send_interrupt();
+        }
+    }
+
+    fn maybe_send_interrupt(&mut self) {
+        let interrupt = match self.interrupt.as_ref() {
+            Some(interrupt) => interrupt,
+            None => return,
+        };
+
+        if self.interrupt_outbox.is_none() && self.slot_change_pending {
+            let mut notification = NotifySlotChange::new();
+            for (i, slot) in self.pipe.slots.iter_mut().enumerate() {
+                notification = notification.with_slot(i, slot.icc_present, slot.icc_changed);
+                slot.icc_changed = false;
+            }
+            self.interrupt_outbox = Some(notification.into());
+            self.slot_change_pending = false;
+        }
+
+        if let Some(packet) = self.interrupt_outbox.as_ref() {
+            match interrupt.write(packet) {
+                Ok(_) => self.interrupt_outbox = None,
+                Err(UsbError::WouldBlock) => {},
+                Err(_error) => {
+                    // e.g., not configured yet, the host polls GetSlotStatus then anyway
+                    info!("dropping interrupt message: {:?}", _error);
+                    self.interrupt_outbox = None;
+                }
+            }
+        }
+    }
+
+    fn mark_slots_changed(&mut self) {
+        for slot in self.pipe.slots.iter_mut() {
+            slot.icc_changed = true;
+        }
+        self.slot_change_pending = true;
     }
 
     /// Read response from application (if any) and start writing it to
@@ -64,21 +252,135 @@ where
         self.poll();
     }
 
+    /// The delay until the first time extension is due, per the block waiting time
+    /// of the parameters in effect.
     pub fn did_start_processing(&mut self) -> Status {
-        if self.pipe.did_started_processing() {
+        match self.pipe.did_started_processing() {
             // We should send a wait extension later
-            Status::ReceivedData(1_000.milliseconds())
-        } else {
-            Status::Idle
+            Some(delay) => Status::ReceivedData(delay.milliseconds()),
+            None => Status::Idle,
         }
     }
 
+    /// Turns false on read.  Intended for resetting the apps' session state (deselecting the
+    /// current app, dropping PIN verification) when the host powers the card off or on,
+    /// or it is removed, as clients expect from a real card.  Always false for slots that
+    /// were not added.
+    pub fn did_reset_session(&mut self, slot: usize) -> bool {
+        self.pipe.slots.get_mut(slot)
+            .map_or(false, |slot| core::mem::replace(&mut slot.session_reset, false))
+    }
+
+    /// The number of slots, see `with_slot`.
+    pub fn num_slots(&self) -> usize {
+        self.pipe.slots.len()
+    }
+
+    fn write_bulk_endpoint(&self, writer: &mut DescriptorWriter, address: EndpointAddress) -> Result<()> {
+        let [size_low, size_high] = (self.pipe.packet_size() as u16).to_le_bytes();
+        writer.write(
+            usb_device::descriptor::descriptor_type::ENDPOINT,
+            // bEndpointAddress, bmAttributes (bulk), wMaxPacketSize, bInterval
+            &[address.into(), EndpointType::Bulk as u8, size_low, size_high, 0],
+        )
+    }
+
+    fn handle_iccd_out(&mut self, request: IccdRequest, value: u16, data: &[u8]) -> bool {
+        match request {
+            IccdRequest::IccPowerOn => self.pipe.handle_control_command(CommandType::PowerOn, 0, &[]),
+            IccdRequest::IccPowerOff => self.pipe.handle_control_command(CommandType::PowerOff, 0, &[]),
+            IccdRequest::XfrBlock => {
+                // wLevelParameter, Version A has no chaining
+                let level_parameter = match self.mode {
+                    TransferMode::ControlB => value,
+                    _ => 0,
+                };
+                self.pipe.handle_control_command(CommandType::XfrBlock, level_parameter, data)
+            }
+            _ => {
+                info!("unexpected direction for {:?}", &request);
+                false
+            }
+        }
+    }
+
+    /// The data stage of an ICCD request from the host, `None` to stall.
+    fn handle_iccd_in(&mut self, request: IccdRequest) -> Option<Vec<u8, CONTROL_BUFFER_SIZE>> {
+        let mut data = Vec::new();
+        match (self.mode, request) {
+            (TransferMode::ControlA, IccdRequest::GetIccStatus) => {
+                // bStatusByte: mute, busy, data available resp. expecting a command
+                let status = if self.pipe.icc_status(0) != 0 {
+                    0x80
+                } else if self.pipe.processing() {
+                    0x40
+                } else if self.pipe.busy() {
+                    0x20
+                } else {
+                    0x10
+                };
+                data.push(status).ok();
+            }
+
+            (TransferMode::ControlB, IccdRequest::SlotStatus) => {
+                // bResponseType (status information), bmICCStatus, bError
+                data.extend_from_slice(&[0x40, self.pipe.icc_status(0), 0]).ok();
+            }
+
+            (TransferMode::ControlA, IccdRequest::DataBlock) => {
+                // no chaining, so we join the response blocks
+                while let Some(packet) = self.pipe.take_control_response() {
+                    if packet[7] >> 6 != 0 {
+                        info!("command failed: {}", packet[8]);
+                        return None;
+                    }
+                    if packet[0] == 0x80 && data.extend_from_slice(&packet[10..]).is_err() {
+                        info!("response too long for Version A");
+                        self.pipe.drop_control_response();
+                        return None;
+                    }
+                    if self.pipe.sending() {
+                        self.pipe.prime_outbox();
+                    }
+                }
+            }
+
+            (TransferMode::ControlB, IccdRequest::DataBlock) => {
+                match self.pipe.take_control_response() {
+                    // bResponseType = bChainParameter of the data block, followed by the data
+                    Some(packet) if packet[0] == 0x80 && packet[7] >> 6 == 0 => {
+                        data.push(packet[9]).ok();
+                        data.extend_from_slice(&packet[10..]).ok();
+                    }
+                    // status information: bmICCStatus, bError
+                    Some(packet) => {
+                        data.extend_from_slice(&[0x40, packet[7], packet[8]]).ok();
+                    }
+                    // polling: the host should ask again after wDelay milliseconds
+                    None if self.pipe.processing() => {
+                        data.push(0x80).ok();
+                        data.extend_from_slice(&ICCD_POLL_MILLISECONDS.to_le_bytes()).ok();
+                    }
+                    None => {
+                        data.extend_from_slice(&[0x40, self.pipe.icc_status(0), 0]).ok();
+                    }
+                }
+            }
+
+            _ => {
+                info!("unexpected request {:?} in mode {:?}", &request, self.mode);
+                return None;
+            }
+        }
+        Some(data)
+    }
+
+    /// Sends a time extension if still processing, returns the delay until the next one is due.
     pub fn send_wait_extension (&mut self) -> Status {
-        if self.pipe.send_wait_extension() {
+        match self.pipe.send_wait_extension() {
             // We should send another wait extension later
-            Status::ReceivedData(1_000.milliseconds())
-        } else {
-            Status::Idle
+            Some(delay) => Status::ReceivedData(delay.milliseconds()),
+            None => Status::Idle,
         }
     }
 }
@@ -96,16 +398,23 @@ where
             0,
             CLASS_CCID,
             SUBCLASS_NONE,
-            TransferMode::Bulk as u8,
+            self.mode as u8,
             Some(self.string_index),
         )?;
         writer.write(
             FUNCTIONAL_INTERFACE,
-            &FUNCTIONAL_INTERFACE_DESCRIPTOR,
+            &functional_interface_descriptor(self.pipe.slots.len(), N + 10),
         )?;
-        writer.endpoint(&self.pipe.write).unwrap();
-        writer.endpoint(&self.read).unwrap();
-        // writer.endpoint(&self.interrupt).unwrap();
+        // wMaxPacketSize follows the bus speed rather than the allocated size
+        if let Some(write) = self.pipe.write.as_ref() {
+            self.write_bulk_endpoint(writer, write.address())?;
+        }
+        if let Some(read) = self.read.as_ref() {
+            self.write_bulk_endpoint(writer, read.address())?;
+        }
+        if let Some(interrupt) = self.interrupt.as_ref() {
+            writer.endpoint(interrupt).unwrap();
+        }
         Ok(())
     }
 
@@ -119,16 +428,32 @@ where
         // info_now!("poll of ccid");
         self.pipe.poll_app();
         self.pipe.maybe_send_packet();
+        self.maybe_send_interrupt();
+    }
+
+    fn reset(&mut self) {
+        // after (re-)enumeration, the host should learn about the slot state
+        self.interrupt_outbox = None;
+        if self.interrupt.is_some() {
+            self.mark_slots_changed();
+        }
     }
 
     fn endpoint_in_complete(&mut self, addr: EndpointAddress) {
-        if addr != self.pipe.write.address() { return; }
+        if self.interrupt.as_ref().map(|interrupt| interrupt.address()) == Some(addr) {
+            self.maybe_send_interrupt();
+            return;
+        }
+        if self.pipe.write.as_ref().map(|write| write.address()) != Some(addr) { return; }
 
         self.pipe.maybe_send_packet();
     }
 
     fn endpoint_out(&mut self, addr: EndpointAddress) {
-        if addr != self.read.address() { return; }
+        let read = match self.read.as_ref() {
+            Some(read) if read.address() == addr => read,
+            _ => return,
+        };
 
         // let maybe_packet = RawPacket::try_from(
         //     |packet| self.read.read(packet));
@@ -136,7 +461,7 @@ where
         let maybe_packet = {
             let mut packet = RawPacket::new();
             packet.resize_default(packet.capacity()).unwrap();
-            let result = self.read.read(&mut packet);
+            let result = read.read(&mut packet);
             result.map(|count| {
                 packet.resize_default(count).unwrap();
                 packet
@@ -153,12 +478,25 @@ where
 
     fn control_in(&mut self, transfer: ControlIn<Bus>) {
         use usb_device::control::*;
-        let Request { request_type, recipient, index, request, .. } = *transfer.request();
+        let Request { request_type, recipient, index, request, length, .. } = *transfer.request();
         if index != u8::from(self.interface_number) as u16 {
             return;
         }
 
         if (request_type, recipient) == (RequestType::Class, Recipient::Interface) {
+            if self.mode != TransferMode::Bulk {
+                if let Ok(request) = IccdRequest::try_from(request) {
+                    match self.handle_iccd_in(request) {
+                        Some(data) => {
+                            let length = core::cmp::min(data.len(), length as usize);
+                            transfer.accept_with(&data[..length]).ok();
+                        }
+                        None => { transfer.reject().ok(); }
+                    }
+                    return;
+                }
+            }
+
             match ClassRequest::try_from(request) {
                 Ok(request) => {
                     match request {
@@ -171,7 +509,10 @@ where
                         ClassRequest::GetDataRates => {
                             transfer.accept_with_static(&DATA_RATE_BPS).ok();
                         },
-                        _ => panic!("unexpected direction for {:?}", &request),
+                        _ => {
+                            info!("unexpected direction for {:?}", &request);
+                            transfer.reject().ok();
+                        }
                     }
                 }
 
@@ -192,6 +533,17 @@ where
         }
 
         if (request_type, recipient) == (RequestType::Class, Recipient::Interface) {
+            if self.mode != TransferMode::Bulk {
+                if let Ok(request) = IccdRequest::try_from(request) {
+                    if self.handle_iccd_out(request, value, transfer.data()) {
+                        transfer.accept().ok();
+                    } else {
+                        transfer.reject().ok();
+                    }
+                    return;
+                }
+            }
+
             match ClassRequest::try_from(request) {
                 Ok(request) => {
                     match request {
@@ -205,7 +557,10 @@ where
                             // transfer.reject().ok();
                             // todo!();
                         }
-                        _ => panic!("unexpected direction for {:?}", &request),
+                        _ => {
+                            info!("unexpected direction for {:?}", &request);
+                            transfer.reject().ok();
+                        }
                     }
                 }
 
@@ -1,22 +1,55 @@
-// can be 8, 16, 32, 64 or 512
+// bulk packet size, depending on the bus speed, see `Ccid::set_bus_speed`
+pub const FULL_SPEED_PACKET_SIZE: usize = 64;
+pub const HIGH_SPEED_PACKET_SIZE: usize = 512;
+// the bulk endpoints can take high-speed packets (e.g. USBHS)
 #[cfg(feature = "highspeed-usb")]
-pub const PACKET_SIZE: usize = 512;
+pub const MAX_PACKET_SIZE: usize = HIGH_SPEED_PACKET_SIZE;
 #[cfg(not(feature = "highspeed-usb"))]
-pub const PACKET_SIZE: usize = 64;
+pub const MAX_PACKET_SIZE: usize = FULL_SPEED_PACKET_SIZE;
+
+/// The speed the device enumerated at, which determines the bulk packet size.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UsbSpeed {
+    FullSpeed,
+    HighSpeed,
+}
+
+impl UsbSpeed {
+    pub fn packet_size(&self) -> usize {
+        match self {
+            UsbSpeed::FullSpeed => FULL_SPEED_PACKET_SIZE,
+            UsbSpeed::HighSpeed => HIGH_SPEED_PACKET_SIZE,
+        }
+    }
+}
+
+// interrupt IN endpoint (optional), only carries RDR_to_PC_NotifySlotChange
+// and RDR_to_PC_HardwareError, so 8 bytes suffice
+pub const INTERRUPT_PACKET_SIZE: usize = 8;
+pub const INTERRUPT_POLL_MILLISECONDS: u8 = 32;
 
 pub const CLASS_CCID: u8 = 0x0B;
 pub const SUBCLASS_NONE: u8 = 0x0;
 
+/// bInterfaceProtocol, selected with `Ccid::new_with_mode`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[repr(u8)]
 pub enum TransferMode {
     // bulk transfers, optional interrupt IN
     Bulk = 0,
     // control transfers, no interrupt IN
+    // ICCD Version A: no chaining, the host polls GET_ICC_STATUS
     ControlA = 1,
     // control transfers, optional interrupt IN
+    // ICCD Version B: chaining, status is signalled in DATA_BLOCK
     ControlB = 2,
 }
 
+// usb-device with the "control-buffer-256" feature, limits the data stage of ICCD requests
+pub const CONTROL_BUFFER_SIZE: usize = 256;
+// ICCD Version B: DATA_BLOCK asks the host to poll again after this many milliseconds
+pub const ICCD_POLL_MILLISECONDS: u16 = 10;
+
 pub const FUNCTIONAL_INTERFACE: u8 = 0x21;
 pub const FUNCTIONAL_INTERFACE_STRING: &str = "CCID/ICCD Interface";
 
@@ -36,12 +69,15 @@ pub const DATA_RATE_BPS: [u8; 4] = [0x80, 0x25, 0x00, 0x00];
 // 254 (as per ICCD spec)
 pub const MAX_IFSD: [u8; 4] = [0xfe, 0x00, 0x00, 0x00];
 
-// "The value shall be between 261 + 10 and 65544 + 10
-// dwMaxCCIDMsgLen 3072
-pub const MAX_MSG_LENGTH: usize = 3072;
-pub const MAX_MSG_LENGTH_LE: [u8; 4] = [0x00, 0x0C, 0x00, 0x00];
+// dwMaxCCIDMsgLen: "The value shall be between 261 + 10 and 65544 + 10",
+// the header plus the largest APDU the app interchange holds
+pub const MIN_MSG_LENGTH: usize = 261 + 10;
+pub const MAX_MSG_LENGTH: usize = 65544 + 10;
 
-pub const NUM_SLOTS: u8 = 1;
+// slots are added with `Ccid::with_slot`, bmSlotICCState of
+// RDR_to_PC_NotifySlotChange fits four of them in one byte
+pub const MAX_SLOTS: usize = 4;
+// the pipe has a single transfer state, so only one slot can process at a time
 pub const MAX_BUSY_SLOTS: u8 = 1;
 // bPinSupport (0x0 = none, 0x01 = verification, 0x02 = modification)
 pub const PIN_SUPPORT: u8 = 0;
@@ -50,13 +86,13 @@ pub const PIN_SUPPORT: u8 = 0;
 pub const FUNCTIONAL_INTERFACE_DESCRIPTOR: [u8; 52] = [
     // bcdCCID rev1.10
     0x10, 0x01,
-    // bMaxSlotIndex
-    // NUM_SLOTS - 1,
+    // bMaxSlotIndex, see `functional_interface_descriptor`
     // "An USB-ICC is regarded as a single slot CCID."
     0x00,
     // bVoltageSupport (5.0V)
     0x01,
-    // dwProtocols: APDU level, T=1 only (0 = T=0, 3 = T0+T1)
+    // dwProtocols: APDU level, T=1 only (bit 0 = T=0, bit 1 = T=1),
+    // so PC_to_RDR_T0APDU is not supported
     0x02, 0x00, 0x00, 0x00,
 
     // dwDefaultClock (3.58 MHz)
@@ -95,6 +131,11 @@ pub const FUNCTIONAL_INTERFACE_DESCRIPTOR: [u8; 52] = [
     // dwMechanical: no special characteristics
     0x00, 0x00, 0x00, 0x00,
 
+    // NB: there is no dwFeatures bit for the interrupt IN endpoint,
+    // its presence is advertised by the endpoint descriptor following
+    // this functional descriptor (cf. Sec. 5.2.3), and bInterfaceProtocol
+    // (Bulk = "bulk transfers, optional interrupt IN").
+
     // dwFeatures, see following comments
     // Auto configuration based on ATR
     // Auto activation on insert
@@ -108,14 +149,11 @@ pub const FUNCTIONAL_INTERFACE_DESCRIPTOR: [u8; 52] = [
     // upper word: 0000 = char level, 0002 = short APDU, 0004 = short+exteded APDU
     0x40, 0x08, 0x04, 0x00,
 
-    // dwMaxCCIDMsgLen (3072)
+    // dwMaxCCIDMsgLen, see `functional_interface_descriptor`
     // gnuk: 271
-    MAX_MSG_LENGTH_LE[0],
-    MAX_MSG_LENGTH_LE[1],
-    MAX_MSG_LENGTH_LE[2],
-    MAX_MSG_LENGTH_LE[3],
+    0x00, 0x00, 0x00, 0x00,
 
-    // bClassGetResponse ("echo"), as per ICCD spec
+    // bClassGetResponse ("echo"), as per ICCD spec, only relevant for T=0
     0xFF,
     // bClassEnvelope ("echo"), as per ICCD spec, gnuk: 0
     0xFF,
@@ -127,3 +165,16 @@ pub const FUNCTIONAL_INTERFACE_DESCRIPTOR: [u8; 52] = [
     // bMaxCCIDBusySlots
     MAX_BUSY_SLOTS,
 ];
+
+/// The functional interface descriptor for a reader with the given number of slots,
+/// accepting messages with up to `max_msg_length` bytes (including the 10 byte header).
+pub fn functional_interface_descriptor(num_slots: usize, max_msg_length: usize) -> [u8; 52] {
+    assert!(0 < num_slots && num_slots <= MAX_SLOTS);
+    let mut descriptor = FUNCTIONAL_INTERFACE_DESCRIPTOR;
+    // bMaxSlotIndex
+    descriptor[2] = num_slots as u8 - 1;
+    // dwMaxCCIDMsgLen
+    let max_msg_length = core::cmp::min(max_msg_length, MAX_MSG_LENGTH) as u32;
+    descriptor[42..46].copy_from_slice(&max_msg_length.to_le_bytes());
+    descriptor
+}
@@ -0,0 +1,101 @@
+//! Vendor commands via PC_to_RDR_Escape.
+//!
+//! The first byte of the escape payload selects the command, the rest is
+//! passed to the app registered for it.  This allows talking to admin-style
+//! functions without an AID SELECT, modeled after the CTAPHID vendor commands
+//! in ctaphid-dispatch.
+//!
+//! Payloads are capped at `MESSAGE_SIZE` (54) bytes in both directions, including
+//! the command byte: longer requests are rejected by the reader with bError = 1
+//! (dwLength), and apps can't produce longer responses.
+//!
+//! Failures are reported in bError of RDR_to_PC_Escape: `Error::InvalidCommand` as
+//! CMD_NOT_SUPPORTED, `Error::InvalidLength` as 1 (dwLength), and `Error::NoResponse`
+//! as HW_ERROR.
+
+use heapless::Vec;
+use interchange::Responder;
+
+use crate::constants::FULL_SPEED_PACKET_SIZE;
+
+/// Escape messages (including the command byte) fit in a single full-speed
+/// bulk packet, so RDR_to_PC_Escape needs no multi-packet transfer.
+pub const MESSAGE_SIZE: usize = 64 - 10;
+
+// escape responses must fit in one packet with the CCID header
+const _: () = assert!(MESSAGE_SIZE + 10 <= FULL_SPEED_PACKET_SIZE);
+
+pub type Message = Vec<u8, MESSAGE_SIZE>;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    // no app registered for the command
+    InvalidCommand,
+    // the request is empty, or its length is wrong for the command
+    InvalidLength,
+    // the app could not handle the command, e.g. the information is not available
+    NoResponse,
+}
+
+pub type Request = Message;
+pub type Response = Result<Message, Error>;
+
+interchange::interchange! {
+    EscapeInterchange: (Request, Response)
+}
+
+pub trait App {
+    /// Escape commands (first payload byte) handled by this app.
+    fn commands(&self) -> &'static [u8];
+
+    /// Handle the command, `request` does not contain the command byte.
+    ///
+    /// The `response` holds up to `MESSAGE_SIZE` bytes.
+    fn call(&mut self, command: u8, request: &[u8], response: &mut Message) -> Result<(), Error>;
+}
+
+pub struct Dispatch {
+    responder: Responder<EscapeInterchange>,
+}
+
+impl Dispatch {
+    pub fn new(responder: Responder<EscapeInterchange>) -> Self {
+        Self { responder }
+    }
+
+    /// Returns true if a request was handled.
+    #[inline(never)]
+    pub fn poll(&mut self, apps: &mut [&mut dyn App]) -> bool {
+        let request = match self.responder.take_request() {
+            Some(request) => request,
+            None => return false,
+        };
+
+        let response = match request.split_first() {
+            Some((&command, data)) => Self::call_app(apps, command, data),
+            None => Err(Error::InvalidLength),
+        };
+
+        if self.responder.respond(&response).is_err() {
+            // the pipe canceled the request in the meantime
+            info!("could not respond to escape command");
+            self.responder.acknowledge_cancel().ok();
+        }
+        true
+    }
+
+    fn call_app(apps: &mut [&mut dyn App], command: u8, data: &[u8]) -> Response {
+        let app = apps.iter_mut().find(|app| app.commands().contains(&command));
+        match app {
+            Some(app) => {
+                let mut response = Message::new();
+                app.call(command, data, &mut response)?;
+                Ok(response)
+            }
+            None => {
+                info!("no app for escape command {}", command);
+                Err(Error::InvalidCommand)
+            }
+        }
+    }
+}
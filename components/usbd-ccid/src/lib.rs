@@ -7,11 +7,15 @@
 extern crate delog;
 generate_macros!();
 
+pub mod atr;
 pub mod constants;
 pub mod class;
+pub mod escape;
 pub mod pipe;
+pub mod time_extension;
 pub mod types;
 
 // pub mod piv;
 
+pub use atr::Atr;
 pub use class::Ccid;
@@ -1,16 +1,24 @@
 use core::convert::TryFrom;
 use heapless::Vec;
-use interchange::{Interchange, Requester};
+use interchange::{Interchange, Requester, Responder};
 
 use crate::{
+    atr::{Atr, MAX_ATR_LENGTH},
     constants::*,
+    escape::{self, EscapeInterchange},
+    time_extension::{HintInterchange, TimeExtension},
     types::packet::{
         Chain,
+        ClockCommand,
         Command as PacketCommand,
+        CommandType,
         DataBlock,
         Error as PacketError,
+        Escape as EscapePacket,
         ExtPacket,
         RawPacket,
+        SetParameters,
+        T1Parameters,
         XfrBlock,
 
         ChainedPacket as _,
@@ -32,12 +40,77 @@ pub enum State {
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[allow(dead_code)]
 enum Error {
-    CmdAborted = 0xff,
-    IccMute = 0xfe,
-    XfrParityError = 0xfd,
+    CmdAborted,
+    IccMute,
+    XfrParityError,
     //..
-    CmdSlotBusy = 0xE0,
-    CommandNotSupported = 0x00,
+    CmdSlotBusy,
+    HwError,
+    CommandNotSupported,
+    // bSlot does not exist, reported with bmICCStatus = "no ICC present"
+    SlotDoesNotExist,
+    // offset of the first invalid field of the command message
+    BadParameter(u8),
+}
+
+impl Error {
+    fn code(&self) -> u8 {
+        match self {
+            Error::CmdAborted => 0xff,
+            Error::IccMute => 0xfe,
+            Error::XfrParityError => 0xfd,
+            Error::CmdSlotBusy => 0xe0,
+            Error::HwError => 0xfb,
+            Error::CommandNotSupported => 0x00,
+            Error::SlotDoesNotExist => 5,
+            Error::BadParameter(offset) => *offset,
+        }
+    }
+}
+
+/// A (virtual) card, with its own ATR and app interchange.
+pub(crate) struct Slot<I>
+where
+    I: 'static + Interchange,
+{
+    interchange: Requester<I>,
+    atr: Vec<u8, MAX_ATR_LENGTH>,
+    // The sequence number of the last bulk command if it was an abort command.
+    bulk_abort: Option<u8>,
+    // The sequence number of the last abort command received over the control pipe, if any.
+    control_abort: Option<u8>,
+    parameters: T1Parameters,
+    clock_stopped: bool,
+    pub(crate) icc_present: bool,
+    // powered on, i.e. not after PowerOff (or removal) without subsequent PowerOn
+    pub(crate) icc_active: bool,
+    // presence changed since the last RDR_to_PC_NotifySlotChange
+    pub(crate) icc_changed: bool,
+    // the card was powered off or reset since the apps were last told
+    pub(crate) session_reset: bool,
+    // the app is still busy with an aborted command, its response is dropped
+    stale_response: bool,
+}
+
+impl<I> Slot<I>
+where
+    I: 'static + Interchange,
+{
+    fn new(interchange: Requester<I>, atr: &Atr) -> Self {
+        Self {
+            interchange,
+            atr: atr.to_bytes(),
+            bulk_abort: None,
+            control_abort: None,
+            parameters: Default::default(),
+            clock_stopped: false,
+            icc_present: true,
+            icc_active: true,
+            icc_changed: false,
+            session_reset: false,
+            stale_response: false,
+        }
+    }
 }
 
 pub struct Pipe<'alloc, Bus, I, const N: usize>
@@ -45,11 +118,17 @@ where
     Bus: 'static + UsbBus,
     I: 'static + Interchange<REQUEST = Vec<u8, N>, RESPONSE = Vec<u8, N>>,
 {
-    pub(crate) write: EndpointIn<'alloc, Bus>,
+    // `None` for the ICCD control modes, the host then fetches responses with DATA_BLOCK
+    pub(crate) write: Option<EndpointIn<'alloc, Bus>>,
     // pub(crate) rpc: TransportEndpoint<'rpc>,
-    seq: u8,
+    pub(crate) slots: Vec<Slot<I>, MAX_SLOTS>,
+    // slot and sequence number of the current command, and of the transfer in progress, if any
+    pub(crate) slot: usize,
+    pub(crate) seq: u8,
+    // bulk packet size for the current bus speed, at most MAX_PACKET_SIZE
+    packet_size: usize,
     state: State,
-    interchange: Requester<I>,
+    // how much of the app's response was sent already, it stays in the interchange until then
     sent: usize,
     outbox: Option<RawPacket>,
 
@@ -58,13 +137,19 @@ where
     packet_len: usize,
     receiving_long: bool,
     long_packet_missing: usize,
+    // the announced message does not fit, we keep receiving it but will reply with an error
+    long_packet_overflow: bool,
     in_chain: usize,
+    // the accepted XfrBlock spans several USB packets, completed once all arrived
+    pending_block: Option<Chain>,
     pub(crate) started_processing: bool,
-    atr: Vec<u8, 32>,
-    // The sequence number of the last bulk command if it was an abort command.
-    bulk_abort: Option<u8>,
-    // The sequence number of the last abort command received over the control pipe, if any.
-    control_abort: Option<u8>,
+    pub(crate) escape: Option<Requester<EscapeInterchange>>,
+    // sequence number of the PC_to_RDR_Escape being processed (in `slot`), if any
+    escape_seq: Option<u8>,
+    // ICCD: the sequence number of the last command received on the control pipe
+    control_seq: u8,
+    pub(crate) time_extension: TimeExtension,
+    pub(crate) hints: Option<Responder<HintInterchange>>,
 }
 
 impl<'alloc, Bus, I, const N: usize> Pipe<'alloc, Bus, I, N>
@@ -73,66 +158,56 @@ where
     I: 'static + Interchange<REQUEST = Vec<u8, N>, RESPONSE = Vec<u8, N>>,
 {
     pub(crate) fn new(
-        write: EndpointIn<'alloc, Bus>,
+        write: Option<EndpointIn<'alloc, Bus>>,
         request_pipe: Requester<I>,
-        card_issuers_data: Option<&[u8]>,
+        atr: &Atr,
     ) -> Self {
 
-        assert!(MAX_MSG_LENGTH >= PACKET_SIZE);
+        assert!(N + 10 >= MIN_MSG_LENGTH, "interchange too small for short APDUs");
+
+        let mut slots = Vec::new();
+        slots.push(Slot::new(request_pipe, atr)).ok();
 
         Self {
             write,
+            slots,
+            slot: 0,
             seq: 0,
+            packet_size: MAX_PACKET_SIZE,
             state: State::Idle,
             sent: 0,
             outbox: None,
-            interchange: request_pipe,
 
             ext_packet: Default::default(),
             packet_len: 0,
             receiving_long: false,
             long_packet_missing: 0,
+            long_packet_overflow: false,
             in_chain: 0,
+            pending_block: None,
             started_processing: false,
-            // later on, we only signal T=1 support
-            // if for some reason not signaling T=0 support leads to issues,
-            // we can enable it here.
-            atr: Self::construct_atr(card_issuers_data, false),
-            bulk_abort: None,
-            control_abort: None,
+            escape: None,
+            escape_seq: None,
+            control_seq: 0,
+            time_extension: Default::default(),
+            hints: None,
         }
     }
 
-    fn construct_atr(card_issuers_data: Option<&[u8]>, signal_t_equals_0: bool) -> Vec<u8, 32> {
-        assert!(card_issuers_data.map_or(true, |data| data.len() <= 13));
-        let k = card_issuers_data.map_or(0u8, |data| 2 + data.len() as u8);
-        let mut atr = Vec::new();
-        // TS: direct convention
-        atr.push(0x3B).ok();
-        // T0: encode length of historical bytes
-        atr.push(0x80 | k).ok();
-        if signal_t_equals_0 {
-            // T=0, more to follow
-            atr.push(0x80).ok();
-        }
-        // T=1
-        atr.push(0x01).ok();
+    pub(crate) fn add_slot(&mut self, request_pipe: Requester<I>, atr: &Atr) {
+        assert!(self.slots.len() < MAX_SLOTS, "at most {} slots", MAX_SLOTS);
+        self.slots.push(Slot::new(request_pipe, atr)).ok();
+    }
 
-        if let Some(data) = card_issuers_data {
-            // no status indicator
-            atr.push(0x80).ok();
-            // tag 5: card issuer's data
-            atr.push(0x50 | data.len() as u8).ok();
-            atr.extend_from_slice(data).ok();
+    /// After (re-)enumeration, partial transfers are dropped.
+    pub(crate) fn set_packet_size(&mut self, packet_size: usize) {
+        assert!(packet_size <= MAX_PACKET_SIZE);
+        if packet_size != self.packet_size {
+            self.packet_size = packet_size;
+            self.outbox = None;
+            self.drop_long_packet();
+            self.resync();
         }
-        // xor of all bytes except TS
-        let mut checksum = 0;
-        for byte in atr.iter().skip(1) {
-            checksum ^= *byte;
-        }
-        atr.push(checksum).ok();
-
-        atr
     }
 
     pub fn busy(&self) -> bool {
@@ -140,6 +215,70 @@ where
         // to send, we can't accept new packets
         self.outbox.is_some()
     }
+
+    /// ICCD: passes a command received on the control pipe through the usual message
+    /// handling, its response is kept until the host fetches it with DATA_BLOCK.
+    ///
+    /// Returns false if the app is still processing the previous command.
+    pub(crate) fn handle_control_command(&mut self, command_type: CommandType, level_parameter: u16, data: &[u8]) -> bool {
+        if matches!(self.state, State::Processing | State::ReadyToSend) {
+            return false;
+        }
+        // a response the host did not fetch is dropped
+        self.outbox = None;
+        self.control_seq = self.control_seq.wrapping_add(1);
+
+        let mut packet = RawPacket::new();
+        packet.resize_default(10).ok();
+        packet[0] = command_type as u8;
+        packet[1..5].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        // bSlot 0, ICCD has a single slot
+        packet[6] = self.control_seq;
+        packet[8..10].copy_from_slice(&level_parameter.to_le_bytes());
+
+        // split up as if received on the bulk endpoint
+        let (first, rest) = data.split_at(core::cmp::min(data.len(), self.packet_size - 10));
+        packet.extend_from_slice(first).ok();
+        self.handle_packet(packet);
+        for chunk in rest.chunks(self.packet_size) {
+            self.handle_packet(RawPacket::from_slice(chunk).unwrap());
+        }
+        true
+    }
+
+    /// ICCD: the response to the last command, if the host did not fetch it yet.
+    pub(crate) fn take_control_response(&mut self) -> Option<RawPacket> {
+        self.outbox.take()
+    }
+
+    /// ICCD: drops the rest of a response the host cannot fetch, to accept new commands.
+    pub(crate) fn drop_control_response(&mut self) {
+        self.outbox = None;
+        self.resync();
+    }
+
+    pub(crate) fn packet_size(&self) -> usize {
+        self.packet_size
+    }
+
+    /// ICCD: whether the app is still processing the last command.
+    pub(crate) fn processing(&self) -> bool {
+        matches!(self.state, State::Processing | State::ReadyToSend)
+    }
+
+    /// Whether more blocks of a chained response are pending.
+    pub(crate) fn sending(&self) -> bool {
+        self.state == State::Sending
+    }
+
+    /// bmICCStatus: 0 = present and active, 1 = present and inactive, 2 = no ICC present
+    pub(crate) fn icc_status(&self, slot: usize) -> u8 {
+        match self.slots.get(slot) {
+            Some(slot) if slot.icc_present && slot.icc_active => 0,
+            Some(slot) if slot.icc_present => 1,
+            _ => 2,
+        }
+    }
 }
 
 
@@ -151,45 +290,47 @@ where
     pub fn handle_packet(&mut self, packet: RawPacket) {
         use crate::types::packet::RawPacketExt;
 
-        // SHOULD CLEAN THIS UP!
         // The situation is as follows: full 64B USB packet received.
         // CCID packet signals no command chaining, but data length > 64 - 10.
         // Then we can expect to receive more USB packets containing only data.
-        // The concatenation of all these is then a valid Command APDU.
+        // The concatenation of all these is then a valid Command APDU, of up to
+        // the interchange size, which we append to the app request as it arrives.
         // (which itself may have command chaining on a higher level, e.g.
         // when certificates are transmitted, because PIV somehow uses short APDUs
         // only (can we fix this), so 255B is the maximum)
-        if !self.receiving_long {
-            if packet.len() < 10 {
-                panic!("unexpected short packet");
+        if self.receiving_long {
+            self.handle_long_packet(&packet);
+            return;
+        }
+
+        if packet.len() < 10 {
+            info!("unexpected short packet");
+            // if at least bSeq is there, we can tell the host
+            if packet.len() > 6 {
+                self.reject(packet[0], packet[5], packet[6], Error::BadParameter(1));
             }
-            self.ext_packet.clear();
-            // TODO check
-            self.ext_packet.extend_from_slice(&packet).unwrap();
-
-            let pl = packet.packet_len();
-            if pl > 54 {
-                self.receiving_long = true;
-                self.in_chain = 1;
-                self.long_packet_missing = pl - 54;
-                self.packet_len = pl;
+            return;
+        }
+        self.ext_packet.clear();
+        // ExtPacket has the size of RawPacket
+        self.ext_packet.extend_from_slice(&packet).ok();
+
+        let pl = packet.packet_len();
+        if pl > packet.len() - 10 {
+            if packet.len() < self.packet_size {
+                // a short USB packet ends the transfer, so the data is missing
+                info!("announced length {} exceeds packet", pl);
+                self.reject(packet[0], packet[5], packet[6], Error::BadParameter(1));
                 return;
-            } else {
-                // normal case
             }
+            // the command is handled right away, see `end_block`
+            self.receiving_long = true;
+            self.in_chain = 1;
+            self.long_packet_missing = pl - (packet.len() - 10);
+            self.long_packet_overflow = false;
+            self.packet_len = pl;
         } else {
-            // TODO check
-            self.ext_packet.extend_from_slice(&packet).ok();
-            self.in_chain += 1;
-            assert!(packet.len() <= self.long_packet_missing);
-            self.long_packet_missing -= packet.len();
-            if self.long_packet_missing > 0 {
-                return;
-            } else {
-                // info!("pl {}, p {}, missing {}, in_chain {}", self.packet_len, packet.len(), self.long_packet_missing, self.in_chain).ok();
-                // info!("packet: {:X?}", &self.ext_packet).ok();
-                self.receiving_long = false;
-            }
+            // normal case
         }
 
         // info!("{:X?}", &packet).ok();
@@ -197,58 +338,276 @@ where
         // match PacketCommand::try_from(packet) {
         match PacketCommand::try_from(self.ext_packet.clone()) {
             Ok(command) => {
-                self.seq = command.seq();
+                let slot = usize::from(command.slot());
+                let seq = command.seq();
+
+                if slot >= self.slots.len() {
+                    self.reject(command[0], command.slot(), seq, Error::SlotDoesNotExist);
+                    return;
+                }
+
+                // Only the data of an XfrBlock may span several USB packets.
+                if self.receiving_long && !matches!(command, PacketCommand::XfrBlock(_)) {
+                    // dwLength
+                    self.reject(command[0], command.slot(), seq, Error::BadParameter(1));
+                    return;
+                }
+
+                // Aborts are tracked per slot, and may interrupt a busy slot.
+                if matches!(command, PacketCommand::Abort(_)) {
+                    self.handle_bulk_abort(slot, seq);
+                    return;
+                }
+
+                // While an app is processing, only an abort may interrupt us.
+                // A chained transfer also keeps other slots busy until it completes.
+                let busy = match self.state {
+                    State::Idle => false,
+                    State::Receiving | State::Sending => slot != self.slot,
+                    State::Processing | State::ReadyToSend => true,
+                };
+                if busy || self.escape_seq.is_some() {
+                    info!("slot busy, rejecting {:?}", command.command_type());
+                    // the pending response must carry the slot and sequence number of its command
+                    self.reject(command[0], command.slot(), seq, Error::CmdSlotBusy);
+                    return;
+                }
+
+                self.slot = slot;
+                self.seq = seq;
 
                 // If we receive an ABORT on the control pipe, we reject all further commands until
                 // we receive a matching ABORT on the bulk endpoint too.
-                if let Some(control_abort) = self.control_abort {
-                    if matches!(command, PacketCommand::Abort(_)) && control_abort == self.seq {
-                        self.abort();
-                    } else {
-                        self.send_slot_status_error(Error::CmdAborted);
-                    }
+                if self.slots[slot].control_abort.is_some() {
+                    self.send_error(command[0], Error::CmdAborted);
+                    return;
+                }
+                self.slots[slot].bulk_abort = None;
+
+                let current = &self.slots[slot];
+                let mute = match command {
+                    PacketCommand::PowerOn(_) => !current.icc_present,
+                    PacketCommand::XfrBlock(_) => !current.icc_active,
+                    _ => false,
+                };
+                if mute {
+                    self.send_error(command[0], Error::IccMute);
                     return;
                 }
-                self.bulk_abort = None;
 
                 // happy path
                 match command {
-                    PacketCommand::PowerOn(_command) => self.send_atr(),
+                    PacketCommand::PowerOn(_command) => {
+                        // cold or warm reset, the card starts a new session
+                        let current = &mut self.slots[slot];
+                        current.icc_active = true;
+                        current.session_reset = true;
+                        current.parameters = Default::default();
+                        current.clock_stopped = false;
+                        self.send_atr();
+                    }
 
-                    PacketCommand::PowerOff(_command) => self.send_slot_status_ok(),
+                    PacketCommand::PowerOff(_command) => {
+                        let current = &mut self.slots[slot];
+                        current.icc_active = false;
+                        current.session_reset = true;
+                        self.send_slot_status_ok();
+                    }
 
                     PacketCommand::GetSlotStatus(_command) => self.send_slot_status_ok(),
 
                     PacketCommand::XfrBlock(command) => self.handle_transfer(command),
 
-                    PacketCommand::Abort(_command) => self.bulk_abort = Some(self.seq),
+                    // handled above
+                    PacketCommand::Abort(_command) => {}
 
                     PacketCommand::GetParameters(_command) => self.send_parameters(),
+
+                    PacketCommand::ResetParameters(_command) => {
+                        self.slots[slot].parameters = Default::default();
+                        self.send_parameters();
+                    }
+
+                    PacketCommand::SetParameters(command) => self.handle_set_parameters(command),
+
+                    PacketCommand::IccClock(command) => match command.clock_command() {
+                        Some(clock_command) => {
+                            self.slots[slot].clock_stopped = clock_command == ClockCommand::Stop;
+                            self.send_slot_status_ok();
+                        }
+                        // bClockCommand
+                        None => self.send_error(command[0], Error::BadParameter(7)),
+                    },
+
+                    // We only do T=1 (and APDU level exchanges), so the T=0 handling of
+                    // GET RESPONSE/ENVELOPE cannot be changed.
+                    PacketCommand::T0Apdu(command) => self.send_error(command[0], Error::CommandNotSupported),
+
+                    // dwMechanical = 0: no mechanical characteristics
+                    PacketCommand::Mechanical(command) => self.send_error(command[0], Error::CommandNotSupported),
+
+                    // bNumClockSupported = bNumDataRatesSupported = 0, so we answer with
+                    // the fixed values in effect, whatever was requested.
+                    PacketCommand::SetDataRateAndClockFrequency(command) => match command.requested() {
+                        Some(_) => self.send_data_rate_and_clock_frequency(),
+                        // dwLength
+                        None => self.send_error(command[0], Error::BadParameter(1)),
+                    },
+
+                    PacketCommand::Escape(command) => self.handle_escape(command),
                 }
             }
 
             Err(PacketError::ShortPacket) => {
-                panic!("short packet!");
+                // cannot happen, as we checked the length above
+                info!("short packet!");
             }
 
             Err(PacketError::UnknownCommand(_p)) => {
                 info!("unknown command {:X?}", &_p);
-                self.seq = self.ext_packet[6];
-                self.send_slot_status_error(Error::CommandNotSupported);
+                self.reject_ext_packet(Error::CommandNotSupported);
             }
         }
     }
 
-    #[inline(never)]
-    fn reset_interchange(&mut self) {
-        let message = Vec::new();
-        self.interchange.take_response();
-        // this may no longer be needed
-        // before the interchange change (adding the request_mut method),
-        // one necessary side-effect of this was to set the interchange's
-        // enum variant to Request.
-        self.interchange.request(&message).ok();
-        self.interchange.cancel().ok();
+    // Continuation of a message spanning several USB packets.  Unless the command
+    // was rejected already, its data is appended to the app request.
+    fn handle_long_packet(&mut self, packet: &[u8]) {
+        self.in_chain += 1;
+        if packet.len() > self.long_packet_missing {
+            info!("more data than announced");
+            self.abandon_long_packet();
+            return;
+        }
+        self.long_packet_missing -= packet.len();
+
+        if self.pending_block.is_some() && !self.long_packet_overflow {
+            let extended = match self.slots[self.slot].interchange.request_mut() {
+                Some(message) => message.extend_from_slice(packet).is_ok(),
+                None => false,
+            };
+            self.long_packet_overflow = !extended;
+        }
+
+        if self.long_packet_missing > 0 {
+            if packet.len() < self.packet_size {
+                // a short USB packet ends the transfer, so the data is missing
+                info!("transfer ended early, {} missing", self.long_packet_missing);
+                self.abandon_long_packet();
+            }
+            return;
+        }
+
+        // info!("pl {}, p {}, missing {}, in_chain {}", self.packet_len, packet.len(), self.long_packet_missing, self.in_chain).ok();
+        self.receiving_long = false;
+        if self.long_packet_overflow {
+            info!("message too long: {}", self.packet_len);
+            self.long_packet_overflow = false;
+            self.abandon_long_packet();
+        } else if let Some(chain) = self.pending_block.take() {
+            self.end_block(chain);
+        }
+    }
+
+    // The host sent an inconsistent or too long message, the next USB packet
+    // is parsed as a new one.  The command was answered already if it was rejected.
+    fn abandon_long_packet(&mut self) {
+        let answered = self.pending_block.is_none();
+        self.drop_long_packet();
+        self.resync();
+        if !answered {
+            self.reject_ext_packet(Error::BadParameter(1));
+        }
+    }
+
+    fn handle_escape(&mut self, command: EscapePacket) {
+        let requester = match self.escape.as_mut() {
+            Some(requester) => requester,
+            None => {
+                self.send_error(command[0], Error::CommandNotSupported);
+                return;
+            }
+        };
+
+        // drop stale responses, e.g. after an abort
+        requester.take_response();
+        let request = match escape::Request::from_slice(command.data()) {
+            Ok(request) => request,
+            Err(()) => {
+                // dwLength
+                self.send_error(command[0], Error::BadParameter(1));
+                return;
+            }
+        };
+        if requester.request(&request).is_err() {
+            self.send_error(command[0], Error::CmdSlotBusy);
+            return;
+        }
+        self.escape_seq = Some(self.seq);
+    }
+
+    fn poll_escape(&mut self) {
+        let seq = match self.escape_seq {
+            Some(seq) => seq,
+            None => return,
+        };
+        let response = match self.escape.as_mut().and_then(|requester| requester.take_response()) {
+            Some(response) => response,
+            None => return,
+        };
+        self.escape_seq = None;
+
+        let mut packet = self.reply_header(0x83, self.slot as u8, seq);
+        match response {
+            Ok(data) => {
+                packet[1..5].copy_from_slice(&(data.len() as u32).to_le_bytes());
+                // escape::MESSAGE_SIZE + 10 <= FULL_SPEED_PACKET_SIZE
+                packet.extend_from_slice(&data).ok();
+            }
+            Err(error) => {
+                info!("escape command failed: {:?}", error);
+                let error = match error {
+                    escape::Error::InvalidCommand => Error::CommandNotSupported,
+                    // dwLength
+                    escape::Error::InvalidLength => Error::BadParameter(1),
+                    escape::Error::NoResponse => Error::HwError,
+                };
+                packet[7] |= 1<<6;
+                packet[8] = error.code();
+            }
+        }
+        self.send_packet_assuming_possible(packet);
+    }
+
+    // Withdraws the request of the current slot from the app, so a late response
+    // is never taken as the answer to a later command.
+    fn cancel_request(&mut self) {
+        let slot = &mut self.slots[self.slot];
+        // the app may have answered already, before we polled it
+        if slot.interchange.take_response().is_none() {
+            match slot.interchange.state() {
+                interchange::State::Requested => {
+                    // not taken by the app yet
+                    slot.interchange.cancel().ok();
+                }
+                interchange::State::BuildingResponse => {
+                    // the dispatcher does not acknowledge cancellations,
+                    // so we let the app finish and drop its response
+                    slot.stale_response = true;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Drops the responses to aborted commands, freeing the interchanges for new commands.
+    fn drop_stale_responses(&mut self) {
+        for slot in self.slots.iter_mut().filter(|slot| slot.stale_response) {
+            if slot.interchange.take_response().is_some() {
+                info!("dropped the response to an aborted command");
+                slot.stale_response = false;
+            }
+        }
     }
 
     fn handle_transfer(&mut self, command: XfrBlock) {
@@ -259,128 +618,222 @@ where
 
         // info!("handle xfrblock").ok();
         // info!("{:X?}", &command);
+        let chain = match command.chain() {
+            Some(chain) => chain,
+            None => {
+                info!("invalid level parameter");
+                self.resync();
+                // wLevelParameter
+                self.send_error(command[0], Error::BadParameter(8));
+                return;
+            }
+        };
+
         match self.state {
 
             State::Idle => {
-                // invariant: BUFFER_SIZE >= PACKET_SIZE
-                match command.chain() {
+                // invariant: BUFFER_SIZE >= MAX_PACKET_SIZE
+                match chain {
                     Chain::BeginsAndEnds => {
                         info!("begins and ends");
-                        self.reset_interchange();
-                        let message = self.interchange.request_mut().unwrap();
-                        message.clear();
-                        message.extend_from_slice(command.data()).unwrap();
-                        self.call_app();
-                        self.state = State::Processing;
+                        if self.start_message(command.data()) {
+                            self.end_block(chain);
+                        }
                         // self.send_empty_datablock();
                     }
                     Chain::Begins => {
                         info!("begins");
-                        self.reset_interchange();
-                        let message = self.interchange.request_mut().unwrap();
-                        message.clear();
-                        message.extend_from_slice(command.data()).unwrap();
-                        self.state = State::Receiving;
-                        self.send_empty_datablock(Chain::ExpectingMore);
+                        if self.start_message(command.data()) {
+                            self.state = State::Receiving;
+                            self.end_block(chain);
+                        }
+                    }
+                    _ => {
+                        info!("unexpected {:?} in idle state", chain);
+                        self.send_error(command[0], Error::BadParameter(8));
                     }
-                    _ =>  panic!("unexpectedly in idle state"),
                 }
             }
 
             State::Receiving => {
-                match command.chain() {
+                match chain {
                     Chain::Continues => {
                         info!("continues");
-                        let message = self.interchange.request_mut().unwrap();
-                        assert!(command.data().len() + message.len() <= MAX_MSG_LENGTH);
-                        message.extend_from_slice(command.data()).unwrap();
-                        self.send_empty_datablock(Chain::ExpectingMore);
+                        if self.extend_message(command.data()) {
+                            self.end_block(chain);
+                        }
                     }
                     Chain::Ends => {
                         info!("ends");
-                        let message = self.interchange.request_mut().unwrap();
-                        assert!(command.data().len() + message.len() <= MAX_MSG_LENGTH);
-                        message.extend_from_slice(command.data()).unwrap();
-                        self.call_app();
-                        self.state = State::Processing;
+                        if self.extend_message(command.data()) {
+                            self.end_block(chain);
+                        }
+                    }
+                    _ => {
+                        info!("unexpected {:?} in receiving state", chain);
+                        self.resync();
+                        self.send_error(command[0], Error::BadParameter(8));
                     }
-                    _ =>  panic!("unexpectedly in receiving state"),
                 }
             }
 
-            State::Processing => {
-                // info!("handle xfrblock").ok();
-                // info!("{:X?}", &command).ok();
-                panic!("ccid pipe unexpectedly received command while in processing state: {:?}", &command);
-            }
-
-            State::ReadyToSend => {
-                panic!("unexpectedly in ready-to-send state")
+            State::Processing | State::ReadyToSend => {
+                // handled in `handle_packet` already
+                self.send_error(command[0], Error::CmdSlotBusy);
             }
 
             State::Sending => {
-                match command.chain() {
+                match chain {
                     Chain::ExpectingMore => {
                         self.prime_outbox();
                     }
-                    _ =>  panic!("unexpectedly in receiving state"),
+                    _ => {
+                        info!("unexpected {:?} in sending state", chain);
+                        self.resync();
+                        self.send_error(command[0], Error::BadParameter(8));
+                    }
                 }
             }
         }
     }
 
-    pub fn send_wait_extension(&mut self) -> bool {
+    /// Calls the app at the end of a command APDU, or asks the host for the next block.
+    /// Deferred until the rest of the data arrived if the block spans several USB packets.
+    fn end_block(&mut self, chain: Chain) {
+        if self.receiving_long {
+            self.pending_block = Some(chain);
+            return;
+        }
+        match chain {
+            Chain::BeginsAndEnds | Chain::Ends => self.call_app(),
+            _ => self.send_empty_datablock(Chain::ExpectingMore),
+        }
+    }
+
+    /// Starts a new request to the app, replying with an error if this fails.
+    fn start_message(&mut self, data: &[u8]) -> bool {
+        self.drop_stale_responses();
+        if self.slots[self.slot].stale_response {
+            // the app is still busy with an aborted command
+            info!("app did not finish the aborted command yet");
+            self.resync();
+            self.send_error(CommandType::XfrBlock as u8, Error::CmdSlotBusy);
+            return false;
+        }
+        let interchange = &mut self.slots[self.slot].interchange;
+        // a response the host did not fetch, e.g. after a resync
+        interchange.take_response();
+        if let Some(message) = interchange.request_mut() {
+            message.clear();
+        }
+        self.extend_message(data)
+    }
+
+    /// Appends to the current request to the app, replying with an error if this fails.
+    fn extend_message(&mut self, data: &[u8]) -> bool {
+        let extended = match self.slots[self.slot].interchange.request_mut() {
+            Some(message) => message.extend_from_slice(data).is_ok(),
+            None => false,
+        };
+        if !extended {
+            info!("could not extend message by {} bytes", data.len());
+            self.resync();
+            // dwLength
+            self.send_error(CommandType::XfrBlock as u8, Error::BadParameter(1));
+        }
+        extended
+    }
+
+    /// Returns the delay until the next time extension is due, if still processing.
+    pub fn send_wait_extension(&mut self) -> Option<u32> {
+        self.poll_hints();
+
         if self.state == State::Processing {
+            let (multiplier, delay) = self.time_extension.next();
+
+            // ICCD: the host polls us instead
+            if self.write.is_none() {
+                return Some(delay);
+            }
+
             // Need to send a wait extension request.
-            let mut packet = RawPacket::new();
-            packet.resize_default(10).ok();
-            packet[0] = 0x80;
-            packet[6] = self.seq;
+            let mut packet = self.reply_header(0x80, self.slot as u8, self.seq);
 
             // CCID_Rev110 6.2-3: Time Extension is requested
-            packet[7] = 2 << 6;
-            // Perhaps 1 is an ok multiplier?
-            packet[8] = 0x1;
+            packet[7] |= 2 << 6;
+            // bError: the BWT multiplier
+            packet[8] = multiplier;
             self.send_packet_assuming_possible(packet);
 
             // Indicate we should check back again for another possible wait extension
-            true
+            Some(delay)
         } else {
             // No longer processing, so the reply has been sent, and we no longer need more time.
-            false
+            None
         }
     }
 
-    /// Turns false on read.  Intended for checking to see if a wait extension request needs to be started.
-    pub fn did_started_processing(&mut self) -> bool {
+    /// Turns `None` on read.  Intended for checking to see if a wait extension request needs to be started,
+    /// returns the delay until the first one is due.
+    pub fn did_started_processing(&mut self) -> Option<u32> {
         if self.started_processing {
             self.started_processing = false;
-            true
+            let waiting_integers = self.slots[self.slot].parameters.waiting_integers;
+            Some(self.time_extension.start(waiting_integers))
         } else {
-            false
+            None
+        }
+    }
+
+    // Takes the app's hint on the duration of the current command, if any.
+    fn poll_hints(&mut self) {
+        if let Some(hints) = self.hints.as_mut() {
+            if let Some(hint) = hints.take_request() {
+                hints.respond(&()).ok();
+                if self.state == State::Processing {
+                    self.time_extension.hint(hint);
+                }
+            }
         }
     }
 
     #[inline(never)]
     fn call_app(&mut self) {
-        self.interchange.send_request().expect("could not deposit command");
+        if self.slots[self.slot].interchange.send_request().is_err() {
+            info!("could not deposit command");
+            self.resync();
+            self.send_error(CommandType::XfrBlock as u8, Error::CmdSlotBusy);
+            return;
+        }
+        // a hint not yet seen belongs to an earlier command
+        if let Some(hints) = self.hints.as_mut() {
+            if hints.take_request().is_some() {
+                hints.respond(&()).ok();
+            }
+        }
         self.started_processing = true;
         self.state = State::Processing;
     }
 
     #[inline(never)]
     pub fn poll_app(&mut self) {
+        self.poll_escape();
+        self.drop_stale_responses();
+
         if let State::Processing = self.state {
             // info!("processing, checking for response, interchange state {:?}",
             //           self.interchange.state()).ok();
 
-            if interchange::State::Responded == self.interchange.state() {
+            if self.slots[self.slot].interchange.state() == interchange::State::Responded {
 
                 // we should have an open XfrBlock allowance
                 self.state = State::ReadyToSend;
                 self.sent = 0;
                 self.prime_outbox();
             }
+        } else if let State::ReadyToSend = self.state {
+            // the outbox was busy when the response arrived
+            self.prime_outbox();
         }
     }
 
@@ -389,104 +842,166 @@ where
             return;
         }
 
-        if self.outbox.is_some() { panic!(); }
-
-        // if let Some(message) = self.interchange.response() {
-            let message: &mut Vec<u8, N> = unsafe { (&mut *self.interchange.interchange.get()).rp_mut() };
-
-            let chunk_size = core::cmp::min(PACKET_SIZE - 10, message.len() - self.sent);
-            let chunk = &message[self.sent..][..chunk_size];
-            self.sent += chunk_size;
-            let more = self.sent < message.len();
-
-            let chain = match (self.state, more) {
-                (State::ReadyToSend, true) => { self.state = State::Sending; Chain::Begins }
-                (State::ReadyToSend, false) => { self.state = State::Idle; Chain::BeginsAndEnds }
-                (State::Sending, true) => Chain::Continues,
-                (State::Sending, false) => { self.state = State::Idle; Chain::Ends }
-                // logically impossible
-                _ => { return; }
-            };
-
-            let primed_packet = DataBlock::new(self.seq, chain, chunk);
-            // info!("priming {:?}", &primed_packet).ok();
-            self.outbox = Some(primed_packet.into());
+        // will be primed again once the outbox is free
+        if self.outbox.is_some() { return; }
+
+        // ICCD: the block needs to fit the data stage of DATA_BLOCK
+        let max_chunk_size = match self.write {
+            Some(_) => self.packet_size - 10,
+            None => core::cmp::min(self.packet_size, CONTROL_BUFFER_SIZE) - 10,
+        };
+        let slot = self.slot;
+        let response = match self.slots[slot].interchange.response() {
+            Ok(response) => response,
+            Err(_) => {
+                info!("response withdrawn");
+                self.resync();
+                return;
+            }
+        };
+        let chunk_size = core::cmp::min(max_chunk_size, response.len() - self.sent);
+        let chunk = &response[self.sent..][..chunk_size];
+        self.sent += chunk_size;
+        let more = self.sent < response.len();
+
+        let chain = match (self.state, more) {
+            (State::ReadyToSend, true) => { self.state = State::Sending; Chain::Begins }
+            (State::ReadyToSend, false) => { self.state = State::Idle; Chain::BeginsAndEnds }
+            (State::Sending, true) => Chain::Continues,
+            (State::Sending, false) => { self.state = State::Idle; Chain::Ends }
+            // logically impossible
+            _ => { return; }
+        };
+
+        let primed_packet = DataBlock::new(slot as u8, self.seq, chain, chunk);
+        // info!("priming {:?}", &primed_packet).ok();
+        self.outbox = Some(primed_packet.into());
+        if !more {
+            // frees the interchange for the next command
+            self.slots[slot].interchange.take_response();
+        }
 
-            // fast-lane response attempt
-            self.maybe_send_packet();
-        // }
+        // fast-lane response attempt
+        self.maybe_send_packet();
     }
 
     fn send_empty_datablock(&mut self, chain: Chain) {
-        let packet = DataBlock::new(self.seq, chain, &[]).into();
+        let packet = DataBlock::new(self.slot as u8, self.seq, chain, &[]).into();
         self.send_packet_assuming_possible(packet);
     }
 
-    fn send_slot_status_ok(&mut self) {
+    /// Response header in the given slot, with bmICCStatus filled in.
+    fn reply_header(&self, message_type: u8, slot: u8, seq: u8) -> RawPacket {
         let mut packet = RawPacket::new();
         packet.resize_default(10).ok();
-        packet[0] = 0x81;
-        packet[6] = self.seq;
+        packet[0] = message_type;
+        packet[5] = slot;
+        packet[6] = seq;
+        packet[7] = self.icc_status(usize::from(slot));
+        packet
+    }
+
+    fn slot_status(&self, slot: u8, seq: u8) -> RawPacket {
+        let mut packet = self.reply_header(0x81, slot, seq);
+        // bClockStatus: 0 = running, 1 = stopped in state L
+        packet[9] = self.slots.get(usize::from(slot)).map_or(false, |slot| slot.clock_stopped) as u8;
+        packet
+    }
+
+    fn send_slot_status_ok(&mut self) {
+        let packet = self.slot_status(self.slot as u8, self.seq);
         self.send_packet_assuming_possible(packet);
     }
 
-    fn send_slot_status_error(&mut self, error: Error) {
-        let mut packet = RawPacket::new();
-        packet.resize_default(10).ok();
-        packet[0] = 0x6c;
-        packet[6] = self.seq;
-        packet[7] = 1<<6;
-        packet[8] = error as u8;
+    /// Replies to the command with given message type with an error,
+    /// using the response message type the host expects (cf. Sec. 6.2).
+    fn send_error(&mut self, command_type: u8, error: Error) {
+        self.reject(command_type, self.slot as u8, self.seq, error);
+    }
+
+    /// Like `send_error`, for commands that do not become the current command,
+    /// so the slot and sequence number of a transfer in progress are kept.
+    fn reject(&mut self, command_type: u8, slot: u8, seq: u8, error: Error) {
+        let message_type = match command_type {
+            // PowerOn, Secure, XfrBlock
+            0x62 | 0x69 | 0x6f => 0x80,
+            // SetParameters, GetParameters, ResetParameters
+            0x61 | 0x6c | 0x6d => 0x82,
+            // Escape
+            0x6b => 0x83,
+            // SetDataRateAndClockFrequency
+            0x73 => 0x84,
+            // everything else, including unknown commands
+            _ => 0x81,
+        };
+        let mut packet = match message_type {
+            0x81 => self.slot_status(slot, seq),
+            _ => self.reply_header(message_type, slot, seq),
+        };
+        packet[7] |= 1<<6;
+        packet[8] = error.code();
+        if message_type == 0x82 {
+            // bProtocolNum
+            packet[9] = 1;
+        }
         self.send_packet_assuming_possible(packet);
     }
 
+    fn reject_ext_packet(&mut self, error: Error) {
+        let (command_type, slot, seq) = (self.ext_packet[0], self.ext_packet[5], self.ext_packet[6]);
+        self.reject(command_type, slot, seq, error);
+    }
+
+    fn handle_set_parameters(&mut self, command: SetParameters) {
+        match command.t1_parameters() {
+            Ok(parameters) => {
+                self.slots[self.slot].parameters = parameters;
+                self.send_parameters();
+            }
+            Err(offset) => {
+                // parameters in effect are left unchanged
+                self.send_error(command[0], Error::BadParameter(offset));
+            }
+        }
+    }
+
     fn send_parameters(&mut self) {
-        let mut packet = RawPacket::new();
-        packet.resize_default(17).ok();
-        packet[0] = 0x82;
-        packet[1] = 7;
-        packet[6] = self.seq;
+        let mut packet = self.reply_header(0x82, self.slot as u8, self.seq);
+        packet[1] = T1Parameters::LEN as u8;
         packet[9] = 1; // T=1
+        packet.extend_from_slice(&self.slots[self.slot].parameters.to_bytes()).ok();
+        self.send_packet_assuming_possible(packet);
+    }
 
-        // just picking the fastest values.
-        //              Fi = 1Mz    Di=1
-        packet[10] = (0b0001 << 4) | (0b0001);
-
-        // just taking default value from spec.
-        packet[11] = 0x10;
-        // not sure, taking default.
-        packet[13] = 0x15;
-        // set max waiting time
-        packet[15] = 0xfe;
+    fn send_data_rate_and_clock_frequency(&mut self) {
+        let mut packet = self.reply_header(0x84, self.slot as u8, self.seq);
+        packet[1] = 8;
+        packet.extend_from_slice(&CLOCK_FREQUENCY_KHZ).ok();
+        packet.extend_from_slice(&DATA_RATE_BPS).ok();
         self.send_packet_assuming_possible(packet);
     }
 
     fn send_atr(&mut self) {
-        let atr = self.atr.clone();
+        let atr = self.slots[self.slot].atr.clone();
         let packet = DataBlock::new(
+            self.slot as u8,
             self.seq,
             Chain::BeginsAndEnds,
+            // see `Atr` for the card capabilities etc. that may be signalled
             &atr,
-
-            // T=0, T=1, command chaining/extended Lc+Le/no logical channels, card issuer's data "Solo 2"
-            // 3B 8C 80 01 80 73 C0 21 C0 56 53 6F 6C 6F 20 32 A4
-            // https://smartcard-atr.apdu.fr/parse?ATR=3B+8C+80+01+80+73+C0+21+C0+56+53+6F+6C+6F+20+32+A4
-            // &[0x3B, 0x8C, 0x80, 0x01, 0x80, 0x73, 0xC0, 0x21, 0xC0, 0x56, 0x53, 0x6F, 0x6C, 0x6F, 0x20, 0x32, 0xA4]
-            //
-            // Not sure if we also need some TA/TB/TC data as in
-            // https://smartcard-atr.apdu.fr/parse?ATR=3B+F8+13+00+00+81+31+FE+15+59+75+62+69+6B+65+79+34+D4
-            // At least TB(1) is deprecated, so it makes no sense
-            // Also, there TD(1) = 0x81 and TD(2) = 0x31 both refer to protocol T=1 which seems wrong
         );
         self.send_packet_assuming_possible(packet.into());
     }
 
 
     fn send_packet_assuming_possible(&mut self, packet: RawPacket) {
-        if !self.outbox.is_none() {
-            // Previous transaction will fail, but we'll be ready for new transactions.
-            self.state = State::Idle;
-            info!("overwriting last session..");
+        if self.outbox.is_some() {
+            info!("overwriting last packet..");
+            // e.g. an earlier time extension is superseded, but a partially
+            // sent response can't be completed
+            if self.state == State::Sending {
+                self.resync();
+            }
         }
         self.outbox = Some(packet);
 
@@ -496,9 +1011,14 @@ where
 
     #[inline(never)]
     pub fn maybe_send_packet(&mut self) {
+        // ICCD: the host fetches the response with DATA_BLOCK
+        let write = match self.write.as_ref() {
+            Some(write) => write,
+            None => return,
+        };
         if let Some(packet) = self.outbox.as_ref() {
-            let needs_zlp = packet.len() == PACKET_SIZE;
-            match self.write.write(packet) {
+            let needs_zlp = packet.len() == self.packet_size;
+            match write.write(packet) {
                 Ok(n) if n == packet.len() => {
                     // if packet.len() > 8 {
                     //     info!("--> sent {:?}... successfully", &packet[..8]).ok();
@@ -513,7 +1033,12 @@ where
                     }
 
                 }
-                Ok(_) => panic!("short write"),
+                Ok(_) => {
+                    // the host will not get a complete message, so give up on it
+                    info!("short write");
+                    self.outbox = None;
+                    self.resync();
+                }
 
                 Err(UsbError::WouldBlock) => {
                     // fine, can't write try later
@@ -521,7 +1046,11 @@ where
                     info!("waiting to send");
                 },
 
-                Err(_) => panic!("unexpected send error"),
+                Err(_error) => {
+                    info!("unexpected send error: {:?}", _error);
+                    self.outbox = None;
+                    self.resync();
+                }
             }
         }
     }
@@ -536,33 +1065,68 @@ where
 
     // Called if we receive an ABORT request on the control pipe.
     pub fn expect_abort(&mut self, slot: u8, seq: u8) {
-        debug_assert!(slot == 0);
-        info!("ABORT expected for seq = {}", seq);
-        // We only have one slot (see FUNCTIONAL_INTERFACE_DESCRIPTOR in constants.rs)
-        if slot != 0 {
-            return;
-        }
-        if self.bulk_abort == Some(seq) {
-            self.abort();
+        info!("ABORT expected for slot = {}, seq = {}", slot, seq);
+        let slot = usize::from(slot);
+        let bulk_abort = match self.slots.get(slot) {
+            Some(current) => current.bulk_abort,
+            None => return,
+        };
+        if bulk_abort == Some(seq) {
+            self.abort(slot, seq);
         } else {
-            self.control_abort = Some(seq);
+            self.slots[slot].control_abort = Some(seq);
         }
     }
 
-    // This method performs an abort and should only be called if we received matching ABORT
-    // requets both from the control pipe and from the bulk endpoint.
-    fn abort(&mut self) {
-        // reset state
-        self.bulk_abort = None;
-        self.control_abort = None;
+    // Called if we receive an ABORT command on the bulk endpoint.
+    fn handle_bulk_abort(&mut self, slot: usize, seq: u8) {
+        match self.slots[slot].control_abort {
+            Some(control_abort) if control_abort == seq => self.abort(slot, seq),
+            Some(_) => self.reject(CommandType::Abort as u8, slot as u8, seq, Error::CmdAborted),
+            None => self.slots[slot].bulk_abort = Some(seq),
+        }
+    }
+
+    // Drops any partial transfer, so the next XfrBlock starts a new command APDU.
+    fn resync(&mut self) {
         self.state = State::Idle;
-        self.outbox = None;
-        self.started_processing = false;
+        self.pending_block = None;
+        self.sent = 0;
+    }
+
+    // Drops the rest of a message spanning several USB packets,
+    // so the next USB packet is parsed as a new message.
+    fn drop_long_packet(&mut self) {
         self.receiving_long = false;
         self.long_packet_missing = 0;
+        self.long_packet_overflow = false;
+    }
+
+    // This method performs an abort and should only be called if we received matching ABORT
+    // requets both from the control pipe and from the bulk endpoint.
+    fn abort(&mut self, slot: usize, seq: u8) {
+        // reset state
+        self.slots[slot].bulk_abort = None;
+        self.slots[slot].control_abort = None;
+        // the transfer in progress, if any, belongs to another slot otherwise
+        if slot == self.slot {
+            if self.state != State::Idle {
+                self.cancel_request();
+            }
+            if self.escape_seq.take().is_some() {
+                if let Some(requester) = self.escape.as_mut() {
+                    requester.cancel().ok();
+                }
+            }
+            self.outbox = None;
+            self.started_processing = false;
+            self.drop_long_packet();
+            self.resync();
+        }
 
         // send response for successful abort
-        self.send_slot_status_ok();
+        let packet = self.slot_status(slot as u8, seq);
+        self.send_packet_assuming_possible(packet);
     }
 
 }
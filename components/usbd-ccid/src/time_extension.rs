@@ -0,0 +1,144 @@
+//! Time extension requests, cf. CCID Rev1.10, Sec. 6.2.6 (bmCommandStatus = 2).
+//!
+//! While an app is processing, the host expects a response, or a time extension
+//! request, within the block waiting time (BWT) of the parameters in effect.
+//! A time extension with multiplier `m` grants `m * BWT` until the next one.
+//! By default, a time extension with multiplier 1 is sent every second; the
+//! `Policy::adaptive` one follows the BWT instead, and escalates the multiplier
+//! for long operations such as RSA key generation.  Apps may hint the expected
+//! duration via `Hinter`.
+
+use interchange::Requester;
+
+use crate::constants::CLOCK_FREQUENCY_KHZ;
+
+/// Expected duration of the command being processed, in milliseconds.
+pub type Hint = u32;
+
+interchange::interchange! {
+    HintInterchange: (Hint, ())
+}
+
+/// BWT in milliseconds for the bWaitingIntegersT1 of the parameters in effect,
+/// cf. ISO 7816-3, Sec. 11.4.3: BWT = 11 etu + 2^BWI * 960 * 372 / f.
+pub fn block_waiting_time(waiting_integers: u8) -> u32 {
+    // BWI > 9 is reserved
+    let bwi = core::cmp::min(waiting_integers >> 4, 9);
+    let clock_khz = u32::from_le_bytes(CLOCK_FREQUENCY_KHZ);
+    // the 11 etu are covered by rounding up
+    ((960 * 372) << bwi) / clock_khz + 1
+}
+
+/// When to request time extensions, and with which BWT multiplier.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Policy {
+    /// Milliseconds until the first time extension, and between time extensions
+    /// with multiplier 1; `None` for half the BWT.
+    pub interval: Option<u32>,
+    /// Multiplier of the first time extensions.
+    pub initial_multiplier: u8,
+    /// The multiplier doubles after this many time extensions (0 = never).
+    pub escalate_after: u8,
+    /// Upper bound of the multiplier.
+    pub max_multiplier: u8,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            interval: Some(1000),
+            initial_multiplier: 1,
+            escalate_after: 0,
+            max_multiplier: 0xff,
+        }
+    }
+}
+
+impl Policy {
+    /// Time extensions halfway through the BWT, doubling the multiplier every
+    /// four time extensions.
+    pub fn adaptive() -> Self {
+        Self {
+            interval: None,
+            escalate_after: 4,
+            ..Default::default()
+        }
+    }
+}
+
+/// Tracks the time extensions of the command being processed.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TimeExtension {
+    pub(crate) policy: Policy,
+    bwt: u32,
+    // delay per unit of the multiplier
+    interval: u32,
+    count: u8,
+    // time since processing started, assuming the extensions are sent when due
+    elapsed: u32,
+    expected: Option<Hint>,
+}
+
+impl TimeExtension {
+    /// Returns the delay until the first time extension is due.
+    pub(crate) fn start(&mut self, waiting_integers: u8) -> u32 {
+        self.bwt = block_waiting_time(waiting_integers);
+        // halfway through, to stay ahead of the host's timeout
+        let interval = self.policy.interval.unwrap_or(self.bwt / 2);
+        self.interval = core::cmp::max(interval, 1);
+        self.count = 0;
+        self.expected = None;
+        self.elapsed = self.interval;
+        self.interval
+    }
+
+    pub(crate) fn hint(&mut self, expected: Hint) {
+        info!("expecting processing to take {} ms", expected);
+        self.expected = Some(expected);
+    }
+
+    /// Returns the multiplier of the time extension due now, and the delay until the next one.
+    pub(crate) fn next(&mut self) -> (u8, u32) {
+        let mut multiplier = u32::from(self.policy.initial_multiplier);
+        if self.policy.escalate_after > 0 {
+            let doublings = core::cmp::min(self.count / self.policy.escalate_after, 8);
+            multiplier <<= doublings;
+        }
+        let mut interval = self.interval;
+        if let Some(expected) = self.expected {
+            // ask for the remaining time at once, the next one is due halfway through it
+            let remaining = expected.saturating_sub(self.elapsed);
+            let hinted = (remaining + self.bwt - 1) / self.bwt;
+            if hinted > multiplier {
+                multiplier = hinted;
+                interval = self.bwt / 2;
+            }
+        }
+        let multiplier = core::cmp::max(core::cmp::min(multiplier, u32::from(self.policy.max_multiplier)), 1);
+        let delay = core::cmp::max(multiplier * interval, 1);
+
+        self.count = self.count.saturating_add(1);
+        self.elapsed = self.elapsed.saturating_add(delay);
+        (multiplier as u8, delay)
+    }
+}
+
+/// App side of the hint channel, see `Ccid::with_time_extension_hints`.
+pub struct Hinter {
+    requester: Requester<HintInterchange>,
+}
+
+impl Hinter {
+    pub fn new(requester: Requester<HintInterchange>) -> Self {
+        Self { requester }
+    }
+
+    /// The command being processed is expected to take this long, e.g. RSA key generation.
+    ///
+    /// Applies to the current command only, and replaces an earlier hint not yet seen.
+    pub fn expect(&mut self, milliseconds: Hint) {
+        self.requester.take_response();
+        self.requester.cancel().ok();
+        self.requester.request(&milliseconds).ok();
+    }
+}
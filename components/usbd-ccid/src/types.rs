@@ -13,6 +13,34 @@ pub enum ClassRequest {
     GetDataRates = 3,
 }
 
+/// ICCD class requests, cf. Sec. 6.1 (Version A) and 6.2 (Version B) of the ICCD spec.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IccdRequest {
+    IccPowerOn = 0x62,
+    IccPowerOff = 0x63,
+    XfrBlock = 0x65,
+    DataBlock = 0x6f,
+    // Version A only
+    GetIccStatus = 0xa0,
+    // Version B only
+    SlotStatus = 0x81,
+}
+
+impl core::convert::TryFrom<u8> for IccdRequest {
+    type Error = ();
+    fn try_from(request: u8) -> core::result::Result<Self, ()> {
+        Ok(match request {
+            0x62 => Self::IccPowerOn,
+            0x63 => Self::IccPowerOff,
+            0x65 => Self::XfrBlock,
+            0x6f => Self::DataBlock,
+            0xa0 => Self::GetIccStatus,
+            0x81 => Self::SlotStatus,
+            _ => return Err(()),
+        })
+    }
+}
+
 pub enum Status {
     Idle,
     ReceivedData(Milliseconds),
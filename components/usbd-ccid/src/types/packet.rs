@@ -3,8 +3,10 @@ use core::convert::TryInto;
 use crate::constants::*;
 
 
-pub type RawPacket = heapless::Vec<u8, PACKET_SIZE>;
-pub type ExtPacket = heapless::Vec<u8, MAX_MSG_LENGTH>;
+pub type RawPacket = heapless::Vec<u8, MAX_PACKET_SIZE>;
+// A command message, or its first USB packet if it spans several: the pipe
+// appends the remaining data of an XfrBlock directly to the app request.
+pub type ExtPacket = heapless::Vec<u8, MAX_PACKET_SIZE>;
 
 pub trait RawPacketExt {
     fn packet_len(&self) -> usize;
@@ -30,8 +32,7 @@ pub trait Packet: core::ops::Deref<Target = ExtPacket> {
 
     #[inline]
     fn slot(&self) -> u8 {
-        // we have only one slot
-        assert!(self[5] == 0);
+        // NB: may exceed the configured slots, the pipe rejects such messages
         *&self[5]
     }
 
@@ -47,7 +48,8 @@ pub trait PacketWithData: Packet {
         // let len = u32::from_le_bytes(self[1..5].try_into().unwrap()) as usize;
         let declared_len =
             u32::from_le_bytes(self[1..5].try_into().unwrap()) as usize;
-        let len = core::cmp::min(MAX_MSG_LENGTH - 10, declared_len);
+        // never trust the host
+        let len = core::cmp::min(self.len() - 10, declared_len);
         // hprintln!("delcared = {}, len = {}", declared_len, len).ok();
         &self[10..][..len]
     }
@@ -55,32 +57,34 @@ pub trait PacketWithData: Packet {
 
 pub trait ChainedPacket: Packet {
 
+    /// Returns `None` for invalid level parameters.
     #[inline(always)]
-    fn chain(&self) -> Chain {
+    fn chain(&self) -> Option<Chain> {
         let level_parameter = u16::from_le_bytes(self[8..10].try_into().unwrap());
-        match level_parameter {
+        Some(match level_parameter {
             0 => Chain::BeginsAndEnds,
             1 => Chain::Begins,
             2 => Chain::Ends,
             3 => Chain::Continues,
             0x10 => Chain::ExpectingMore,
-            _ => panic!("invalid power select parameter"),
-        }
+            _ => return None,
+        })
     }
 }
 
 impl ChainedPacket for XfrBlock {}
 
 pub struct DataBlock<'a> {
+    slot: u8,
     seq: u8,
     chain: Chain,
     data: &'a [u8],
 }
 
 impl<'a> DataBlock<'a> {
-    pub fn new(seq: u8, chain: Chain, data: &'a [u8]) -> Self {
-        assert!(data.len() + 10 <= PACKET_SIZE);
-        Self { seq, chain, data }
+    pub fn new(slot: u8, seq: u8, chain: Chain, data: &'a [u8]) -> Self {
+        assert!(data.len() + 10 <= MAX_PACKET_SIZE);
+        Self { slot, seq, chain, data }
     }
 }
 
@@ -90,6 +94,7 @@ impl core::fmt::Debug for DataBlock<'_> {
         let mut debug_struct = f.debug_struct("DataBlock");
 
         debug_struct
+            .field("slot", &self.slot)
             .field("seq", &self.seq)
         ;
 
@@ -125,7 +130,7 @@ impl Into<RawPacket> for DataBlock<'_> {
         packet.resize_default(10 + len).ok();
         packet[0] = 0x80;
         packet[1..][..4].copy_from_slice(&len.to_le_bytes());
-        packet[5] = 0;
+        packet[5] = self.slot;
         packet[6] = self.seq;
 
         // status
@@ -140,6 +145,69 @@ impl Into<RawPacket> for DataBlock<'_> {
     }
 }
 
+pub type InterruptPacket = heapless::Vec<u8, INTERRUPT_PACKET_SIZE>;
+
+/// RDR_to_PC_NotifySlotChange, cf. Sec. 6.3.1
+///
+/// Up to four slots fit in the single bmSlotICCState byte we send.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NotifySlotChange {
+    slot_icc_state: u8,
+}
+
+impl NotifySlotChange {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_slot(mut self, slot: usize, present: bool, changed: bool) -> Self {
+        assert!(slot < MAX_SLOTS);
+        // bmSlotICCState: bit 2n = ICC present in slot n, bit 2n + 1 = change
+        self.slot_icc_state |= ((present as u8) | ((changed as u8) << 1)) << (2 * slot);
+        self
+    }
+}
+
+impl Into<InterruptPacket> for NotifySlotChange {
+    fn into(self) -> InterruptPacket {
+        let mut packet = InterruptPacket::new();
+        packet.push(0x50).ok();
+        packet.push(self.slot_icc_state).ok();
+        packet
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum HardwareErrorCode {
+    Overcurrent = 0x01,
+}
+
+/// RDR_to_PC_HardwareError, cf. Sec. 6.3.2
+#[derive(Copy, Clone, Debug)]
+pub struct HardwareError {
+    slot: u8,
+    seq: u8,
+    code: HardwareErrorCode,
+}
+
+impl HardwareError {
+    pub fn new(slot: u8, seq: u8, code: HardwareErrorCode) -> Self {
+        Self { slot, seq, code }
+    }
+}
+
+impl Into<InterruptPacket> for HardwareError {
+    fn into(self) -> InterruptPacket {
+        let mut packet = InterruptPacket::new();
+        packet.push(0x51).ok();
+        packet.push(self.slot).ok();
+        packet.push(self.seq).ok();
+        packet.push(self.code as u8).ok();
+        packet
+    }
+}
+
 #[repr(u8)]
 #[derive(Copy, Clone, Debug)]
 pub enum CommandType {
@@ -153,16 +221,16 @@ pub enum CommandType {
     GetParameters = 0x6c,
     XfrBlock = 0x6f,
     Abort = 0x72,
-
-    // unsupported
     ResetParameters = 0x6d,
     SetParameters = 0x61,
-    Escape = 0x6b,//  for vendor commands
-    IccClock = 0x7e,
+    IccClock = 0x6e,
     T0Apdu = 0x6a,
-    Secure = 0x69,
     Mechanical = 0x71,
     SetDataRateAndClockFrequency = 0x73,
+    Escape = 0x6b,//  for vendor commands
+
+    // unsupported
+    Secure = 0x69,
 }
 
 macro_rules! command_message {
@@ -202,6 +270,14 @@ macro_rules! command_message {
         }
 
         impl Command {
+            pub fn slot(&self) -> u8 {
+                match self {
+                    $(
+                        Command::$Name(packet) => packet.slot(),
+                    )*
+                }
+            }
+
             pub fn seq(&self) -> u8 {
                 match self {
                     $(
@@ -276,9 +352,17 @@ command_message!(
     GetParameters: 0x6c,
     XfrBlock: 0x6f,
     Abort: 0x72,
+    ResetParameters: 0x6d,
+    SetParameters: 0x61,
+    IccClock: 0x6e,
+    T0Apdu: 0x6a,
+    Mechanical: 0x71,
+    SetDataRateAndClockFrequency: 0x73,
+    Escape: 0x6b,
 );
 
 impl PacketWithData for XfrBlock {}
+impl PacketWithData for Escape {}
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum PowerSelection {
@@ -289,15 +373,140 @@ pub enum PowerSelection {
 }
 
 impl PowerOn {
+    /// Returns `None` for invalid power select parameters.
     #[inline(always)]
-    pub fn power_select(&self) -> PowerSelection {
-        match &self[7] {
+    pub fn power_select(&self) -> Option<PowerSelection> {
+        Some(match &self[7] {
             0 => PowerSelection::Automatic,
             1 => PowerSelection::V5,
             2 => PowerSelection::V3_3,
             3 => PowerSelection::V1_8,
-            _ => panic!("invalid power select parameter"),
+            _ => return None,
+        })
+    }
+}
+
+/// Protocol Data Structure for T=1, cf. Table 6.1-10.
+/// We are a virtual card, so these have no effect beyond being reported back.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct T1Parameters {
+    pub findex_dindex: u8,
+    pub tcck: u8,
+    pub guard_time: u8,
+    pub waiting_integers: u8,
+    pub clock_stop: u8,
+    pub ifsc: u8,
+    pub nad: u8,
+}
+
+impl Default for T1Parameters {
+    fn default() -> Self {
+        Self {
+            // just picking the fastest values.
+            //              Fi = 1Mz    Di=1
+            findex_dindex: (0b0001 << 4) | (0b0001),
+            // just taking default value from spec.
+            tcck: 0x10,
+            guard_time: 0x00,
+            // not sure, taking default.
+            waiting_integers: 0x15,
+            // stopping the clock is not allowed
+            clock_stop: 0x00,
+            // set max IFSC
+            ifsc: 0xfe,
+            nad: 0x00,
+        }
+    }
+}
+
+impl T1Parameters {
+    pub const LEN: usize = 7;
+
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        [
+            self.findex_dindex,
+            self.tcck,
+            self.guard_time,
+            self.waiting_integers,
+            self.clock_stop,
+            self.ifsc,
+            self.nad,
+        ]
+    }
+}
+
+impl SetParameters {
+    #[inline(always)]
+    pub fn protocol(&self) -> u8 {
+        self[7]
+    }
+
+    /// Returns the T=1 parameters, or the offset of the first invalid field.
+    pub fn t1_parameters(&self) -> core::result::Result<T1Parameters, u8> {
+        if self.protocol() != 1 {
+            // bProtocolNum: we only do T=1
+            return Err(7);
+        }
+        let declared_len = u32::from_le_bytes(self[1..5].try_into().unwrap()) as usize;
+        if declared_len != T1Parameters::LEN || self.len() < 10 + T1Parameters::LEN {
+            // dwLength
+            return Err(1);
+        }
+        let data = &self[10..][..T1Parameters::LEN];
+        // bmTCCKST1: 0x10 | checksum type (LRC/CRC) | convention (direct/inverse)
+        if data[1] & !0x03 != 0x10 {
+            return Err(11);
+        }
+        // bClockStop: we don't allow clock stop, but any value is fine to ask for
+        if data[4] > 3 {
+            return Err(14);
+        }
+        // bIFSC: 0x00 and 0xff are RFU
+        if data[5] == 0x00 || data[5] == 0xff {
+            return Err(15);
+        }
+        Ok(T1Parameters {
+            findex_dindex: data[0],
+            tcck: data[1],
+            guard_time: data[2],
+            waiting_integers: data[3],
+            clock_stop: data[4],
+            ifsc: data[5],
+            nad: data[6],
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ClockCommand {
+    Restart,
+    Stop,
+}
+
+impl IccClock {
+    /// Returns `None` for RFU values of bClockCommand.
+    #[inline(always)]
+    pub fn clock_command(&self) -> Option<ClockCommand> {
+        match self[7] {
+            0 => Some(ClockCommand::Restart),
+            1 => Some(ClockCommand::Stop),
+            _ => None,
+        }
+    }
+}
+
+impl SetDataRateAndClockFrequency {
+    /// dwClockFrequency and dwDataRate requested by the host (little-endian),
+    /// if the message is well-formed.
+    pub fn requested(&self) -> Option<([u8; 4], [u8; 4])> {
+        let declared_len = u32::from_le_bytes(self[1..5].try_into().unwrap()) as usize;
+        if declared_len != 8 || self.len() < 18 {
+            return None;
         }
+        Some((
+            self[10..14].try_into().unwrap(),
+            self[14..18].try_into().unwrap(),
+        ))
     }
 }
 
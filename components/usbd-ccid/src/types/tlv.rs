@@ -0,0 +1,17 @@
+//! COMPACT-TLV data objects, cf. ISO 7816-4, Sec. 5.2.2.
+//!
+//! Used in the historical bytes of the ATR: the tag is the high nibble
+//! and the length the low nibble of the first byte.
+
+use heapless::Vec;
+
+pub const MAX_COMPACT_LENGTH: usize = 15;
+
+/// Appends the data object, fails if the value is too long or the buffer is full.
+pub fn push_compact<const N: usize>(buffer: &mut Vec<u8, N>, tag: u8, value: &[u8]) -> Result<(), ()> {
+    if tag > 0xf || value.len() > MAX_COMPACT_LENGTH || buffer.len() + 1 + value.len() > N {
+        return Err(());
+    }
+    buffer.push((tag << 4) | value.len() as u8).ok();
+    buffer.extend_from_slice(value)
+}
@@ -0,0 +1,65 @@
+use usbd_ccid::atr::{Atr, InterfaceBytes, MAX_HISTORICAL_BYTES};
+
+#[test]
+fn tck_only_beyond_t0() {
+    // only T=0, implied or indicated
+    assert_eq!(&Atr::new().to_bytes(), &[0x3B, 0x00]);
+    assert_eq!(&Atr::new().with_protocol(0).to_bytes(), &[0x3B, 0x80, 0x00]);
+
+    // T=1: xor of T0 and TD1
+    assert_eq!(&Atr::new().with_protocol(1).to_bytes(), &[0x3B, 0x80, 0x01, 0x81]);
+    // T=0 and T=1
+    assert_eq!(&Atr::new().with_protocol(0).with_protocol(1).to_bytes(), &[0x3B, 0x80, 0x80, 0x01, 0x01]);
+}
+
+#[test]
+fn several_protocol_groups() {
+    // T=0, then T=1 with TA3 = IFSC 254 and TB3 = BWI 4 / CWI 5
+    // https://smartcard-atr.apdu.fr/parse?ATR=3B+80+80+31+FE+45+8A
+    let atr = Atr::new()
+        .with_protocol(0)
+        .with_protocol(1)
+        .with_interface_bytes(InterfaceBytes { ta: Some(0xFE), tb: Some(0x45), tc: None });
+    assert_eq!(&atr.to_bytes(), &[0x3B, 0x80, 0x80, 0x31, 0xFE, 0x45, 0x8A]);
+
+    // TD1 and TD2 of the same protocol, with interface bytes in the first two groups
+    let atr = Atr::new()
+        .with_interface_bytes(InterfaceBytes { ta: Some(0x96), tb: None, tc: None })
+        .with_protocol(1)
+        .with_interface_bytes(InterfaceBytes { ta: None, tb: None, tc: Some(0x00) })
+        .with_protocol(1);
+    assert_eq!(&atr.to_bytes(), &[0x3B, 0x90, 0x96, 0xC1, 0x00, 0x01, 0xC6]);
+}
+
+#[test]
+#[should_panic(expected = "too many interface bytes")]
+fn at_most_four_groups() {
+    Atr::new().with_protocol(1).with_protocol(1).with_protocol(1).with_protocol(1);
+}
+
+#[test]
+fn historical_bytes_limit() {
+    // category indicator, tag/length and 13 bytes of data fill the historical bytes
+    let atr = Atr::new().with_protocol(1).with_card_issuers_data(b"Nitrokey 3 AM").to_bytes();
+    assert_eq!(atr.len(), 2 + 1 + MAX_HISTORICAL_BYTES + 1);
+    assert_eq!(atr[1], 0x80 | MAX_HISTORICAL_BYTES as u8);
+    assert_eq!(&atr[3..6], &[0x80, 0x5D, b'N']);
+    let tck = atr[1..atr.len() - 1].iter().fold(0, |checksum, byte| checksum ^ byte);
+    assert_eq!(atr[atr.len() - 1], tck);
+
+    // as do a life cycle status and some card issuer's data
+    let atr = Atr::new().with_card_issuers_data(b"Nitrokey3AM").with_status_indicator(0x05, None);
+    assert_eq!(atr.to_bytes().len(), 2 + MAX_HISTORICAL_BYTES);
+}
+
+#[test]
+#[should_panic(expected = "historical bytes too long")]
+fn too_long_card_issuers_data() {
+    Atr::new().with_card_issuers_data(b"Nitrokey 3 AM+");
+}
+
+#[test]
+#[should_panic(expected = "historical bytes too long")]
+fn no_room_for_status_word() {
+    Atr::new().with_card_issuers_data(b"Nitrokey3AM").with_status_indicator(0x05, Some([0x90, 0x00]));
+}
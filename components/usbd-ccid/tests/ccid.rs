@@ -0,0 +1,451 @@
+//! Protocol tests against an in-memory USB bus, the test plays the host.
+
+mod common;
+
+use common::*;
+use interchange::Interchange;
+use usb_bus_mock::MockBus;
+use usb_device::{
+    bus::UsbBusAllocator,
+    device::{UsbDeviceBuilder, UsbVidPid},
+};
+use usbd_ccid::{
+    constants::TransferMode,
+    time_extension::{block_waiting_time, HintInterchange, Hinter, Policy},
+    types::Status,
+    Ccid,
+};
+
+interchange::interchange! { AtrInterchange: (Message, Message) }
+interchange::interchange! { ChainInterchange: (Message, Message) }
+interchange::interchange! { ZlpInterchange: (Message, Message) }
+interchange::interchange! { AbortInterchange: (Message, Message) }
+interchange::interchange! { WaitInterchange: (Message, Message) }
+interchange::interchange! { StaleInterchange: (Message, Message) }
+interchange::interchange! { ExtendedInterchange: (Message, Message) }
+interchange::interchange! { IccdAInterchange: (Message, Message) }
+interchange::interchange! { IccdBInterchange: (Message, Message) }
+interchange::interchange! { PolicyInterchange: (Message, Message) }
+interchange::interchange! { InterruptedInterchange: (Message, Message) }
+
+// ICCD class requests (bmRequestType, bRequest), the interface is 0
+const ICC_POWER_ON: (u8, u8, u16, u16) = (0x21, 0x62, 0, 0);
+const ICC_POWER_OFF: (u8, u8, u16, u16) = (0x21, 0x63, 0, 0);
+const DATA_BLOCK: (u8, u8, u16, u16) = (0xA1, 0x6F, 0, 0);
+const GET_ICC_STATUS: (u8, u8, u16, u16) = (0xA1, 0xA0, 0, 0);
+const SLOT_STATUS: (u8, u8, u16, u16) = (0xA1, 0x81, 0, 0);
+
+fn xfr_block(level_parameter: u16) -> (u8, u8, u16, u16) {
+    (0x21, 0x65, level_parameter, 0)
+}
+
+#[test]
+fn power_on_returns_atr() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, _responder) = AtrInterchange::claim().unwrap();
+    let mut ccid = Ccid::new(&allocator, requester, atr());
+    let host = Host::new(&bus);
+
+    host.send(&mut ccid, &command(PC_TO_RDR_ICC_POWER_ON, 7, 0, &[]));
+    let response = host.receive(&mut ccid);
+    let expected = atr().to_bytes();
+    assert_eq!(response[0], RDR_TO_PC_DATA_BLOCK);
+    assert_eq!(response[1] as usize, expected.len());
+    assert_eq!(response[6], 7);
+    assert_eq!(&response[10..], &expected[..]);
+}
+
+#[test]
+fn chained_xfr_block() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, mut responder) = ChainInterchange::claim().unwrap();
+    let mut ccid = Ccid::new(&allocator, requester, atr());
+    let host = Host::new(&bus);
+
+    // command APDU in two blocks, each acknowledged with an empty block
+    host.send(&mut ccid, &command(PC_TO_RDR_XFR_BLOCK, 1, 1, &[0x00, 0xA4]));
+    let response = host.receive(&mut ccid);
+    assert_eq!(&response[..], &[RDR_TO_PC_DATA_BLOCK, 0, 0, 0, 0, 0, 1, 0, 0, 0x10]);
+
+    host.send(&mut ccid, &command(PC_TO_RDR_XFR_BLOCK, 2, 2, &[0x04, 0x00]));
+    assert!(host.nothing_received());
+    assert_eq!(&responder.take_request().unwrap()[..], &[0x00, 0xA4, 0x04, 0x00]);
+
+    // response APDU in two blocks, the host asks for the second
+    let data: Vec<u8> = (0..100).collect();
+    responder.respond(&Message::from_slice(&data).unwrap()).ok().unwrap();
+    ccid.check_for_app_response();
+
+    let response = host.receive(&mut ccid);
+    assert_eq!(response.len(), 64);
+    assert_eq!(&response[..10], &[RDR_TO_PC_DATA_BLOCK, 54, 0, 0, 0, 0, 2, 0, 0, 1]);
+    assert_eq!(&response[10..], &data[..54]);
+    // full packet, so the transfer is ended by a zero-length packet
+    assert!(host.receive(&mut ccid).is_empty());
+
+    host.send(&mut ccid, &command(PC_TO_RDR_XFR_BLOCK, 3, 0x10, &[]));
+    let response = host.receive(&mut ccid);
+    assert_eq!(&response[..10], &[RDR_TO_PC_DATA_BLOCK, 46, 0, 0, 0, 0, 3, 0, 0, 2]);
+    assert_eq!(&response[10..], &data[54..]);
+    assert!(host.nothing_received());
+}
+
+#[test]
+fn zlp_after_full_packet() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, mut responder) = ZlpInterchange::claim().unwrap();
+    let mut ccid = Ccid::new(&allocator, requester, atr());
+    let host = Host::new(&bus);
+
+    host.send(&mut ccid, &command(PC_TO_RDR_XFR_BLOCK, 1, 0, &[0x00, 0xCA, 0x00, 0x00]));
+    respond(&mut responder, &[0x42; 54]);
+    ccid.check_for_app_response();
+
+    let response = host.receive(&mut ccid);
+    assert_eq!(response.len(), 64);
+    assert_eq!(&response[..10], &[RDR_TO_PC_DATA_BLOCK, 54, 0, 0, 0, 0, 1, 0, 0, 0]);
+    assert!(host.receive(&mut ccid).is_empty());
+    assert!(host.nothing_received());
+
+    // one byte less fits in a short packet
+    host.send(&mut ccid, &command(PC_TO_RDR_XFR_BLOCK, 2, 0, &[0x00, 0xCA, 0x00, 0x00]));
+    respond(&mut responder, &[0x42; 53]);
+    ccid.check_for_app_response();
+
+    assert_eq!(host.receive(&mut ccid).len(), 63);
+    assert!(host.nothing_received());
+}
+
+#[test]
+fn abort_handshake() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, _responder) = AbortInterchange::claim().unwrap();
+    let mut ccid = Ccid::new(&allocator, requester, atr());
+    let mut device = UsbDeviceBuilder::new(&allocator, UsbVidPid(0x1209, 0x0001)).build();
+    let host = Host::new(&bus);
+
+    // ABORT class request (host to device, class, interface 0): slot 0, seq 5
+    bus.setup(0x21, 0x01, u16::from_le_bytes([0, 5]), 0);
+    device.poll(&mut [&mut ccid]);
+
+    // until the bulk ABORT arrives, other commands fail with CMD_ABORTED
+    host.send(&mut ccid, &command(PC_TO_RDR_GET_SLOT_STATUS, 6, 0, &[]));
+    let response = host.receive(&mut ccid);
+    assert_eq!([response[0], response[6], response[7] >> 6, response[8]], [RDR_TO_PC_SLOT_STATUS, 6, 1, 0xff]);
+
+    host.send(&mut ccid, &command(PC_TO_RDR_ABORT, 5, 0, &[]));
+    let response = host.receive(&mut ccid);
+    assert_eq!(&response[..], &[RDR_TO_PC_SLOT_STATUS, 0, 0, 0, 0, 0, 5, 0, 0, 0]);
+
+    host.send(&mut ccid, &command(PC_TO_RDR_GET_SLOT_STATUS, 7, 0, &[]));
+    let response = host.receive(&mut ccid);
+    assert_eq!(&response[..], &[RDR_TO_PC_SLOT_STATUS, 0, 0, 0, 0, 0, 7, 0, 0, 0]);
+}
+
+#[test]
+fn wait_extension() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, mut responder) = WaitInterchange::claim().unwrap();
+    let mut ccid = Ccid::new(&allocator, requester, atr());
+    let host = Host::new(&bus);
+
+    assert!(matches!(ccid.did_start_processing(), Status::Idle));
+    host.send(&mut ccid, &command(PC_TO_RDR_XFR_BLOCK, 9, 0, &[0x00, 0x20, 0x00, 0x81]));
+    // every second by default
+    assert_eq!(delay(ccid.did_start_processing()), Some(1000));
+    // read once
+    assert!(matches!(ccid.did_start_processing(), Status::Idle));
+
+    // time extension requested
+    assert_eq!(delay(ccid.send_wait_extension()), Some(1000));
+    let response = host.receive(&mut ccid);
+    assert_eq!([response[0], response[6], response[7] >> 6, response[8]], [RDR_TO_PC_DATA_BLOCK, 9, 2, 1]);
+
+    // the host is slow to take them, which does not end the command
+    assert_eq!(delay(ccid.send_wait_extension()), Some(1000));
+    assert_eq!(delay(ccid.send_wait_extension()), Some(1000));
+    assert_eq!(delay(ccid.send_wait_extension()), Some(1000));
+    for _ in 0..2 {
+        let response = host.receive(&mut ccid);
+        assert_eq!([response[0], response[6], response[7] >> 6, response[8]], [RDR_TO_PC_DATA_BLOCK, 9, 2, 1]);
+    }
+    assert!(host.nothing_received());
+
+    respond(&mut responder, &[0x90, 0x00]);
+    ccid.check_for_app_response();
+    let response = host.receive(&mut ccid);
+    assert_eq!(&response[..], &[RDR_TO_PC_DATA_BLOCK, 2, 0, 0, 0, 0, 9, 0, 0, 0, 0x90, 0x00]);
+
+    assert!(matches!(ccid.send_wait_extension(), Status::Idle));
+    assert!(host.nothing_received());
+}
+
+#[test]
+fn abort_drops_stale_response() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, mut responder) = StaleInterchange::claim().unwrap();
+    let mut ccid = Ccid::new(&allocator, requester, atr());
+    let mut device = UsbDeviceBuilder::new(&allocator, UsbVidPid(0x1209, 0x0001)).build();
+    let host = Host::new(&bus);
+
+    // the app is still processing when the abort completes
+    host.send(&mut ccid, &command(PC_TO_RDR_XFR_BLOCK, 1, 0, &[0x00, 0x20, 0x00, 0x81]));
+    assert!(matches!(ccid.did_start_processing(), Status::ReceivedData(_)));
+    assert!(responder.take_request().is_some());
+    bus.setup(0x21, 0x01, u16::from_le_bytes([0, 2]), 0);
+    device.poll(&mut [&mut ccid]);
+    host.send(&mut ccid, &command(PC_TO_RDR_ABORT, 2, 0, &[]));
+    let response = host.receive(&mut ccid);
+    assert_eq!(&response[..], &[RDR_TO_PC_SLOT_STATUS, 0, 0, 0, 0, 0, 2, 0, 0, 0]);
+    assert!(matches!(ccid.send_wait_extension(), Status::Idle));
+
+    // its late response is dropped
+    responder.respond(&Message::from_slice(&[0x90, 0x00]).unwrap()).ok().unwrap();
+    ccid.check_for_app_response();
+    assert!(host.nothing_received());
+
+    // the app answers between the control and the bulk abort
+    host.send(&mut ccid, &command(PC_TO_RDR_XFR_BLOCK, 3, 0, &[0x00, 0xCA, 0x00, 0x00]));
+    assert!(responder.take_request().is_some());
+    bus.setup(0x21, 0x01, u16::from_le_bytes([0, 4]), 0);
+    device.poll(&mut [&mut ccid]);
+    responder.respond(&Message::from_slice(b"stale").unwrap()).ok().unwrap();
+    host.send(&mut ccid, &command(PC_TO_RDR_ABORT, 4, 0, &[]));
+    let response = host.receive(&mut ccid);
+    assert_eq!(&response[..], &[RDR_TO_PC_SLOT_STATUS, 0, 0, 0, 0, 0, 4, 0, 0, 0]);
+
+    // the next command gets its own response only
+    host.send(&mut ccid, &command(PC_TO_RDR_XFR_BLOCK, 5, 0, &[0x00, 0xCA, 0x00, 0x00]));
+    ccid.check_for_app_response();
+    assert!(host.nothing_received());
+    respond(&mut responder, &[0x90, 0x00]);
+    ccid.check_for_app_response();
+    let response = host.receive(&mut ccid);
+    assert_eq!(&response[..], &[RDR_TO_PC_DATA_BLOCK, 2, 0, 0, 0, 0, 5, 0, 0, 0, 0x90, 0x00]);
+}
+
+#[test]
+fn extended_apdu_in_single_block() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, mut responder) = ExtendedInterchange::claim().unwrap();
+    let mut ccid = Ccid::new(&allocator, requester, atr());
+    let host = Host::new(&bus);
+
+    // e.g. an RSA-4096 certificate upload, far beyond the old 3072 byte limit
+    let apdu: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+    host.send(&mut ccid, &command(PC_TO_RDR_XFR_BLOCK, 1, 0, &apdu));
+    assert_eq!(&responder.take_request().unwrap()[..], &apdu[..]);
+    responder.respond(&Message::from_slice(&[0x90, 0x00]).unwrap()).ok().unwrap();
+    ccid.check_for_app_response();
+    let response = host.receive(&mut ccid);
+    assert_eq!(&response[..], &[RDR_TO_PC_DATA_BLOCK, 2, 0, 0, 0, 0, 1, 0, 0, 0, 0x90, 0x00]);
+
+    // one byte more than the interchange holds is rejected once the transfer ends
+    let apdu = vec![0x42; 7610];
+    host.send(&mut ccid, &command(PC_TO_RDR_XFR_BLOCK, 2, 0, &apdu));
+    let response = host.receive(&mut ccid);
+    assert_eq!(&response[..], &[RDR_TO_PC_DATA_BLOCK, 0, 0, 0, 0, 0, 2, 0x40, 1, 0]);
+    assert!(host.nothing_received());
+    assert!(responder.take_request().is_none());
+
+    // and the next command is parsed as such
+    host.send(&mut ccid, &command(PC_TO_RDR_XFR_BLOCK, 3, 0, &[0x00, 0xCA, 0x00, 0x00]));
+    assert_eq!(&responder.take_request().unwrap()[..], &[0x00, 0xCA, 0x00, 0x00]);
+}
+
+#[test]
+fn iccd_version_a() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, mut responder) = IccdAInterchange::claim().unwrap();
+    let mut ccid = Ccid::new_with_mode(&allocator, requester, atr(), TransferMode::ControlA);
+    let mut device = UsbDeviceBuilder::new(&allocator, UsbVidPid(0x1209, 0x0001))
+        .max_packet_size_0(64)
+        .build();
+
+    // expecting a command
+    assert_eq!(bus.control_in(&mut device, &mut [&mut ccid], GET_ICC_STATUS, 1), Some(vec![0x10]));
+
+    assert!(bus.control_out(&mut device, &mut [&mut ccid], ICC_POWER_ON, &[]));
+    // data available
+    assert_eq!(bus.control_in(&mut device, &mut [&mut ccid], GET_ICC_STATUS, 1), Some(vec![0x20]));
+    let response = bus.control_in(&mut device, &mut [&mut ccid], DATA_BLOCK, 256).unwrap();
+    assert_eq!(&response[..], &atr().to_bytes()[..]);
+
+    // spans several bulk-sized blocks internally
+    let apdu: Vec<u8> = (0..200).collect();
+    assert!(bus.control_out(&mut device, &mut [&mut ccid], xfr_block(0), &apdu));
+    assert_eq!(&responder.take_request().unwrap()[..], &apdu[..]);
+    // busy
+    assert_eq!(bus.control_in(&mut device, &mut [&mut ccid], GET_ICC_STATUS, 1), Some(vec![0x40]));
+    assert!(!bus.control_out(&mut device, &mut [&mut ccid], xfr_block(0), &apdu));
+
+    // no chaining, the response comes in one piece
+    let data: Vec<u8> = (0..150).collect();
+    responder.respond(&Message::from_slice(&data).unwrap()).ok().unwrap();
+    ccid.check_for_app_response();
+    assert_eq!(bus.control_in(&mut device, &mut [&mut ccid], GET_ICC_STATUS, 1), Some(vec![0x20]));
+    assert_eq!(bus.control_in(&mut device, &mut [&mut ccid], DATA_BLOCK, 256), Some(data));
+    assert_eq!(bus.control_in(&mut device, &mut [&mut ccid], GET_ICC_STATUS, 1), Some(vec![0x10]));
+
+    // a response beyond the data stage can't be fetched, and is dropped
+    assert!(bus.control_out(&mut device, &mut [&mut ccid], xfr_block(0), &[0x00, 0xCA, 0x00, 0x00]));
+    let data: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    respond(&mut responder, &data);
+    ccid.check_for_app_response();
+    assert_eq!(bus.control_in(&mut device, &mut [&mut ccid], DATA_BLOCK, 256), None);
+    assert_eq!(bus.control_in(&mut device, &mut [&mut ccid], GET_ICC_STATUS, 1), Some(vec![0x10]));
+    assert!(bus.control_out(&mut device, &mut [&mut ccid], xfr_block(0), &[0x00, 0xCA, 0x00, 0x00]));
+    respond(&mut responder, &[0x90, 0x00]);
+    ccid.check_for_app_response();
+    assert_eq!(bus.control_in(&mut device, &mut [&mut ccid], DATA_BLOCK, 256), Some(vec![0x90, 0x00]));
+
+    // mute
+    assert!(bus.control_out(&mut device, &mut [&mut ccid], ICC_POWER_OFF, &[]));
+    assert_eq!(bus.control_in(&mut device, &mut [&mut ccid], GET_ICC_STATUS, 1), Some(vec![0x80]));
+}
+
+#[test]
+fn iccd_version_b() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, mut responder) = IccdBInterchange::claim().unwrap();
+    let mut ccid = Ccid::new_with_mode(&allocator, requester, atr(), TransferMode::ControlB);
+    let mut device = UsbDeviceBuilder::new(&allocator, UsbVidPid(0x1209, 0x0001))
+        .max_packet_size_0(64)
+        .build();
+
+    assert!(bus.control_out(&mut device, &mut [&mut ccid], ICC_POWER_ON, &[]));
+    let response = bus.control_in(&mut device, &mut [&mut ccid], DATA_BLOCK, 256).unwrap();
+    assert_eq!(response[0], 0x00);
+    assert_eq!(&response[1..], &atr().to_bytes()[..]);
+
+    // command chaining: the first block is acknowledged with "expecting more"
+    assert!(bus.control_out(&mut device, &mut [&mut ccid], xfr_block(1), &[0x00, 0xA4]));
+    assert_eq!(bus.control_in(&mut device, &mut [&mut ccid], DATA_BLOCK, 256), Some(vec![0x10]));
+    assert!(bus.control_out(&mut device, &mut [&mut ccid], xfr_block(2), &[0x04, 0x00]));
+    assert_eq!(&responder.take_request().unwrap()[..], &[0x00, 0xA4, 0x04, 0x00]);
+
+    // while the app is processing, the host is asked to poll again
+    assert_eq!(bus.control_in(&mut device, &mut [&mut ccid], DATA_BLOCK, 256), Some(vec![0x80, 10, 0]));
+    assert!(!bus.control_out(&mut device, &mut [&mut ccid], xfr_block(0), &[0x00, 0xCA, 0x00, 0x00]));
+
+    // response chaining
+    let data: Vec<u8> = (0..100).collect();
+    responder.respond(&Message::from_slice(&data).unwrap()).ok().unwrap();
+    ccid.check_for_app_response();
+    let response = bus.control_in(&mut device, &mut [&mut ccid], DATA_BLOCK, 256).unwrap();
+    assert_eq!(response[0], 0x01);
+    assert_eq!(&response[1..], &data[..54]);
+    assert!(bus.control_out(&mut device, &mut [&mut ccid], xfr_block(0x10), &[]));
+    let response = bus.control_in(&mut device, &mut [&mut ccid], DATA_BLOCK, 256).unwrap();
+    assert_eq!(response[0], 0x02);
+    assert_eq!(&response[1..], &data[54..]);
+
+    // status information: present and active, resp. inactive after power off
+    assert_eq!(bus.control_in(&mut device, &mut [&mut ccid], SLOT_STATUS, 3), Some(vec![0x40, 0, 0]));
+    assert!(bus.control_out(&mut device, &mut [&mut ccid], ICC_POWER_OFF, &[]));
+    assert_eq!(bus.control_in(&mut device, &mut [&mut ccid], SLOT_STATUS, 3), Some(vec![0x40, 1, 0]));
+}
+
+#[test]
+fn time_extension_policy() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, mut responder) = PolicyInterchange::claim().unwrap();
+    let (hint_requester, hint_responder) = HintInterchange::claim().unwrap();
+    let mut ccid = Ccid::new(&allocator, requester, atr())
+        .with_time_extension_policy(Policy::adaptive())
+        .with_time_extension_hints(hint_responder);
+    let mut hinter = Hinter::new(hint_requester);
+    let host = Host::new(&bus);
+
+    // default parameters: bWI = 1
+    assert_eq!(block_waiting_time(0x15), 200);
+    host.send(&mut ccid, &command(PC_TO_RDR_XFR_BLOCK, 1, 0, &[0x00, 0x47, 0x80, 0x00]));
+    // halfway through the block waiting time
+    assert_eq!(delay(ccid.did_start_processing()), Some(100));
+
+    // the multiplier doubles after four time extensions
+    for multiplier in [1, 1, 1, 1, 2, 2, 2, 2, 4] {
+        assert_eq!(delay(ccid.send_wait_extension()), Some(multiplier * 100));
+        let response = host.receive(&mut ccid);
+        assert_eq!([response[7] >> 6, response[8]], [2, multiplier as u8]);
+    }
+
+    // the app expects key generation to take 10 s, of which 1.7 s have passed
+    hinter.expect(10_000);
+    assert_eq!(delay(ccid.send_wait_extension()), Some(42 * 100));
+    assert_eq!(host.receive(&mut ccid)[8], 42);
+
+    respond(&mut responder, &[0x90, 0x00]);
+    ccid.check_for_app_response();
+    assert_eq!(host.receive(&mut ccid)[0], RDR_TO_PC_DATA_BLOCK);
+    assert_eq!(delay(ccid.send_wait_extension()), None);
+
+    // a late hint is not applied to the next command
+    hinter.expect(60_000);
+
+    // bWI = 4 as negotiated by the host, the multiplier starts over
+    let mut set_parameters = command(PC_TO_RDR_SET_PARAMETERS, 2, 0, &[0x11, 0x10, 0x00, 0x45, 0x00, 0xFE, 0x00]);
+    // bProtocolNum
+    set_parameters[7] = 1;
+    host.send(&mut ccid, &set_parameters);
+    assert_eq!(host.receive(&mut ccid)[8], 0);
+    host.send(&mut ccid, &command(PC_TO_RDR_XFR_BLOCK, 3, 0, &[0x00, 0xCA, 0x00, 0x00]));
+    assert_eq!(delay(ccid.did_start_processing()), Some(1597 / 2));
+    assert_eq!(delay(ccid.send_wait_extension()), Some(1597 / 2));
+    assert_eq!(host.receive(&mut ccid)[8], 1);
+}
+
+#[test]
+fn chained_response_interrupted_by_abort() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, mut responder) = InterruptedInterchange::claim().unwrap();
+    let mut ccid = Ccid::new(&allocator, requester, atr());
+    let mut device = UsbDeviceBuilder::new(&allocator, UsbVidPid(0x1209, 0x0001)).build();
+    let host = Host::new(&bus);
+
+    // a response in four blocks, the host only takes the first two
+    host.send(&mut ccid, &command(PC_TO_RDR_XFR_BLOCK, 1, 0, &[0x00, 0xCA, 0x00, 0x00]));
+    let data: Vec<u8> = (0..200).collect();
+    respond(&mut responder, &data);
+    ccid.check_for_app_response();
+    let response = host.receive(&mut ccid);
+    assert_eq!([response[6], response[9]], [1, 1]);
+    assert_eq!(&response[10..], &data[..54]);
+    assert!(host.receive(&mut ccid).is_empty());
+    host.send(&mut ccid, &command(PC_TO_RDR_XFR_BLOCK, 2, 0x10, &[]));
+    let response = host.receive(&mut ccid);
+    assert_eq!([response[6], response[9]], [2, 3]);
+    assert_eq!(&response[10..], &data[54..108]);
+    assert!(host.receive(&mut ccid).is_empty());
+
+    bus.setup(0x21, 0x01, u16::from_le_bytes([0, 3]), 0);
+    device.poll(&mut [&mut ccid]);
+    host.send(&mut ccid, &command(PC_TO_RDR_ABORT, 3, 0, &[]));
+    let response = host.receive(&mut ccid);
+    assert_eq!(&response[..], &[RDR_TO_PC_SLOT_STATUS, 0, 0, 0, 0, 0, 3, 0, 0, 0]);
+
+    // the rest of the response is gone
+    host.send(&mut ccid, &command(PC_TO_RDR_XFR_BLOCK, 4, 0x10, &[]));
+    let response = host.receive(&mut ccid);
+    assert_eq!([response[0], response[6], response[7] >> 6, response[8]], [RDR_TO_PC_DATA_BLOCK, 4, 1, 8]);
+
+    // and the next command gets its own response
+    host.send(&mut ccid, &command(PC_TO_RDR_XFR_BLOCK, 5, 0, &[0x00, 0xCA, 0x00, 0x00]));
+    ccid.check_for_app_response();
+    assert!(host.nothing_received());
+    respond(&mut responder, &[0x90, 0x00]);
+    ccid.check_for_app_response();
+    let response = host.receive(&mut ccid);
+    assert_eq!(&response[..], &[RDR_TO_PC_DATA_BLOCK, 2, 0, 0, 0, 0, 5, 0, 0, 0, 0x90, 0x00]);
+}
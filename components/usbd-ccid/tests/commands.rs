@@ -0,0 +1,90 @@
+mod common;
+
+use common::*;
+use usb_bus_mock::MockBus;
+use usb_device::bus::UsbBusAllocator;
+use usbd_ccid::Ccid;
+
+const DEFAULT_PARAMETERS: [u8; 7] = [0x11, 0x10, 0x00, 0x15, 0x00, 0xfe, 0x00];
+
+fn set_parameters(seq: u8, parameters: &[u8]) -> Vec<u8> {
+    let mut message = command(PC_TO_RDR_SET_PARAMETERS, seq, 0, parameters);
+    // bProtocolNum
+    message[7] = 1;
+    message
+}
+
+fn icc_clock(seq: u8, clock_command: u8) -> Vec<u8> {
+    let mut message = command(PC_TO_RDR_ICC_CLOCK, seq, 0, &[]);
+    // bClockCommand
+    message[7] = clock_command;
+    message
+}
+
+fn parameters(seq: u8, parameters: &[u8]) -> Vec<u8> {
+    let mut message = vec![RDR_TO_PC_PARAMETERS, 7, 0, 0, 0, 0, seq, 0, 0, 1];
+    message.extend_from_slice(parameters);
+    message
+}
+
+#[test]
+fn reader_commands() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, _responder) = claim();
+    let mut ccid = Ccid::new(&allocator, requester, atr());
+    let host = Host::new(&bus);
+
+    // T=1 parameters, as negotiated by the host
+    host.send(&mut ccid, &command(PC_TO_RDR_GET_PARAMETERS, 1, 0, &[]));
+    assert_eq!(host.receive(&mut ccid), parameters(1, &DEFAULT_PARAMETERS));
+    let negotiated = [0x11, 0x11, 0x00, 0x45, 0x00, 0x20, 0x00];
+    host.send(&mut ccid, &set_parameters(2, &negotiated));
+    assert_eq!(host.receive(&mut ccid), parameters(2, &negotiated));
+
+    // bIFSC = 0xff is RFU, the parameters in effect are kept
+    host.send(&mut ccid, &set_parameters(3, &[0x11, 0x10, 0x00, 0x15, 0x00, 0xff, 0x00]));
+    assert_eq!(host.receive(&mut ccid), vec![RDR_TO_PC_PARAMETERS, 0, 0, 0, 0, 0, 3, 0x40, 15, 1]);
+    // as is T=0
+    let mut t0 = set_parameters(4, &negotiated[..5]);
+    t0[7] = 0;
+    host.send(&mut ccid, &t0);
+    assert_eq!(host.receive(&mut ccid), vec![RDR_TO_PC_PARAMETERS, 0, 0, 0, 0, 0, 4, 0x40, 7, 1]);
+    host.send(&mut ccid, &command(PC_TO_RDR_GET_PARAMETERS, 5, 0, &[]));
+    assert_eq!(host.receive(&mut ccid), parameters(5, &negotiated));
+
+    host.send(&mut ccid, &command(PC_TO_RDR_RESET_PARAMETERS, 6, 0, &[]));
+    assert_eq!(host.receive(&mut ccid), parameters(6, &DEFAULT_PARAMETERS));
+
+    // bClockStatus follows the clock command
+    host.send(&mut ccid, &icc_clock(7, 1));
+    assert_eq!(host.receive(&mut ccid), vec![RDR_TO_PC_SLOT_STATUS, 0, 0, 0, 0, 0, 7, 0, 0, 1]);
+    host.send(&mut ccid, &command(PC_TO_RDR_GET_SLOT_STATUS, 8, 0, &[]));
+    assert_eq!(host.receive(&mut ccid), vec![RDR_TO_PC_SLOT_STATUS, 0, 0, 0, 0, 0, 8, 0, 0, 1]);
+    host.send(&mut ccid, &icc_clock(9, 0));
+    assert_eq!(host.receive(&mut ccid), vec![RDR_TO_PC_SLOT_STATUS, 0, 0, 0, 0, 0, 9, 0, 0, 0]);
+    host.send(&mut ccid, &icc_clock(10, 2));
+    assert_eq!(host.receive(&mut ccid), vec![RDR_TO_PC_SLOT_STATUS, 0, 0, 0, 0, 0, 10, 0x40, 7, 0]);
+
+    // we only do T=1, and have no mechanical parts
+    host.send(&mut ccid, &command(PC_TO_RDR_T0_APDU, 11, 0, &[]));
+    assert_eq!(host.receive_status(&mut ccid), [RDR_TO_PC_SLOT_STATUS, 0, 11, 1, COMMAND_NOT_SUPPORTED]);
+    host.send(&mut ccid, &command(PC_TO_RDR_MECHANICAL, 12, 0, &[]));
+    assert_eq!(host.receive_status(&mut ccid), [RDR_TO_PC_SLOT_STATUS, 0, 12, 1, COMMAND_NOT_SUPPORTED]);
+
+    // whatever the host asks for, the fixed clock and data rate are in effect
+    let requested = [0x40, 0x1f, 0x00, 0x00, 0x00, 0x4b, 0x00, 0x00];
+    host.send(&mut ccid, &command(PC_TO_RDR_SET_DATA_RATE_AND_CLOCK_FREQUENCY, 13, 0, &requested));
+    assert_eq!(
+        host.receive(&mut ccid),
+        vec![RDR_TO_PC_DATA_RATE_AND_CLOCK_FREQUENCY, 8, 0, 0, 0, 0, 13, 0, 0, 0,
+             0xfc, 0x0d, 0x00, 0x00, 0x80, 0x25, 0x00, 0x00],
+    );
+    host.send(&mut ccid, &command(PC_TO_RDR_SET_DATA_RATE_AND_CLOCK_FREQUENCY, 14, 0, &requested[..4]));
+    assert_eq!(
+        host.receive(&mut ccid),
+        vec![RDR_TO_PC_DATA_RATE_AND_CLOCK_FREQUENCY, 0, 0, 0, 0, 0, 14, 0x40, 1, 0],
+    );
+
+    assert!(host.nothing_received());
+}
@@ -0,0 +1,160 @@
+//! The test plays the host of a CCID reader on an in-memory USB bus.
+//!
+//! Assumes full-speed packets (no `highspeed-usb` feature).
+
+#![allow(dead_code)]
+
+use interchange::{Interchange, Requester, Responder};
+use usb_bus_mock::MockBus;
+use usb_device::{
+    class::UsbClass,
+    device::UsbDevice,
+    endpoint::{EndpointAddress, EndpointType},
+    UsbDirection,
+};
+use usbd_ccid::{types::Status, Atr};
+
+pub type Message = heapless::Vec<u8, 7609>;
+
+interchange::interchange! { CcidInterchange: (Message, Message) }
+
+pub const PC_TO_RDR_SET_PARAMETERS: u8 = 0x61;
+pub const PC_TO_RDR_ICC_POWER_ON: u8 = 0x62;
+pub const PC_TO_RDR_ICC_POWER_OFF: u8 = 0x63;
+pub const PC_TO_RDR_GET_SLOT_STATUS: u8 = 0x65;
+pub const PC_TO_RDR_ESCAPE: u8 = 0x6b;
+pub const PC_TO_RDR_GET_PARAMETERS: u8 = 0x6c;
+pub const PC_TO_RDR_RESET_PARAMETERS: u8 = 0x6d;
+pub const PC_TO_RDR_ICC_CLOCK: u8 = 0x6e;
+pub const PC_TO_RDR_XFR_BLOCK: u8 = 0x6f;
+pub const PC_TO_RDR_T0_APDU: u8 = 0x6a;
+pub const PC_TO_RDR_MECHANICAL: u8 = 0x71;
+pub const PC_TO_RDR_ABORT: u8 = 0x72;
+pub const PC_TO_RDR_SET_DATA_RATE_AND_CLOCK_FREQUENCY: u8 = 0x73;
+pub const RDR_TO_PC_DATA_BLOCK: u8 = 0x80;
+pub const RDR_TO_PC_SLOT_STATUS: u8 = 0x81;
+pub const RDR_TO_PC_PARAMETERS: u8 = 0x82;
+pub const RDR_TO_PC_ESCAPE: u8 = 0x83;
+pub const RDR_TO_PC_DATA_RATE_AND_CLOCK_FREQUENCY: u8 = 0x84;
+pub const RDR_TO_PC_NOTIFY_SLOT_CHANGE: u8 = 0x50;
+
+// bError of failed commands
+pub const CMD_ABORTED: u8 = 0xff;
+pub const ICC_MUTE: u8 = 0xfe;
+pub const CMD_SLOT_BUSY: u8 = 0xe0;
+pub const COMMAND_NOT_SUPPORTED: u8 = 0x00;
+pub const SLOT_DOES_NOT_EXIST: u8 = 5;
+
+// ABORT class request (host to device, class, interface 0)
+pub fn abort_request(slot: u8, seq: u8) -> (u8, u8, u16, u16) {
+    (0x21, 0x01, u16::from_le_bytes([slot, seq]), 0)
+}
+
+// GET_DESCRIPTOR(CONFIGURATION)
+pub const GET_CONFIGURATION_DESCRIPTOR: (u8, u8, u16, u16) = (0x80, 0x06, 0x0200, 0);
+
+pub struct Host {
+    bus: MockBus,
+    bulk_out: EndpointAddress,
+    bulk_in: EndpointAddress,
+}
+
+impl Host {
+    pub fn new(bus: &MockBus) -> Self {
+        Self {
+            bus: bus.clone(),
+            bulk_out: bus.endpoint(EndpointType::Bulk, UsbDirection::Out),
+            bulk_in: bus.endpoint(EndpointType::Bulk, UsbDirection::In),
+        }
+    }
+
+    pub fn send(&self, class: &mut dyn UsbClass<MockBus>, message: &[u8]) {
+        for packet in message.chunks(64) {
+            self.send_packet(class, packet);
+        }
+    }
+
+    /// A single packet, which need not be a complete message.
+    pub fn send_packet(&self, class: &mut dyn UsbClass<MockBus>, packet: &[u8]) {
+        self.bus.send(self.bulk_out, packet);
+        class.endpoint_out(self.bulk_out);
+    }
+
+    pub fn receive(&self, class: &mut dyn UsbClass<MockBus>) -> Vec<u8> {
+        let packet = self.bus.receive(self.bulk_in).expect("no response");
+        class.endpoint_in_complete(self.bulk_in);
+        packet
+    }
+
+    /// bMessageType, bSlot, bSeq, bmCommandStatus and bError of the next response.
+    pub fn receive_status(&self, class: &mut dyn UsbClass<MockBus>) -> [u8; 5] {
+        let response = self.receive(class);
+        [response[0], response[5], response[6], response[7] >> 6, response[8]]
+    }
+
+    pub fn nothing_received(&self) -> bool {
+        self.bus.pending(self.bulk_in) == 0
+    }
+}
+
+/// Claims the interchange, which is only possible once per test binary.
+pub fn claim() -> (Requester<CcidInterchange>, Responder<CcidInterchange>) {
+    CcidInterchange::claim().unwrap()
+}
+
+pub fn command(message_type: u8, seq: u8, level_parameter: u16, data: &[u8]) -> Vec<u8> {
+    slot_command(message_type, 0, seq, level_parameter, data)
+}
+
+pub fn slot_command(message_type: u8, slot: u8, seq: u8, level_parameter: u16, data: &[u8]) -> Vec<u8> {
+    let mut message = vec![message_type];
+    message.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    message.push(slot);
+    message.push(seq);
+    // bBWI resp. bPowerSelect
+    message.push(0);
+    message.extend_from_slice(&level_parameter.to_le_bytes());
+    message.extend_from_slice(data);
+    message
+}
+
+/// Answers the pending request, like the apdu-dispatch would.
+pub fn respond<I>(responder: &mut Responder<I>, data: &[u8])
+where
+    I: Interchange<REQUEST = Message, RESPONSE = Message>,
+{
+    assert!(responder.take_request().is_some());
+    responder.respond(&Message::from_slice(data).unwrap()).ok().unwrap();
+}
+
+pub fn delay(status: Status) -> Option<u32> {
+    match status {
+        Status::ReceivedData(milliseconds) => Some(milliseconds.0),
+        Status::Idle => None,
+    }
+}
+
+pub fn atr() -> Atr {
+    Atr::new().with_protocol(1).with_card_issuers_data(b"Test")
+}
+
+/// The endpoint descriptors (bEndpointAddress, bmAttributes, wMaxPacketSize, bInterval)
+/// of the configuration descriptor.
+pub fn endpoint_descriptors(
+    bus: &MockBus,
+    device: &mut UsbDevice<'_, MockBus>,
+    classes: &mut [&mut dyn UsbClass<MockBus>],
+) -> Vec<(u8, u8, u16, u8)> {
+    let descriptor = bus.control_in(device, classes, GET_CONFIGURATION_DESCRIPTOR, 255).unwrap();
+    let mut endpoints = Vec::new();
+    let mut rest = &descriptor[..];
+    while rest.len() >= 2 {
+        let (current, next) = rest.split_at(rest[0] as usize);
+        // ENDPOINT
+        if current[1] == 0x05 {
+            endpoints.push((current[2], current[3], u16::from_le_bytes([current[4], current[5]]), current[6]));
+        }
+        rest = next;
+    }
+    endpoints
+}
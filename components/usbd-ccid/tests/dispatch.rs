@@ -0,0 +1,97 @@
+//! The reader in front of apdu-dispatch, as in the runners.
+
+mod common;
+
+use core::cell::RefCell;
+
+use apdu_dispatch::{
+    app, command::SIZE as CommandSize, dispatch::ApduDispatch, interchanges::{Contact, Contactless},
+    response, response::SIZE as ResponseSize, Command,
+};
+use common::*;
+use interchange::Interchange;
+use usb_bus_mock::MockBus;
+use usb_device::{
+    bus::UsbBusAllocator,
+    device::{UsbDeviceBuilder, UsbVidPid},
+};
+use usbd_ccid::Ccid;
+
+const AID: [u8; 5] = [0xF0, 0x00, 0x00, 0x00, 0x01];
+const GET_DATA: &[u8] = &[0x00, 0xCA, 0x00, 0x00];
+
+/// Answers with the number of calls so far, running `interrupt` during the next call,
+/// like the USB interrupt preempting the app.
+struct CountingApp<'a> {
+    calls: u8,
+    interrupt: Option<Box<dyn FnOnce() + 'a>>,
+}
+
+impl iso7816::App for CountingApp<'_> {
+    fn aid(&self) -> iso7816::Aid {
+        iso7816::Aid::new(&AID)
+    }
+}
+
+impl app::App<CommandSize, ResponseSize> for CountingApp<'_> {
+    fn select(&mut self, _apdu: &Command, _reply: &mut response::Data) -> app::Result {
+        Ok(())
+    }
+
+    fn deselect(&mut self) {}
+
+    fn call(&mut self, _interface: app::Interface, _apdu: &Command, reply: &mut response::Data) -> app::Result {
+        if let Some(interrupt) = self.interrupt.take() {
+            interrupt();
+        }
+        self.calls += 1;
+        reply.push(self.calls).unwrap();
+        Ok(())
+    }
+}
+
+#[test]
+fn abort_while_the_app_processes() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, contact) = Contact::claim().unwrap();
+    let (_, contactless) = Contactless::claim().unwrap();
+    let ccid = RefCell::new(Ccid::new(&allocator, requester, atr()));
+    let device = RefCell::new(UsbDeviceBuilder::new(&allocator, UsbVidPid(0x1209, 0x0001)).build());
+    let host = Host::new(&bus);
+    let mut dispatch = ApduDispatch::new(contact, contactless);
+    let mut app = CountingApp { calls: 0, interrupt: None };
+
+    let select = [&[0x00, 0xA4, 0x04, 0x00, AID.len() as u8][..], &AID].concat();
+    host.send(&mut *ccid.borrow_mut(), &command(PC_TO_RDR_XFR_BLOCK, 1, 0, &select));
+    dispatch.poll(&mut [&mut app]);
+    ccid.borrow_mut().check_for_app_response();
+    let response = host.receive(&mut *ccid.borrow_mut());
+    assert_eq!(&response[..], &[RDR_TO_PC_DATA_BLOCK, 2, 0, 0, 0, 0, 1, 0, 0, 0, 0x90, 0x00]);
+
+    // the host aborts the command the app is working on
+    app.interrupt = Some(Box::new(|| {
+        let mut ccid = ccid.borrow_mut();
+        assert!(bus.control_out(&mut *device.borrow_mut(), &mut [&mut *ccid], abort_request(0, 3), &[]));
+        host.send(&mut *ccid, &command(PC_TO_RDR_ABORT, 3, 0, &[]));
+        assert_eq!(host.receive(&mut *ccid), vec![RDR_TO_PC_SLOT_STATUS, 0, 0, 0, 0, 0, 3, 0, 0, 0]);
+        // the app can't take another command before it is done
+        host.send(&mut *ccid, &command(PC_TO_RDR_XFR_BLOCK, 4, 0, GET_DATA));
+        assert_eq!(host.receive_status(&mut *ccid), [RDR_TO_PC_DATA_BLOCK, 0, 4, 1, CMD_SLOT_BUSY]);
+    }));
+    host.send(&mut *ccid.borrow_mut(), &command(PC_TO_RDR_XFR_BLOCK, 2, 0, GET_DATA));
+    dispatch.poll(&mut [&mut app]);
+    assert_eq!(app.calls, 1);
+
+    // its response is dropped
+    ccid.borrow_mut().check_for_app_response();
+    assert!(host.nothing_received());
+
+    // and the next command is answered
+    host.send(&mut *ccid.borrow_mut(), &command(PC_TO_RDR_XFR_BLOCK, 5, 0, GET_DATA));
+    dispatch.poll(&mut [&mut app]);
+    ccid.borrow_mut().check_for_app_response();
+    let response = host.receive(&mut *ccid.borrow_mut());
+    assert_eq!(&response[..], &[RDR_TO_PC_DATA_BLOCK, 3, 0, 0, 0, 0, 5, 0, 0, 0, 2, 0x90, 0x00]);
+    assert!(host.nothing_received());
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use common::*;
+use interchange::Interchange;
+use usb_bus_mock::MockBus;
+use usb_device::bus::UsbBusAllocator;
+use usbd_ccid::{
+    escape::{self, EscapeInterchange},
+    Ccid,
+};
+
+const VERSION: u8 = 0x61;
+const UUID: u8 = 0x62;
+
+struct Info {
+    uuid: Option<[u8; 16]>,
+}
+
+impl escape::App for Info {
+    fn commands(&self) -> &'static [u8] {
+        &[VERSION, UUID]
+    }
+
+    fn call(&mut self, command: u8, request: &[u8], response: &mut escape::Message) -> Result<(), escape::Error> {
+        if !request.is_empty() {
+            return Err(escape::Error::InvalidLength);
+        }
+        match command {
+            VERSION => response.extend_from_slice(&[1, 2, 3]).unwrap(),
+            _ => {
+                let uuid = self.uuid.ok_or(escape::Error::NoResponse)?;
+                response.extend_from_slice(&uuid).unwrap();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn escape_round_trip() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, _responder) = claim();
+    let (escape_requester, escape_responder) = EscapeInterchange::claim().unwrap();
+    let mut ccid = Ccid::new(&allocator, requester, atr()).with_escape_channel(escape_requester);
+    let mut dispatch = escape::Dispatch::new(escape_responder);
+    let mut info = Info { uuid: None };
+    let host = Host::new(&bus);
+
+    host.send(&mut ccid, &command(PC_TO_RDR_ESCAPE, 1, 0, &[VERSION]));
+    // the answer comes from the dispatch
+    assert!(host.nothing_received());
+    assert!(dispatch.poll(&mut [&mut info]));
+    ccid.check_for_app_response();
+    let response = host.receive(&mut ccid);
+    assert_eq!(&response[..], &[RDR_TO_PC_ESCAPE, 3, 0, 0, 0, 0, 1, 0, 0, 0, 1, 2, 3]);
+
+    // other commands have to wait for the escape command
+    host.send(&mut ccid, &command(PC_TO_RDR_ESCAPE, 2, 0, &[UUID]));
+    host.send(&mut ccid, &command(PC_TO_RDR_GET_SLOT_STATUS, 3, 0, &[]));
+    assert_eq!(host.receive_status(&mut ccid), [RDR_TO_PC_SLOT_STATUS, 0, 3, 1, CMD_SLOT_BUSY]);
+    // the app can't answer
+    assert!(dispatch.poll(&mut [&mut info]));
+    ccid.check_for_app_response();
+    assert_eq!(host.receive(&mut ccid), vec![RDR_TO_PC_ESCAPE, 0, 0, 0, 0, 0, 2, 0x40, 0xfb, 0]);
+
+    info.uuid = Some([0x42; 16]);
+    host.send(&mut ccid, &command(PC_TO_RDR_ESCAPE, 4, 0, &[UUID]));
+    assert!(dispatch.poll(&mut [&mut info]));
+    ccid.check_for_app_response();
+    let response = host.receive(&mut ccid);
+    assert_eq!(&response[..10], &[RDR_TO_PC_ESCAPE, 16, 0, 0, 0, 0, 4, 0, 0, 0]);
+    assert_eq!(&response[10..], &[0x42; 16]);
+
+    // no app for the command
+    host.send(&mut ccid, &command(PC_TO_RDR_ESCAPE, 5, 0, &[0x01]));
+    assert!(dispatch.poll(&mut [&mut info]));
+    ccid.check_for_app_response();
+    assert_eq!(host.receive_status(&mut ccid), [RDR_TO_PC_ESCAPE, 0, 5, 1, COMMAND_NOT_SUPPORTED]);
+
+    // no command at all, resp. unexpected data for the command
+    host.send(&mut ccid, &command(PC_TO_RDR_ESCAPE, 6, 0, &[]));
+    assert!(dispatch.poll(&mut [&mut info]));
+    ccid.check_for_app_response();
+    assert_eq!(host.receive_status(&mut ccid), [RDR_TO_PC_ESCAPE, 0, 6, 1, 1]);
+    host.send(&mut ccid, &command(PC_TO_RDR_ESCAPE, 7, 0, &[VERSION, 0x00]));
+    assert!(dispatch.poll(&mut [&mut info]));
+    ccid.check_for_app_response();
+    assert_eq!(host.receive_status(&mut ccid), [RDR_TO_PC_ESCAPE, 0, 7, 1, 1]);
+
+    // payloads beyond escape::MESSAGE_SIZE are refused by the reader
+    host.send(&mut ccid, &command(PC_TO_RDR_ESCAPE, 8, 0, &[VERSION; escape::MESSAGE_SIZE + 1]));
+    assert_eq!(host.receive_status(&mut ccid), [RDR_TO_PC_ESCAPE, 0, 8, 1, 1]);
+    assert!(!dispatch.poll(&mut [&mut info]));
+
+    assert!(host.nothing_received());
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use common::*;
+use usb_bus_mock::MockBus;
+use usb_device::{
+    bus::UsbBusAllocator,
+    class::UsbClass,
+    device::{UsbDeviceBuilder, UsbVidPid},
+    endpoint::EndpointType,
+    UsbDirection,
+};
+use usbd_ccid::Ccid;
+
+#[test]
+fn slot_changes_are_notified() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, _responder) = claim();
+    let mut ccid = Ccid::new(&allocator, requester, atr()).with_interrupt_endpoint(&allocator);
+    let mut device = UsbDeviceBuilder::new(&allocator, UsbVidPid(0x1209, 0x0001))
+        .max_packet_size_0(64)
+        .build();
+    let interrupt = bus.endpoint(EndpointType::Interrupt, UsbDirection::In);
+
+    // bulk OUT, bulk IN, and an interrupt IN endpoint of 8 bytes, polled every 32 ms
+    let endpoints = endpoint_descriptors(&bus, &mut device, &mut [&mut ccid]);
+    assert_eq!(endpoints.len(), 3);
+    assert!(endpoints.contains(&(u8::from(interrupt), 0x03, 8, 32)));
+
+    // after enumeration, the current state is signalled
+    ccid.reset();
+    ccid.poll();
+    assert_eq!(bus.receive(interrupt), Some(vec![RDR_TO_PC_NOTIFY_SLOT_CHANGE, 0b11]));
+    ccid.endpoint_in_complete(interrupt);
+    assert_eq!(bus.pending(interrupt), 0);
+
+    // card removed
+    ccid.set_icc_present(0, false);
+    assert_eq!(bus.receive(interrupt), Some(vec![RDR_TO_PC_NOTIFY_SLOT_CHANGE, 0b10]));
+    ccid.endpoint_in_complete(interrupt);
+
+    // no change, no notification
+    ccid.set_icc_present(0, false);
+    ccid.poll();
+    assert_eq!(bus.pending(interrupt), 0);
+
+    // notifications wait for the host to take the previous one,
+    // the last one reports the latest state
+    ccid.set_icc_present(0, true);
+    ccid.set_icc_present(0, false);
+    ccid.set_icc_present(0, true);
+    assert_eq!(bus.receive(interrupt), Some(vec![RDR_TO_PC_NOTIFY_SLOT_CHANGE, 0b11]));
+    ccid.endpoint_in_complete(interrupt);
+    assert_eq!(bus.receive(interrupt), Some(vec![RDR_TO_PC_NOTIFY_SLOT_CHANGE, 0b10]));
+    ccid.endpoint_in_complete(interrupt);
+    assert_eq!(bus.receive(interrupt), Some(vec![RDR_TO_PC_NOTIFY_SLOT_CHANGE, 0b11]));
+    ccid.endpoint_in_complete(interrupt);
+    assert_eq!(bus.pending(interrupt), 0);
+}
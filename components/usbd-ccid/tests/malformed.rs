@@ -0,0 +1,77 @@
+mod common;
+
+use common::*;
+use usb_bus_mock::MockBus;
+use usb_device::bus::UsbBusAllocator;
+use usbd_ccid::Ccid;
+
+#[test]
+fn malformed_messages_are_rejected() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, mut responder) = claim();
+    let mut ccid = Ccid::new(&allocator, requester, atr());
+    let host = Host::new(&bus);
+
+    // header cut short, but bSeq is there
+    host.send_packet(&mut ccid, &command(PC_TO_RDR_GET_SLOT_STATUS, 1, 0, &[])[..8]);
+    assert_eq!(host.receive_status(&mut ccid), [RDR_TO_PC_SLOT_STATUS, 0, 1, 1, 1]);
+    // too short to answer at all
+    host.send_packet(&mut ccid, &command(PC_TO_RDR_GET_SLOT_STATUS, 2, 0, &[])[..5]);
+    assert!(host.nothing_received());
+
+    // dwLength exceeds the data, and the transfer ended with a short packet
+    let mut xfr_block = command(PC_TO_RDR_XFR_BLOCK, 3, 0, &[0x00, 0xCA, 0x00, 0x00]);
+    xfr_block[1] = 10;
+    host.send_packet(&mut ccid, &xfr_block);
+    assert_eq!(host.receive_status(&mut ccid), [RDR_TO_PC_DATA_BLOCK, 0, 3, 1, 1]);
+    assert!(responder.take_request().is_none());
+
+    // dwLength falls short of the data of the next packet
+    let mut xfr_block = command(PC_TO_RDR_XFR_BLOCK, 4, 0, &[0x42; 54]);
+    xfr_block[1] = 60;
+    host.send_packet(&mut ccid, &xfr_block);
+    assert!(host.nothing_received());
+    host.send_packet(&mut ccid, &[0x42; 10]);
+    assert_eq!(host.receive_status(&mut ccid), [RDR_TO_PC_DATA_BLOCK, 0, 4, 1, 1]);
+    assert!(responder.take_request().is_none());
+
+    // only the data of an XfrBlock may span several packets, the rest is skipped
+    let mut get_slot_status = command(PC_TO_RDR_GET_SLOT_STATUS, 5, 0, &[0x00; 54]);
+    get_slot_status[1] = 100;
+    host.send_packet(&mut ccid, &get_slot_status);
+    assert_eq!(host.receive_status(&mut ccid), [RDR_TO_PC_SLOT_STATUS, 0, 5, 1, 1]);
+    host.send_packet(&mut ccid, &[0x00; 46]);
+    assert!(host.nothing_received());
+
+    // unknown message type
+    host.send(&mut ccid, &command(0x99, 6, 0, &[]));
+    assert_eq!(host.receive_status(&mut ccid), [RDR_TO_PC_SLOT_STATUS, 0, 6, 1, COMMAND_NOT_SUPPORTED]);
+
+    // bSlot beyond our single slot, with bmICCStatus = no ICC present
+    host.send(&mut ccid, &slot_command(PC_TO_RDR_GET_SLOT_STATUS, 2, 7, 0, &[]));
+    let response = host.receive(&mut ccid);
+    assert_eq!([response[5], response[6], response[7], response[8]], [2, 7, 0x42, SLOT_DOES_NOT_EXIST]);
+
+    // while the app is processing, other commands are answered as busy
+    host.send(&mut ccid, &command(PC_TO_RDR_XFR_BLOCK, 8, 0, &[0x00, 0xCA, 0x00, 0x00]));
+    assert_eq!(&responder.take_request().unwrap()[..], &[0x00, 0xCA, 0x00, 0x00]);
+    host.send(&mut ccid, &command(PC_TO_RDR_GET_SLOT_STATUS, 9, 0, &[]));
+    assert_eq!(host.receive_status(&mut ccid), [RDR_TO_PC_SLOT_STATUS, 0, 9, 1, CMD_SLOT_BUSY]);
+    host.send(&mut ccid, &command(PC_TO_RDR_XFR_BLOCK, 10, 0, &[0x00, 0xCB, 0x00, 0x00]));
+    assert_eq!(host.receive_status(&mut ccid), [RDR_TO_PC_DATA_BLOCK, 0, 10, 1, CMD_SLOT_BUSY]);
+
+    // without disturbing the command in progress
+    responder.respond(&Message::from_slice(&[0x90, 0x00]).unwrap()).ok().unwrap();
+    ccid.check_for_app_response();
+    let response = host.receive(&mut ccid);
+    assert_eq!(&response[..], &[RDR_TO_PC_DATA_BLOCK, 2, 0, 0, 0, 0, 8, 0, 0, 0, 0x90, 0x00]);
+
+    // and we are back in sync
+    host.send(&mut ccid, &command(PC_TO_RDR_XFR_BLOCK, 11, 0, &[0x00, 0xCA, 0x00, 0x00]));
+    respond(&mut responder, &[0x6A, 0x88]);
+    ccid.check_for_app_response();
+    let response = host.receive(&mut ccid);
+    assert_eq!(&response[..], &[RDR_TO_PC_DATA_BLOCK, 2, 0, 0, 0, 0, 11, 0, 0, 0, 0x6A, 0x88]);
+    assert!(host.nothing_received());
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use common::*;
+use interchange::Interchange;
+use usb_bus_mock::MockBus;
+use usb_device::{
+    bus::UsbBusAllocator,
+    device::{UsbDeviceBuilder, UsbVidPid},
+};
+use usbd_ccid::{Atr, Ccid};
+
+interchange::interchange! { SecondInterchange: (Message, Message) }
+
+#[test]
+fn two_slots() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, mut responder) = claim();
+    let (second_requester, mut second_responder) = SecondInterchange::claim().unwrap();
+    let second_atr = Atr::new().with_protocol(1).with_card_issuers_data(b"PIV");
+    let mut ccid = Ccid::new(&allocator, requester, atr()).with_slot(second_requester, second_atr.clone());
+    let mut device = UsbDeviceBuilder::new(&allocator, UsbVidPid(0x1209, 0x0001))
+        .max_packet_size_0(64)
+        .build();
+    let host = Host::new(&bus);
+    assert_eq!(ccid.num_slots(), 2);
+
+    // bMaxSlotIndex of the CCID class descriptor
+    let descriptor = bus.control_in(&mut device, &mut [&mut ccid], GET_CONFIGURATION_DESCRIPTOR, 255).unwrap();
+    let functional = descriptor.windows(2).position(|window| window == [0x36, 0x21]).unwrap();
+    assert_eq!(descriptor[functional + 4], 1);
+
+    // each slot has its own ATR
+    host.send(&mut ccid, &slot_command(PC_TO_RDR_ICC_POWER_ON, 0, 1, 0, &[]));
+    let response = host.receive(&mut ccid);
+    assert_eq!([response[5], response[6]], [0, 1]);
+    assert_eq!(&response[10..], &atr().to_bytes()[..]);
+    host.send(&mut ccid, &slot_command(PC_TO_RDR_ICC_POWER_ON, 1, 2, 0, &[]));
+    let response = host.receive(&mut ccid);
+    assert_eq!([response[5], response[6]], [1, 2]);
+    assert_eq!(&response[10..], &second_atr.to_bytes()[..]);
+    assert!(ccid.did_reset_session(0));
+    assert!(ccid.did_reset_session(1));
+    assert!(!ccid.did_reset_session(1));
+
+    // and its own app
+    host.send(&mut ccid, &slot_command(PC_TO_RDR_XFR_BLOCK, 1, 3, 0, &[0x00, 0xCB, 0x3F, 0xFF]));
+    assert!(responder.take_request().is_none());
+    respond(&mut second_responder, &[0x90, 0x00]);
+    ccid.check_for_app_response();
+    let response = host.receive(&mut ccid);
+    assert_eq!(&response[..], &[RDR_TO_PC_DATA_BLOCK, 2, 0, 0, 0, 1, 3, 0, 0, 0, 0x90, 0x00]);
+
+    // while slot 0 is processing, slot 1 is busy, but can be aborted
+    host.send(&mut ccid, &slot_command(PC_TO_RDR_XFR_BLOCK, 0, 4, 0, &[0x00, 0xCA, 0x00, 0x00]));
+    assert!(responder.take_request().is_some());
+    host.send(&mut ccid, &slot_command(PC_TO_RDR_GET_SLOT_STATUS, 1, 5, 0, &[]));
+    assert_eq!(host.receive_status(&mut ccid), [RDR_TO_PC_SLOT_STATUS, 1, 5, 1, CMD_SLOT_BUSY]);
+    assert!(bus.control_out(&mut device, &mut [&mut ccid], abort_request(1, 6), &[]));
+    host.send(&mut ccid, &slot_command(PC_TO_RDR_ABORT, 1, 6, 0, &[]));
+    assert_eq!(host.receive(&mut ccid), vec![RDR_TO_PC_SLOT_STATUS, 0, 0, 0, 0, 1, 6, 0, 0, 0]);
+
+    // which leaves the command of slot 0 alone
+    responder.respond(&Message::from_slice(&[0x61, 0x00]).unwrap()).ok().unwrap();
+    ccid.check_for_app_response();
+    let response = host.receive(&mut ccid);
+    assert_eq!(&response[..], &[RDR_TO_PC_DATA_BLOCK, 2, 0, 0, 0, 0, 4, 0, 0, 0, 0x61, 0x00]);
+
+    // card removal per slot, non-existent slots are ignored
+    ccid.set_icc_present(1, false);
+    ccid.set_icc_present(2, false);
+    assert!(ccid.did_reset_session(1));
+    assert!(!ccid.did_reset_session(2));
+    host.send(&mut ccid, &slot_command(PC_TO_RDR_XFR_BLOCK, 1, 7, 0, &[0x00, 0xCA, 0x00, 0x00]));
+    assert_eq!(host.receive_status(&mut ccid), [RDR_TO_PC_DATA_BLOCK, 1, 7, 1, ICC_MUTE]);
+    host.send(&mut ccid, &slot_command(PC_TO_RDR_GET_SLOT_STATUS, 0, 8, 0, &[]));
+    assert_eq!(host.receive(&mut ccid), vec![RDR_TO_PC_SLOT_STATUS, 0, 0, 0, 0, 0, 8, 0, 0, 0]);
+    assert!(host.nothing_received());
+}
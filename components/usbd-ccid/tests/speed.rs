@@ -0,0 +1,74 @@
+//! Only high-speed capable builds can switch: `cargo test --features highspeed-usb`.
+
+#![cfg(feature = "highspeed-usb")]
+
+mod common;
+
+use common::*;
+use usb_bus_mock::MockBus;
+use usb_device::{
+    bus::UsbBusAllocator,
+    device::{UsbDeviceBuilder, UsbVidPid},
+};
+use usbd_ccid::{constants::UsbSpeed, Ccid};
+
+#[test]
+fn packet_size_follows_bus_speed() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, mut responder) = claim();
+    let mut ccid = Ccid::new(&allocator, requester, atr());
+    let mut device = UsbDeviceBuilder::new(&allocator, UsbVidPid(0x1209, 0x0001))
+        .max_packet_size_0(64)
+        .build();
+    let host = Host::new(&bus);
+    let data: Vec<u8> = (0..200).collect();
+
+    // high speed until told otherwise: command and response fit a single packet
+    let endpoints = endpoint_descriptors(&bus, &mut device, &mut [&mut ccid]);
+    assert_eq!(endpoints.iter().map(|endpoint| endpoint.2).collect::<Vec<_>>(), [512, 512]);
+    host.send_packet(&mut ccid, &command(PC_TO_RDR_XFR_BLOCK, 1, 0, &data));
+    assert_eq!(&responder.take_request().unwrap()[..], &data[..]);
+    responder.respond(&Message::from_slice(&data).unwrap()).ok().unwrap();
+    ccid.check_for_app_response();
+    let response = host.receive(&mut ccid);
+    assert_eq!(&response[..10], &[RDR_TO_PC_DATA_BLOCK, 200, 0, 0, 0, 0, 1, 0, 0, 0]);
+    assert_eq!(&response[10..], &data[..]);
+    assert!(host.nothing_received());
+
+    // enumerated behind a full-speed hub
+    ccid.set_bus_speed(UsbSpeed::FullSpeed);
+    let endpoints = endpoint_descriptors(&bus, &mut device, &mut [&mut ccid]);
+    assert_eq!(endpoints.iter().map(|endpoint| endpoint.2).collect::<Vec<_>>(), [64, 64]);
+
+    // the command arrives in 64 byte packets, the response is chained in blocks of 54 bytes
+    host.send(&mut ccid, &command(PC_TO_RDR_XFR_BLOCK, 2, 0, &data));
+    assert_eq!(&responder.take_request().unwrap()[..], &data[..]);
+    responder.respond(&Message::from_slice(&data).unwrap()).ok().unwrap();
+    ccid.check_for_app_response();
+    let mut received = Vec::new();
+    let mut seq = 2;
+    loop {
+        let response = host.receive(&mut ccid);
+        assert!(response.len() <= 64);
+        assert_eq!(response[6], seq);
+        received.extend_from_slice(&response[10..]);
+        if response.len() == 64 {
+            assert!(host.receive(&mut ccid).is_empty());
+        }
+        // bChainParameter: the response ends with this block
+        if response[9] == 2 {
+            break;
+        }
+        seq += 1;
+        host.send(&mut ccid, &command(PC_TO_RDR_XFR_BLOCK, seq, 0x10, &[]));
+    }
+    assert_eq!(received, data);
+    assert_eq!(seq, 5);
+
+    // and back
+    ccid.set_bus_speed(UsbSpeed::HighSpeed);
+    let endpoints = endpoint_descriptors(&bus, &mut device, &mut [&mut ccid]);
+    assert_eq!(endpoints.iter().map(|endpoint| endpoint.2).collect::<Vec<_>>(), [512, 512]);
+    assert!(host.nothing_received());
+}
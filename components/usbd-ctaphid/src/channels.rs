@@ -0,0 +1,88 @@
+//! Allocated CTAPHID channels, and the CTAPHID_LOCK held by one of them.
+//!
+//! Channels are allocated by CTAPHID_INIT on the broadcast channel.  The table
+//! is bounded: when it is full, the least recently used channel is dropped.
+//! Timestamps are the milliseconds passed to `Pipe::check_timeout`.
+
+use embedded_time::duration::{Extensions, Milliseconds};
+
+use crate::{
+    constants::{MAX_CHANNELS, MAX_LOCK_SECONDS},
+    types::{elapsed, Instant},
+};
+
+/// An allocated channel.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Channel {
+    pub cid: u32,
+    /// When the channel was allocated.
+    pub allocated: Instant,
+    /// When the channel last started a transaction.
+    pub last_used: Instant,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct Lock {
+    cid: u32,
+    since: Instant,
+    duration: Milliseconds,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Channels {
+    channels: heapless::Vec<Channel, MAX_CHANNELS>,
+    lock: Option<Lock>,
+}
+
+impl Channels {
+    pub(crate) fn contains(&self, cid: u32) -> bool {
+        self.channels.iter().any(|channel| channel.cid == cid)
+    }
+
+    /// Adds a channel, dropping the least recently used one if the table is full.
+    pub(crate) fn allocate(&mut self, cid: u32, now: Instant) {
+        if self.channels.is_full() {
+            let locked = self.lock.map(|lock| lock.cid);
+            let evict = self.channels.iter()
+                .enumerate()
+                .filter(|(_, channel)| Some(channel.cid) != locked)
+                .max_by_key(|(_, channel)| elapsed(now, channel.last_used))
+                .map(|(i, _)| i)
+                .unwrap();
+            info!("dropping channel {:08X}", self.channels[evict].cid);
+            self.channels.swap_remove(evict);
+        }
+        self.channels.push(Channel { cid, allocated: now, last_used: now }).ok();
+    }
+
+    pub(crate) fn touch(&mut self, cid: u32, now: Instant) {
+        if let Some(channel) = self.channels.iter_mut().find(|channel| channel.cid == cid) {
+            channel.last_used = now;
+        }
+    }
+
+    /// Locks the device to the channel for the given time, or releases the lock for 0 seconds.
+    ///
+    /// Returns false if the time exceeds the maximum of 10 seconds.
+    pub(crate) fn lock(&mut self, cid: u32, seconds: u8, now: Instant) -> bool {
+        if seconds > MAX_LOCK_SECONDS {
+            return false;
+        }
+        self.lock = match seconds {
+            0 => None,
+            seconds => Some(Lock { cid, since: now, duration: (seconds as u32 * 1000).milliseconds() }),
+        };
+        true
+    }
+
+    /// The channel holding an unexpired lock, if any.
+    pub(crate) fn locked_by(&mut self, now: Instant) -> Option<u32> {
+        if let Some(lock) = self.lock {
+            if elapsed(now, lock.since) >= lock.duration {
+                info!("lock of {:08X} expired", lock.cid);
+                self.lock = None;
+            }
+        }
+        self.lock.map(|lock| lock.cid)
+    }
+}
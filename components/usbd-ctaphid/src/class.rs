@@ -2,11 +2,12 @@
 // use core::convert::TryFrom as _;
 
 use interchange::Requester;
-use embedded_time::duration::Extensions;
+use embedded_time::duration::{Extensions, Milliseconds};
+use rand_core::{CryptoRng, RngCore, SeedableRng};
 
 use crate::{
-    types::Status,
-    constants::{INTERRUPT_POLL_MILLISECONDS, PACKET_SIZE},
+    types::{KeepaliveStatus, Status},
+    constants::{INTERRUPT_POLL_MILLISECONDS, KEEPALIVE_PERIOD_MILLISECONDS, PACKET_SIZE},
     pipe::Pipe,
 };
 
@@ -25,13 +26,20 @@ use usb_device::{
 pub struct CtapHid<'alloc, Bus: UsbBus> {
     interface: InterfaceNumber,
     pipe: Pipe<'alloc, Bus>,
+    keepalive_period: Milliseconds,
 }
 
 impl<'alloc, Bus> CtapHid<'alloc, Bus>
 where
 	Bus: UsbBus
 {
-	pub fn new(allocate: &'alloc UsbBusAllocator<Bus>, interchange: Requester<HidInterchange>, initial_milliseconds: u32)
+	/// The RNG seeds the generator of the channel IDs assigned by CTAPHID_INIT.
+	pub fn new<R: CryptoRng + RngCore>(
+        allocate: &'alloc UsbBusAllocator<Bus>,
+        interchange: Requester<HidInterchange>,
+        initial_milliseconds: u32,
+        rng: &mut R,
+    )
         -> Self
     {
         // 64 bytes, interrupt endpoint polled every 5 milliseconds
@@ -41,11 +49,16 @@ where
         let write_endpoint: EndpointIn<'alloc, Bus> =
             allocate.interrupt(PACKET_SIZE as u16, INTERRUPT_POLL_MILLISECONDS);
 
-        let pipe = Pipe::new(read_endpoint, write_endpoint, interchange, initial_milliseconds);
+        let mut seed = <chacha20::ChaCha8Rng as SeedableRng>::Seed::default();
+        rng.fill_bytes(&mut seed);
+        let rng = chacha20::ChaCha8Rng::from_seed(seed);
+
+        let pipe = Pipe::new(read_endpoint, write_endpoint, interchange, initial_milliseconds, rng);
 
         Self {
             interface: allocate.interface(),
             pipe,
+            keepalive_period: KEEPALIVE_PERIOD_MILLISECONDS.milliseconds(),
         }
     }
 
@@ -72,6 +85,14 @@ where
         self
     }
 
+    /// Period of the keepalive messages while the app processes a CBOR request.
+    ///
+    /// Defaults to the 100 milliseconds CTAP 2.1 asks for, some hosts time out much later.
+    pub fn with_keepalive_period(mut self, period: Milliseconds) -> Self {
+        self.keepalive_period = period;
+        self
+    }
+
     // implement DerefMut<Target = Pipe> instead
     pub fn pipe(&mut self) -> &mut Pipe<'alloc, Bus> {
         &mut self.pipe
@@ -90,16 +111,22 @@ where
     /// Indicate whether or not a task should be scheduled to send keepalive messages.
     pub fn did_start_processing(&mut self) -> Status {
         if self.pipe.did_start_processing() {
-            Status::ReceivedData(250.milliseconds())
+            Status::ReceivedData(self.keepalive_period)
         } else {
             Status::Idle
         }
     }
 
-    /// Send a keep alive message with 1 of 2 possible statuses.
-    pub fn send_keepalive(&mut self, is_waiting_for_user_presence: bool) -> Status {
-        if self.pipe.send_keepalive(is_waiting_for_user_presence) {
-            Status::ReceivedData(250.milliseconds())
+    /// Indicate whether a CTAPHID_CANCEL aborted the request the app was processing,
+    /// e.g. to stop waiting for user presence.
+    pub fn did_cancel_processing(&mut self) -> bool {
+        self.pipe.did_cancel_processing()
+    }
+
+    /// Send a keep alive message with the status of the app, e.g. waiting for user presence.
+    pub fn send_keepalive(&mut self, status: KeepaliveStatus) -> Status {
+        if self.pipe.send_keepalive(status) {
+            Status::ReceivedData(self.keepalive_period)
         } else {
             Status::Idle
         }
@@ -5,4 +5,15 @@ pub const PACKET_SIZE: usize = 64;
 
 // 1200
 // pub const MESSAGE_SIZE: usize = ctap_types::sizes::REALISTIC_MAX_MESSAGE_SIZE;
-pub const MESSAGE_SIZE: usize = 3072;
+/// Maximum message size of the CTAPHID framing: an initialization packet and
+/// 128 continuation packets, 7609 bytes.
+pub const MESSAGE_SIZE: usize = PACKET_SIZE - 7 + 128 * (PACKET_SIZE - 5);
+
+/// Default period of the CTAPHID_KEEPALIVE messages, cf. `CtapHid::with_keepalive_period`.
+pub const KEEPALIVE_PERIOD_MILLISECONDS: u32 = 100;
+
+/// Number of channels kept, the least recently used is dropped when full.
+pub const MAX_CHANNELS: usize = 8;
+
+/// Maximum time a CTAPHID_LOCK may be held.
+pub const MAX_LOCK_SECONDS: u8 = 10;
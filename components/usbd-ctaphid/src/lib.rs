@@ -16,6 +16,7 @@ generate_macros!();
 
 // pub mod authenticator;
 
+pub mod channels;
 pub mod constants;
 pub mod class;
 pub use class::CtapHid;
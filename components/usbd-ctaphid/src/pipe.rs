@@ -7,7 +7,10 @@ an *initialization* packet, followed by zero or more *continuation* packets.
 
 In the case of multiple clients, the first to get through its initialization
 packet in device idle state locks the device for other channels (they will
-receive busy errors).
+receive busy errors).  Beyond that, a channel may lock the device for up to
+10 seconds with CTAPHID_LOCK, e.g. for a sequence of transactions.
+
+Only channels allocated with CTAPHID_INIT (on the broadcast channel) are accepted.
 
 No state is maintained between transactions.
 */
@@ -24,6 +27,8 @@ use ctap_types::Error as AuthenticatorError;
 
 use interchange::Requester;
 
+use rand_core::RngCore;
+
 // use serde::Serialize;
 use usb_device::{
     bus::{UsbBus},
@@ -33,22 +38,30 @@ use usb_device::{
 };
 
 use crate::{
+    channels::Channels,
     constants::{
         // 3072
         MESSAGE_SIZE,
         // 64
         PACKET_SIZE,
     },
-    types::KeepaliveStatus,
+    types::{elapsed, Instant, KeepaliveStatus},
 };
 
+use embedded_time::duration::Extensions;
+
+const BROADCAST_CHANNEL: u32 = 0xffff_ffff;
+
+// large enough for the INIT response, the longest payload of the pipe itself
+const BUFFER_SIZE: usize = 17;
+
 /// The actual payload of given length is dealt with separately
 #[derive(Copy,Clone,Debug,Eq,PartialEq)]
 pub struct Request {
     channel: u32,
     command: Command,
     length: u16,
-    timestamp: u32,
+    timestamp: Instant,
 }
 
 /// The actual payload of given length is dealt with separately
@@ -57,6 +70,18 @@ pub struct Response {
     channel: u32,
     command: Command,
     length: u16,
+    payload: Payload,
+}
+
+/// Where the payload of a response is kept while it is sent.
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+enum Payload {
+    // the pipe's own buffer
+    Pipe,
+    // the request in the interchange, echoed by PING
+    Request,
+    // the app's response in the interchange
+    App,
 }
 
 impl Response {
@@ -65,6 +90,7 @@ impl Response {
             channel: request.channel,
             command: request.command,
             length: size as u16,
+            payload: Payload::Pipe,
         }
     }
 
@@ -73,6 +99,7 @@ impl Response {
             channel: request.channel,
             command: ctaphid_dispatch::command::Command::Error,
             length: 1,
+            payload: Payload::Pipe,
         }
     }
 }
@@ -131,22 +158,28 @@ pub struct Pipe<'alloc, Bus: UsbBus> {
 
     interchange: Requester<HidInterchange>,
 
-    // shared between requests and responses, due to size
-    buffer: [u8; MESSAGE_SIZE],
+    // payload of the requests handled by the pipe and of its own responses;
+    // longer messages (to and from the app, and PING) stay in the interchange
+    buffer: [u8; BUFFER_SIZE],
 
-    // we assign channel IDs one by one, this is the one last assigned
-    // TODO: move into "app"
-    last_channel: u32,
+    // channel IDs are random, so they can't be guessed by other clients
+    rng: chacha20::ChaCha8Rng,
+
+    // allocated channels, and the lock
+    channels: Channels,
 
     // Indicator of implemented commands in INIT response.
     pub(crate) implements: u8,
 
     // timestamp that gets used for timing out CID's
-    pub(crate) last_milliseconds: u32,
+    pub(crate) now: Instant,
 
     // a "read once" indicator if now we're waiting on the application processing
     started_processing: bool,
 
+    // a "read once" indicator if the application processing was canceled
+    canceled_processing: bool,
+
     needs_keepalive: bool,
 
     pub(crate) version: crate::Version,
@@ -163,6 +196,7 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
         write_endpoint: EndpointIn<'alloc, Bus>,
         interchange: Requester<HidInterchange>,
         initial_milliseconds: u32,
+        rng: chacha20::ChaCha8Rng,
     ) -> Self
     {
         Self {
@@ -170,12 +204,14 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
             write_endpoint,
             state: State::Idle,
             interchange,
-            buffer: [0u8; MESSAGE_SIZE],
-            last_channel: 0,
+            buffer: [0u8; BUFFER_SIZE],
+            rng,
+            channels: Channels::default(),
             // Default to nothing implemented.
             implements: 0x80,
-            last_milliseconds: initial_milliseconds,
+            now: Instant::new(initial_milliseconds),
             started_processing: false,
+            canceled_processing: false,
             needs_keepalive: false,
             version: Default::default(),
         }
@@ -207,13 +243,10 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
         // Remove response if it's there
         if let Some(_response) = self.interchange.take_response() {
         } else {
-            // Cancel if there's a request or processing
-            match self.interchange.state() {
-                interchange::State::Requested |
-                interchange::State::BuildingResponse => {
-                    self.interchange.cancel().expect("canceled");
-                }
-                _ => {}
+            // Cancel if the app did not take the request yet; the dispatcher does not
+            // acknowledge cancellations, so a response being built is dropped once it arrives
+            if self.interchange.state() == interchange::State::Requested {
+                self.interchange.cancel().expect("canceled");
             }
         }
 
@@ -265,19 +298,43 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
             let command_number = packet[4] & !0x80;
             // info_now!("command number {}", command_number);
 
-            let command = match Command::try_from(command_number) {
-                Ok(command) => command,
-                // `solo ls` crashes here as it uses command 0x86
-                Err(_) => {
-                    info!("Ignoring invalid command.");
-                    return; },
-            };
+            // vendor commands (0x40..=0x7F) are passed on to the apps
+            let command = Command::try_from(command_number);
 
             // can't actually fail
             let length = u16::from_be_bytes(packet[5..][..2].try_into().unwrap());
 
-            let timestamp = self.last_milliseconds;
-            let current_request = Request { channel, command, length, timestamp};
+            let timestamp = self.now;
+            let current_request = Request {
+                channel,
+                // only used to address the error response
+                command: command.unwrap_or(Command::Error),
+                length,
+                timestamp,
+            };
+
+            if let Some(owner) = self.channels.locked_by(self.now) {
+                if channel != owner {
+                    info!("locked by other channel.");
+                    self.send_error_now(current_request, AuthenticatorError::ChannelBusy);
+                    return;
+                }
+            }
+
+            if channel != BROADCAST_CHANNEL && !self.channels.contains(channel) {
+                info!("unknown channel.");
+                self.send_error_now(current_request, AuthenticatorError::InvalidChannel);
+                return;
+            }
+
+            let command = match command {
+                Ok(command) => command,
+                Err(_) => {
+                    info!("invalid command {}.", command_number);
+                    self.send_error_now(current_request, AuthenticatorError::InvalidCommand);
+                    return;
+                }
+            };
 
             if !(self.state == State::Idle) {
                 let request = match self.state {
@@ -295,6 +352,12 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                 if packet[4] == 0x86 {
                     info!("Resyncing!");
                     self.cancel_ongoing_activity();
+                } else if command == Command::Cancel {
+                    if channel == request.channel {
+                        self.cancel_request(request);
+                    }
+                    // no response to CANCEL itself
+                    return;
                 } else {
                     if channel == request.channel {
                         info!("Expected seq");
@@ -315,11 +378,16 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                 return;
             }
 
+            if payload_in_interchange(command) && !self.start_app_request(current_request) {
+                return;
+            }
+
             if length > PACKET_SIZE as u16 - 7 {
                 // store received part of payload,
                 // prepare for continuation packets
-                self.buffer[..PACKET_SIZE - 7]
-                    .copy_from_slice(&packet[7..]);
+                if !self.store_payload(current_request, 0, &packet[7..]) {
+                    return;
+                }
                 self.state = State::Receiving((current_request, {
                     let state = MessageState::default();
                     // info_now!("got {} so far", state.transmitted);
@@ -329,9 +397,9 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                 return;
             } else {
                 // request fits in one packet
-                self.buffer[..length as usize]
-                    .copy_from_slice(&packet[7..][..length as usize]);
-                self.dispatch_request(current_request);
+                if self.store_payload(current_request, 0, &packet[7..][..length as usize]) {
+                    self.dispatch_request(current_request);
+                }
                 return;
             }
         } else {
@@ -361,17 +429,18 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                         // info_now!("transmitted {} + (PACKET_SIZE - 5) < {}",
                         //           message_state.transmitted, payload_length);
                         // store received part of payload
-                        self.buffer[message_state.transmitted..][..PACKET_SIZE - 5]
-                            .copy_from_slice(&packet[5..]);
+                        if !self.store_payload(request, message_state.transmitted, &packet[5..]) {
+                            return;
+                        }
                         message_state.absorb_packet();
                         self.state = State::Receiving((request, message_state));
                         // info_now!("absorbed packet, awaiting next");
                         return;
                     } else {
                         let missing = request.length as usize - message_state.transmitted;
-                        self.buffer[message_state.transmitted..payload_length]
-                            .copy_from_slice(&packet[5..][..missing]);
-                        self.dispatch_request(request);
+                        if self.store_payload(request, message_state.transmitted, &packet[5..][..missing]) {
+                            self.dispatch_request(request);
+                        }
                     }
                 },
                 _ => {
@@ -383,25 +452,76 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
         }
     }
 
+    /// Prepares the interchange for the payload of a request to the app, or of a PING.
+    fn start_app_request(&mut self, request: Request) -> bool {
+        if self.interchange.state() == interchange::State::Responded {
+            info!("dumping stale response");
+            self.release_response();
+        }
+        // a request not passed on to the app (a PING, or an aborted one) is overwritten
+        if !matches!(self.interchange.state(), interchange::State::Idle | interchange::State::BuildingRequest) {
+            // busy
+            info_now!("STATE: {:?}", self.interchange.state());
+            info!("can't handle more than one authenticator request at a time.");
+            self.send_error_now(request, AuthenticatorError::ChannelBusy);
+            return false;
+        }
+
+        let fits = match self.interchange.request_mut() {
+            Some((command, message)) => {
+                *command = request.command;
+                message.clear();
+                request.length as usize <= message.capacity()
+            }
+            None => false,
+        };
+        if !fits {
+            info!("Error message too big for app.");
+            self.send_error_now(request, AuthenticatorError::InvalidLength);
+        }
+        fits
+    }
+
+    /// Stores received payload at given offset, in the buffer or the app's request.
+    fn store_payload(&mut self, request: Request, offset: usize, data: &[u8]) -> bool {
+        if !payload_in_interchange(request.command) {
+            // only the first bytes are used, the length is checked when dispatching
+            if let Some(buffer) = self.buffer.get_mut(offset..) {
+                let length = core::cmp::min(buffer.len(), data.len());
+                buffer[..length].copy_from_slice(&data[..length]);
+            }
+            return true;
+        }
+        let extended = match self.interchange.request_mut() {
+            Some((_, message)) => message.len() == offset && message.extend_from_slice(data).is_ok(),
+            None => false,
+        };
+        if !extended {
+            info!("could not extend request by {} bytes", data.len());
+            self.start_sending_error(request, AuthenticatorError::InvalidLength);
+        }
+        extended
+    }
+
     pub fn check_timeout(&mut self, milliseconds: u32) {
         // At any point the RP application could crash or something,
         // so its up to the device to timeout those transactions.
-        let last = self.last_milliseconds;
-        self.last_milliseconds = milliseconds;
+        // `Instant` compares correctly across the wraparound of the milliseconds
+        let last = self.now;
+        let now = Instant::new(milliseconds);
+        self.now = now;
         match &mut self.state {
             State::Receiving((request, _message_state)) => {
-                if (milliseconds - last) > 200 {
+                // going back in time counts as a lapse, too
+                if now < last || elapsed(now, last) > 200.milliseconds() {
                     // If there's a lapse in `check_timeout(...)` getting called (e.g. due to logging),
                     // this could lead to inaccurate timestamps on requests.  So we'll
                     // just "forgive" requests temporarily if this happens.
-                    debug!("lapse in hid check.. {} {} {}", request.timestamp, milliseconds, last);
-                    request.timestamp = milliseconds;
+                    debug!("lapse in hid check.. {:?} {:?} {:?}", request.timestamp, now, last);
+                    request.timestamp = now;
                 }
-                // compare keeping in mind of possible overflow in timestamp.
-                else if (milliseconds > request.timestamp && (milliseconds - request.timestamp) > 550)
-                || (milliseconds < request.timestamp && milliseconds > 550)
-                {
-                    debug!("Channel timeout. {}, {}, {}", request.timestamp, milliseconds, last);
+                else if elapsed(now, request.timestamp) > 550.milliseconds() {
+                    debug!("Channel timeout. {:?}, {:?}, {:?}", request.timestamp, now, last);
                     let req = *request;
                     self.start_sending_error(req, AuthenticatorError::Timeout);
                 }
@@ -410,12 +530,41 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
         };
     }
 
+    /// CTAPHID_CANCEL of the transaction in progress: the app is told, and a cancelled
+    /// CBOR request gets a CTAP2_ERR_KEEPALIVE_CANCEL response straight away.
+    fn cancel_request(&mut self, request: Request) {
+        info!("cancel");
+        let was_processing = matches!(self.state, State::WaitingOnAuthenticator(_));
+        self.cancel_ongoing_activity();
+        if was_processing {
+            self.started_processing = false;
+            self.canceled_processing = true;
+            if request.command == Command::Cbor {
+                self.buffer[0] = AuthenticatorError::KeepaliveCancel as u8;
+                let response = Response::from_request_and_size(request, 1);
+                self.start_sending(response);
+            }
+        }
+    }
+
+    fn allocate_channel(&mut self) -> u32 {
+        loop {
+            let cid = self.rng.next_u32();
+            if cid != 0 && cid != BROADCAST_CHANNEL && !self.channels.contains(cid) {
+                self.channels.allocate(cid, self.now);
+                return cid;
+            }
+        }
+    }
+
     fn dispatch_request(&mut self, request: Request) {
 
+        self.channels.touch(request.channel, self.now);
+
         match request.command {
             Command::Init => {}
             _ => {
-                if request.channel == 0xffffffff {
+                if request.channel == BROADCAST_CHANNEL {
                     self.start_sending_error(request, AuthenticatorError::InvalidChannel);
                     return;
                 }
@@ -432,23 +581,28 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                         self.start_sending_error(request, AuthenticatorError::InvalidChannel);
                     },
 
-                    // broadcast channel ID - request for assignment
+                    // broadcast channel ID - request for assignment,
+                    // allocated channel ID - resynchronization, keeping the channel
                     cid => {
                         if request.length != 8 {
                             // error
                             info!("Invalid length for init.  ignore.");
                         } else {
-                            self.last_channel += 1;
+                            let assigned = if cid == BROADCAST_CHANNEL {
+                                self.allocate_channel()
+                            } else {
+                                cid
+                            };
                             // info_now!(
-                            //     "assigned channel {}", self.last_channel);
+                            //     "assigned channel {}", assigned);
                             let _nonce = &self.buffer[..8];
                             let response = Response {
                                 channel: cid,
-                                command: request.command,
-                                length: 17,
+                                payload: Payload::Pipe,
+                                ..Response::from_request_and_size(request, 17)
                             };
 
-                            self.buffer[8..12].copy_from_slice(&self.last_channel.to_be_bytes());
+                            self.buffer[8..12].copy_from_slice(&assigned.to_be_bytes());
                             // CTAPHID protocol version
                             self.buffer[12] = 2;
                             // major device version number
@@ -470,23 +624,43 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
             },
 
             Command::Ping => {
-                let response = Response::from_request_and_size(request, request.length as usize);
+                let response = Response {
+                    payload: Payload::Request,
+                    ..Response::from_request_and_size(request, request.length as usize)
+                };
                 self.start_sending(response);
             },
 
+            Command::Cancel => {
+                // nothing to cancel, and no response
+                info!("ignoring cancel outside of transaction.");
+            },
+
+            // only sent by the device
+            Command::Error | Command::KeepAlive => {
+                self.start_sending_error(request, AuthenticatorError::InvalidCommand);
+            },
+
+            Command::Lock => {
+                if request.length != 1 {
+                    self.start_sending_error(request, AuthenticatorError::InvalidLength);
+                } else if self.channels.lock(request.channel, self.buffer[0], self.now) {
+                    let response = Response::from_request_and_size(request, 0);
+                    self.start_sending(response);
+                } else {
+                    info!("lock time exceeds 10 seconds.");
+                    self.start_sending_error(request, AuthenticatorError::InvalidParameter);
+                }
+            },
+
             _ => {
                 if request.command == Command::Cbor {
                     self.needs_keepalive = true;
                 } else {
                     self.needs_keepalive = false;
                 }
-                if self.interchange.state() == interchange::State::Responded {
-                    info!("dumping stale response");
-                    self.interchange.take_response();
-                }
-                match self.interchange.request(
-                    &(request.command, heapless::Vec::from_slice(&self.buffer[..request.length as usize]).unwrap())
-                ) {
+                // the payload is in place already
+                match self.interchange.send_request() {
                     Ok(_) => {
                         self.state = State::WaitingOnAuthenticator(request);
                         self.started_processing = true;
@@ -511,7 +685,11 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
         }
     }
 
-    pub fn send_keepalive(&mut self, is_waiting_for_user_presence: bool) -> bool {
+    pub fn did_cancel_processing(&mut self) -> bool {
+        core::mem::replace(&mut self.canceled_processing, false)
+    }
+
+    pub fn send_keepalive(&mut self, status: KeepaliveStatus) -> bool {
         if let State::WaitingOnAuthenticator(request) = &self.state {
             if !self.needs_keepalive {
                 // let response go out normally in idle loop
@@ -527,11 +705,7 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                 packet[4] = 0x80 | 0x3B;
                 packet[5..7].copy_from_slice(&1u16.to_be_bytes());
 
-                if is_waiting_for_user_presence {
-                    packet[7] = KeepaliveStatus::UpNeeded as u8;
-                } else {
-                    packet[7] = KeepaliveStatus::Processing as u8;
-                }
+                packet[7] = status as u8;
 
                 self.write_endpoint.write(&packet).ok();
 
@@ -546,30 +720,42 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
     #[inline(never)]
     pub fn handle_response(&mut self) {
         if let State::WaitingOnAuthenticator(request) = self.state {
+            // the response is sent from the interchange, see `copy_payload`
+            let length = match self.interchange.response() {
+                Ok(Ok(message)) => Ok(message.len()),
+                Ok(Err(ctaphid_dispatch::app::Error::InvalidCommand)) => {
+                    info!("Got waiting reply from authenticator??");
+                    Err(Some(AuthenticatorError::InvalidCommand))
+                }
+                Ok(Err(ctaphid_dispatch::app::Error::InvalidLength)) => {
+                    info!("Error, payload needed app command.");
+                    Err(Some(AuthenticatorError::InvalidLength))
+                }
+                Ok(Err(ctaphid_dispatch::app::Error::NoResponse)) => {
+                    info!("Got waiting noresponse from authenticator??");
+                    Err(None)
+                }
+                Err(_) => return,
+            };
 
-
-            if let Some(response) = self.interchange.take_response() {
-                match response {
-
-                    Err(ctaphid_dispatch::app::Error::InvalidCommand) => {
-                        info!("Got waiting reply from authenticator??");
-                        self.start_sending_error(request, AuthenticatorError::InvalidCommand);
-
-                    }
-                    Err(ctaphid_dispatch::app::Error::InvalidLength) => {
-                        info!("Error, payload needed app command.");
-                        self.start_sending_error(request, AuthenticatorError::InvalidLength);
-                    }
-                    Err(ctaphid_dispatch::app::Error::NoResponse) => {
-                        info!("Got waiting noresponse from authenticator??");
-                    }
-
-                    Ok(message) => {
-                        info!("Got {} bytes response from authenticator, starting send", message.len());
-                        let response = Response::from_request_and_size(request, message.len());
-                        self.buffer[..message.len()]
-                            .copy_from_slice(&message);
-                        self.start_sending(response);
+            match length {
+                Ok(length) if length > MESSAGE_SIZE => {
+                    info!("Error, response of {} bytes too big.", length);
+                    self.release_response();
+                    self.start_sending_error(request, AuthenticatorError::InvalidLength);
+                }
+                Ok(length) => {
+                    info!("Got {} bytes response from authenticator, starting send", length);
+                    let response = Response {
+                        payload: Payload::App,
+                        ..Response::from_request_and_size(request, length)
+                    };
+                    self.start_sending(response);
+                }
+                Err(error) => {
+                    self.release_response();
+                    if let Some(error) = error {
+                        self.start_sending_error(request, error);
                     }
                 }
             }
@@ -577,6 +763,12 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
 
     }
 
+    // Frees the interchange of the app's response, once it is sent or dropped.
+    #[inline(never)]
+    fn release_response(&mut self) {
+        self.interchange.take_response();
+    }
+
     fn start_sending(&mut self, response: Response) {
         self.state = State::WaitingToSend(response);
         self.maybe_write_packet();
@@ -601,6 +793,42 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
         self.buffer[0] = last_first_byte;
     }
 
+    // The response can't be delivered (e.g. the endpoint is stalled, or a short write),
+    // so it is dropped, and we are ready for the next request.
+    fn abort_sending(&mut self, response: Response, error: UsbError) {
+        info_now!("dropping response on {:08X}, write failed: {:?}", response.channel, error);
+        self.finish_sending(response);
+    }
+
+    fn finish_sending(&mut self, response: Response) {
+        self.state = State::Idle;
+        if response.payload == Payload::App {
+            self.release_response();
+        }
+    }
+
+    // Copies the response payload at `offset` into `data`, failing if it is gone.
+    fn copy_payload(&mut self, response: &Response, offset: usize, data: &mut [u8]) -> bool {
+        let payload: &[u8] = match response.payload {
+            Payload::Pipe => &self.buffer,
+            Payload::Request => match self.interchange.request_mut() {
+                Some((_, message)) => &message[..],
+                None => &[],
+            },
+            Payload::App => match self.interchange.response() {
+                Ok(Ok(message)) => &message[..],
+                _ => &[],
+            },
+        };
+        match payload.get(offset..offset + data.len()) {
+            Some(chunk) => {
+                data.copy_from_slice(chunk);
+                true
+            }
+            None => false,
+        }
+    }
+
     // called from poll, and when a packet has been sent
     #[inline(never)]
     pub(crate) fn maybe_write_packet(&mut self) {
@@ -616,12 +844,14 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                 packet[5..7].copy_from_slice(&response.length.to_be_bytes());
 
                 let fits_in_one_packet = 7 + response.length as usize <= PACKET_SIZE;
-                if fits_in_one_packet {
-                    packet[7..][..response.length as usize]
-                        .copy_from_slice( &self.buffer[..response.length as usize]);
-                    self.state = State::Idle;
+                let chunk = if fits_in_one_packet {
+                    &mut packet[7..][..response.length as usize]
                 } else {
-                    packet[7..].copy_from_slice(&self.buffer[..PACKET_SIZE - 7]);
+                    &mut packet[7..]
+                };
+                if !self.copy_payload(&response, 0, chunk) {
+                    self.abort_sending(response, UsbError::InvalidState);
+                    return;
                 }
 
                 // try actually sending
@@ -635,14 +865,13 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                         // this shouldn't happen probably
                         info!("hid usb WouldBlock");
                     },
-                    Err(_) => {
-                        // info_now!("weird USB errrorrr");
-                        panic!("unexpected error writing packet!");
+                    Err(error) => {
+                        self.abort_sending(response, error);
                     },
                     Ok(PACKET_SIZE) => {
                         // goodie, this worked
                         if fits_in_one_packet {
-                            self.state = State::Idle;
+                            self.finish_sending(response);
                             // info_now!("StartSent {} bytes, idle again", response.length);
                             // info_now!("IDLE again");
                         } else {
@@ -654,8 +883,7 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                         }
                     },
                     Ok(_) => {
-                        // info_now!("short write");
-                        panic!("unexpected size writing packet!");
+                        self.abort_sending(response, UsbError::BufferOverflow);
                     },
                 };
             },
@@ -669,12 +897,14 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                 let sent = message_state.transmitted;
                 let remaining = response.length as usize - sent;
                 let last_packet = 5 + remaining <= PACKET_SIZE;
-                if last_packet {
-                    packet[5..][..remaining].copy_from_slice(
-                        &self.buffer[message_state.transmitted..][..remaining]);
+                let chunk = if last_packet {
+                    &mut packet[5..][..remaining]
                 } else {
-                    packet[5..].copy_from_slice(
-                        &self.buffer[message_state.transmitted..][..PACKET_SIZE - 5]);
+                    &mut packet[5..]
+                };
+                if !self.copy_payload(&response, sent, chunk) {
+                    self.abort_sending(response, UsbError::InvalidState);
+                    return;
                 }
 
                 // try actually sending
@@ -689,14 +919,13 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                         // info_now!("can't send seq {}, write endpoint busy",
                         //           message_state.next_sequence);
                     },
-                    Err(_) => {
-                        // info_now!("weird USB error");
-                        panic!("unexpected error writing packet!");
+                    Err(error) => {
+                        self.abort_sending(response, error);
                     },
                     Ok(PACKET_SIZE) => {
                         // goodie, this worked
                         if last_packet {
-                            self.state = State::Idle;
+                            self.finish_sending(response);
                             // info_now!("in IDLE state after {:?}", &message_state);
                         } else {
                             message_state.absorb_packet();
@@ -708,7 +937,7 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                     },
                     Ok(_) => {
                         debug!("short write");
-                        panic!("unexpected size writing packet!");
+                        self.abort_sending(response, UsbError::BufferOverflow);
                     },
                 };
             },
@@ -720,4 +949,14 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
     }
 }
 
+// Commands answered by the pipe itself, the others are passed on to the app.
+fn handled_by_pipe(command: Command) -> bool {
+    matches!(command,
+        Command::Init | Command::Ping | Command::Lock | Command::Cancel |
+        Command::Error | Command::KeepAlive)
+}
 
+// Payload kept in the interchange rather than the pipe's buffer, PING may be as long as app messages.
+fn payload_in_interchange(command: Command) -> bool {
+    command == Command::Ping || !handled_by_pipe(command)
+}
@@ -1,5 +1,34 @@
 
-use embedded_time::duration::Milliseconds;
+use core::{
+    convert::TryFrom,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use embedded_time::{clock, duration::Milliseconds, fraction::Fraction};
+
+/// The clock of the milliseconds passed to `CtapHid::check_timeout`,
+/// which wrap around after 49.7 days.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Uptime;
+
+impl embedded_time::Clock for Uptime {
+    type T = u32;
+    const SCALING_FACTOR: Fraction = Fraction::new(1, 1_000);
+
+    // the runner passes the time in
+    fn try_now(&self) -> Result<Instant, clock::Error> {
+        Err(clock::Error::NotRunning)
+    }
+}
+
+pub type Instant = embedded_time::Instant<Uptime>;
+
+/// Time from `since` to `now`, correct across wraparound, zero if `now` is earlier.
+pub(crate) fn elapsed(now: Instant, since: Instant) -> Milliseconds {
+    now.checked_duration_since(&since)
+        .and_then(|duration| Milliseconds::<u32>::try_from(duration).ok())
+        .unwrap_or(Milliseconds(0))
+}
 
 // Status to indicate Whether or not to send keepalive messages
 pub enum Status {
@@ -9,7 +38,60 @@ pub enum Status {
     ReceivedData(Milliseconds),
 }
 
+/// Status reported by CTAPHID_KEEPALIVE while the app processes a CBOR request.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum KeepaliveStatus {
     Processing = 1,
     UpNeeded = 2,
 }
+
+/// What the user interface does about user presence, shared with the USB tasks.
+///
+/// The UI is owned by Trussed, so the CTAPHID keepalive can't ask it directly:
+/// the UI sets this status for the whole wait in its user presence check.
+pub struct UserPresenceStatus {
+    waiting: AtomicBool,
+    // set by a CTAPHID_CANCEL of the request being processed, until the next request
+    cancelled: AtomicBool,
+}
+
+impl UserPresenceStatus {
+    pub const fn new() -> Self {
+        Self {
+            waiting: AtomicBool::new(false),
+            cancelled: AtomicBool::new(false),
+        }
+    }
+
+    pub fn set_waiting(&self, waiting: bool) {
+        self.waiting.store(waiting, Ordering::Relaxed);
+    }
+
+    pub fn waiting(&self) -> bool {
+        self.waiting.load(Ordering::Relaxed)
+    }
+
+    /// The status for CTAPHID_KEEPALIVE messages.
+    pub fn keepalive_status(&self) -> KeepaliveStatus {
+        if self.waiting() {
+            KeepaliveStatus::UpNeeded
+        } else {
+            KeepaliveStatus::Processing
+        }
+    }
+
+    pub fn set_cancelled(&self, cancelled: bool) {
+        self.cancelled.store(cancelled, Ordering::Relaxed);
+    }
+
+    /// The user presence check should give up, as the host is no longer interested.
+    pub fn cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for UserPresenceStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
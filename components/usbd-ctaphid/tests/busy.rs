@@ -0,0 +1,35 @@
+mod common;
+
+use common::*;
+use usb_bus_mock::MockBus;
+use usb_device::bus::UsbBusAllocator;
+use usbd_ctaphid::CtapHid;
+
+#[test]
+fn other_channel_is_busy() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, mut responder) = claim();
+    let mut ctaphid = CtapHid::new(&allocator, requester, 0, &mut rng()).implements_ctap2();
+    let host = Host::new(&bus);
+    let first = init_channel(&host, &mut ctaphid);
+    let second = init_channel(&host, &mut ctaphid);
+
+    host.send_message(&mut ctaphid, first, CBOR, &[0x04]);
+    assert!(responder.take_request().is_some());
+
+    // while the app is processing, other channels are told to retry later
+    host.send_message(&mut ctaphid, second, PING, b"hi");
+    let (channel, command, data) = host.receive_message(&mut ctaphid);
+    assert_eq!((channel, command), (second, ERROR));
+    assert_eq!(data, [ctap_types::Error::ChannelBusy as u8]);
+
+    // which does not disturb the transaction in progress
+    responder.respond(&Ok(heapless::Vec::from_slice(&[0x00]).unwrap())).ok().unwrap();
+    ctaphid.check_for_app_response();
+    assert_eq!(host.receive_message(&mut ctaphid), (first, CBOR, vec![0x00]));
+    assert!(host.nothing_received());
+
+    host.send_message(&mut ctaphid, second, PING, b"hi");
+    assert_eq!(host.receive_message(&mut ctaphid), (second, PING, b"hi".to_vec()));
+}
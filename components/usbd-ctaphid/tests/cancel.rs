@@ -0,0 +1,64 @@
+mod common;
+
+use common::*;
+use usb_bus_mock::MockBus;
+use usb_device::bus::UsbBusAllocator;
+use usbd_ctaphid::CtapHid;
+
+#[test]
+fn cancel_while_processing() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, mut responder) = claim();
+    let mut ctaphid = CtapHid::new(&allocator, requester, 0, &mut rng()).implements_ctap2();
+    let host = Host::new(&bus);
+    let channel = init_channel(&host, &mut ctaphid);
+    let other = init_channel(&host, &mut ctaphid);
+
+    // nothing to cancel
+    host.send_message(&mut ctaphid, channel, CANCEL, &[]);
+    assert!(host.nothing_received());
+    assert!(!ctaphid.did_cancel_processing());
+
+    host.send_message(&mut ctaphid, channel, CBOR, &[0x01]);
+    assert!(responder.take_request().is_some());
+
+    // CANCEL from another channel is not for us
+    host.send_message(&mut ctaphid, other, CANCEL, &[]);
+    assert!(host.nothing_received());
+    assert_eq!(responder.state(), interchange::State::BuildingResponse);
+
+    host.send_message(&mut ctaphid, channel, CANCEL, &[]);
+    let (response_channel, command, data) = host.receive_message(&mut ctaphid);
+    assert_eq!((response_channel, command), (channel, CBOR));
+    assert_eq!(data, [ctap_types::Error::KeepaliveCancel as u8]);
+    assert!(ctaphid.did_cancel_processing());
+    assert!(!ctaphid.did_cancel_processing());
+
+    // no keepalives for the canceled request
+    assert!(matches!(ctaphid.send_keepalive(usbd_ctaphid::types::KeepaliveStatus::UpNeeded), usbd_ctaphid::types::Status::Idle));
+
+    // the app is not interrupted, so new requests have to wait for it
+    assert_eq!(responder.state(), interchange::State::BuildingResponse);
+    host.send_message(&mut ctaphid, channel, CBOR, &[0x04]);
+    let (response_channel, command, data) = host.receive_message(&mut ctaphid);
+    assert_eq!((response_channel, command), (channel, ERROR));
+    assert_eq!(data, [ctap_types::Error::ChannelBusy as u8]);
+    // as do PINGs, which are echoed from the interchange
+    host.send_message(&mut ctaphid, channel, PING, b"next");
+    let (response_channel, command, data) = host.receive_message(&mut ctaphid);
+    assert_eq!((response_channel, command), (channel, ERROR));
+    assert_eq!(data, [ctap_types::Error::ChannelBusy as u8]);
+
+    // and its late response is dropped
+    responder.respond(&Ok(heapless::Vec::from_slice(&[0x00]).unwrap())).ok().unwrap();
+    ctaphid.check_for_app_response();
+    assert!(host.nothing_received());
+    host.send_message(&mut ctaphid, channel, PING, b"next");
+    assert_eq!(host.receive_message(&mut ctaphid), (channel, PING, b"next".to_vec()));
+    host.send_message(&mut ctaphid, channel, CBOR, &[0x04]);
+    assert!(responder.take_request().is_some());
+    responder.respond(&Ok(heapless::Vec::from_slice(&[0x01]).unwrap())).ok().unwrap();
+    ctaphid.check_for_app_response();
+    assert_eq!(host.receive_message(&mut ctaphid), (channel, CBOR, vec![0x01]));
+}
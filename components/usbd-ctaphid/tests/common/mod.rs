@@ -0,0 +1,127 @@
+//! The test plays the host of a CTAPHID device on an in-memory USB bus.
+
+#![allow(dead_code)]
+
+use ctaphid_dispatch::types::HidInterchange;
+use interchange::{Interchange, Requester, Responder};
+use rand_core::SeedableRng;
+use usb_bus_mock::MockBus;
+use usb_device::{
+    class::UsbClass,
+    endpoint::{EndpointAddress, EndpointType},
+    UsbDirection,
+};
+
+pub const BROADCAST: u32 = 0xffff_ffff;
+
+pub const INIT: u8 = 0x86;
+pub const PING: u8 = 0x81;
+pub const LOCK: u8 = 0x84;
+pub const CBOR: u8 = 0x90;
+pub const CANCEL: u8 = 0x91;
+pub const ERROR: u8 = 0xBF;
+
+pub struct Host {
+    bus: MockBus,
+    interrupt_out: EndpointAddress,
+    interrupt_in: EndpointAddress,
+}
+
+impl Host {
+    pub fn new(bus: &MockBus) -> Self {
+        Self {
+            bus: bus.clone(),
+            interrupt_out: bus.endpoint(EndpointType::Interrupt, UsbDirection::Out),
+            interrupt_in: bus.endpoint(EndpointType::Interrupt, UsbDirection::In),
+        }
+    }
+
+    pub fn send(&self, class: &mut dyn UsbClass<MockBus>, packet: &[u8]) {
+        // reports are always sent in full
+        let mut report = packet.to_vec();
+        report.resize(64, 0);
+        self.bus.send(self.interrupt_out, &report);
+        class.endpoint_out(self.interrupt_out);
+    }
+
+    /// Sends the message, split in initialization and continuation packets.
+    pub fn send_message(&self, class: &mut dyn UsbClass<MockBus>, channel: u32, command: u8, data: &[u8]) {
+        let (first, rest) = data.split_at(data.len().min(57));
+        self.send(class, &init_packet(channel, command, data.len() as u16, first));
+        for (sequence, chunk) in rest.chunks(59).enumerate() {
+            self.send(class, &continuation_packet(channel, sequence as u8, chunk));
+        }
+    }
+
+    pub fn receive(&self, class: &mut dyn UsbClass<MockBus>) -> Option<Vec<u8>> {
+        let packet = self.bus.receive(self.interrupt_in)?;
+        class.endpoint_in_complete(self.interrupt_in);
+        Some(packet)
+    }
+
+    /// Receives a message, returns its channel, command and payload.
+    pub fn receive_message(&self, class: &mut dyn UsbClass<MockBus>) -> (u32, u8, Vec<u8>) {
+        let packet = self.receive(class).expect("no response");
+        assert_eq!(packet.len(), 64);
+        let channel = u32::from_be_bytes([packet[0], packet[1], packet[2], packet[3]]);
+        let command = packet[4];
+        assert!(command & 0x80 != 0, "expected initialization packet");
+        let length = u16::from_be_bytes([packet[5], packet[6]]) as usize;
+
+        let mut data = packet[7..][..length.min(57)].to_vec();
+        let mut sequence = 0;
+        while data.len() < length {
+            let packet = self.receive(class).expect("missing continuation packet");
+            assert_eq!(&packet[..4], &channel.to_be_bytes());
+            assert_eq!(packet[4], sequence);
+            let missing = (length - data.len()).min(59);
+            data.extend_from_slice(&packet[5..][..missing]);
+            sequence += 1;
+        }
+        (channel, command, data)
+    }
+
+    /// Stalls or unstalls the interrupt IN endpoint.
+    pub fn stall(&self, stalled: bool) {
+        self.bus.stall(self.interrupt_in, stalled);
+    }
+
+    pub fn nothing_received(&self) -> bool {
+        self.bus.pending(self.interrupt_in) == 0
+    }
+}
+
+pub fn init_packet(channel: u32, command: u8, length: u16, data: &[u8]) -> Vec<u8> {
+    let mut packet = channel.to_be_bytes().to_vec();
+    packet.push(command);
+    packet.extend_from_slice(&length.to_be_bytes());
+    packet.extend_from_slice(data);
+    packet
+}
+
+pub fn continuation_packet(channel: u32, sequence: u8, data: &[u8]) -> Vec<u8> {
+    let mut packet = channel.to_be_bytes().to_vec();
+    packet.push(sequence);
+    packet.extend_from_slice(data);
+    packet
+}
+
+/// Claims the interchange, which is only possible once per test binary.
+pub fn claim() -> (Requester<HidInterchange>, Responder<HidInterchange>) {
+    HidInterchange::claim().unwrap()
+}
+
+/// Seeds the channel IDs.
+pub fn rng() -> chacha20::ChaCha8Rng {
+    chacha20::ChaCha8Rng::from_seed([0x5a; 32])
+}
+
+/// Allocates a channel with CTAPHID_INIT.
+pub fn init_channel(host: &Host, class: &mut dyn UsbClass<MockBus>) -> u32 {
+    let nonce = [1, 2, 3, 4, 5, 6, 7, 8];
+    host.send_message(class, BROADCAST, INIT, &nonce);
+    let (channel, command, data) = host.receive_message(class);
+    assert_eq!((channel, command), (BROADCAST, INIT));
+    assert_eq!(&data[..8], &nonce);
+    u32::from_be_bytes([data[8], data[9], data[10], data[11]])
+}
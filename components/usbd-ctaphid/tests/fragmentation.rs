@@ -0,0 +1,36 @@
+mod common;
+
+use common::*;
+use ctaphid_dispatch::command::Command;
+use usb_bus_mock::MockBus;
+use usb_device::bus::UsbBusAllocator;
+use usbd_ctaphid::CtapHid;
+
+#[test]
+fn fragmented_messages() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, mut responder) = claim();
+    let mut ctaphid = CtapHid::new(&allocator, requester, 0, &mut rng()).implements_ctap2();
+    let host = Host::new(&bus);
+    let channel = init_channel(&host, &mut ctaphid);
+
+    // handled by the pipe itself: one continuation packet each way
+    let data: Vec<u8> = (0..100).collect();
+    host.send_message(&mut ctaphid, channel, PING, &data);
+    assert_eq!(host.receive_message(&mut ctaphid), (channel, PING, data));
+
+    // handled by the app: two continuation packets in, three out
+    let request: Vec<u8> = (0..150u8).rev().collect();
+    host.send_message(&mut ctaphid, channel, CBOR, &request);
+    assert!(host.nothing_received());
+    let (command, message) = responder.take_request().unwrap();
+    assert_eq!(command, Command::Cbor);
+    assert_eq!(&message[..], &request[..]);
+
+    let response: Vec<u8> = (0..200).map(|i| i as u8).collect();
+    responder.respond(&Ok(heapless::Vec::from_slice(&response).unwrap())).ok().unwrap();
+    ctaphid.check_for_app_response();
+    assert_eq!(host.receive_message(&mut ctaphid), (channel, CBOR, response));
+    assert!(host.nothing_received());
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use common::*;
+use usb_bus_mock::MockBus;
+use usb_device::bus::UsbBusAllocator;
+use usbd_ctaphid::CtapHid;
+
+#[test]
+fn init_and_ping() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, _responder) = claim();
+    let mut ctaphid = CtapHid::new(&allocator, requester, 0, &mut rng())
+        .implements_ctap1()
+        .implements_ctap2();
+    let host = Host::new(&bus);
+
+    let nonce = [8, 7, 6, 5, 4, 3, 2, 1];
+    host.send_message(&mut ctaphid, BROADCAST, INIT, &nonce);
+    let (channel, command, data) = host.receive_message(&mut ctaphid);
+    assert_eq!((channel, command), (BROADCAST, INIT));
+    assert_eq!(data.len(), 17);
+    assert_eq!(&data[..8], &nonce);
+    let assigned = u32::from_be_bytes([data[8], data[9], data[10], data[11]]);
+    assert!(assigned != 0 && assigned != BROADCAST);
+    // CTAPHID protocol version
+    assert_eq!(data[12], 2);
+    // CBOR, and MSG (NMSG not set)
+    assert_eq!(data[16], 0x04);
+
+    // another INIT gets another channel, which is not guessable from the last one
+    let another = init_channel(&host, &mut ctaphid);
+    assert_ne!(another, assigned);
+    assert_ne!(another, assigned.wrapping_add(1));
+
+    host.send_message(&mut ctaphid, assigned, PING, b"hello");
+    assert_eq!(host.receive_message(&mut ctaphid), (assigned, PING, b"hello".to_vec()));
+    assert!(host.nothing_received());
+
+    // commands other than INIT are not allowed on the broadcast channel
+    host.send_message(&mut ctaphid, BROADCAST, PING, b"hello");
+    let (channel, command, data) = host.receive_message(&mut ctaphid);
+    assert_eq!((channel, command), (BROADCAST, ERROR));
+    assert_eq!(data, [ctap_types::Error::InvalidChannel as u8]);
+}
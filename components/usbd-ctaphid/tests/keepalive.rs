@@ -0,0 +1,48 @@
+mod common;
+
+use common::*;
+use embedded_time::duration::Extensions;
+use usb_bus_mock::MockBus;
+use usb_device::bus::UsbBusAllocator;
+use usbd_ctaphid::{
+    types::{KeepaliveStatus, Status},
+    CtapHid,
+};
+
+const KEEPALIVE: u8 = 0xBB;
+
+#[test]
+fn keepalive_status() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, mut responder) = claim();
+    let mut ctaphid = CtapHid::new(&allocator, requester, 0, &mut rng())
+        .implements_ctap2()
+        .with_keepalive_period(100.milliseconds());
+    let host = Host::new(&bus);
+    let channel = init_channel(&host, &mut ctaphid);
+
+    // no keepalives for pipe commands
+    host.send_message(&mut ctaphid, channel, PING, b"ping");
+    assert!(matches!(ctaphid.did_start_processing(), Status::Idle));
+    assert_eq!(host.receive_message(&mut ctaphid), (channel, PING, b"ping".to_vec()));
+
+    host.send_message(&mut ctaphid, channel, CBOR, &[0x01]);
+    assert!(responder.take_request().is_some());
+    assert!(matches!(ctaphid.did_start_processing(), Status::ReceivedData(period) if period == 100.milliseconds()));
+
+    // the status is reported as given, for every period of the wait
+    for status in [KeepaliveStatus::Processing, KeepaliveStatus::UpNeeded, KeepaliveStatus::UpNeeded] {
+        assert!(matches!(ctaphid.send_keepalive(status), Status::ReceivedData(period) if period == 100.milliseconds()));
+        assert_eq!(host.receive_message(&mut ctaphid), (channel, KEEPALIVE, vec![status as u8]));
+    }
+
+    responder.respond(&Ok(heapless::Vec::from_slice(&[0x00]).unwrap())).ok().unwrap();
+    ctaphid.check_for_app_response();
+    let (response_channel, command, _) = host.receive_message(&mut ctaphid);
+    assert_eq!((response_channel, command), (channel, CBOR));
+
+    // the response went out, stop the keepalives
+    assert!(matches!(ctaphid.send_keepalive(KeepaliveStatus::Processing), Status::Idle));
+    assert!(host.nothing_received());
+}
@@ -0,0 +1,36 @@
+mod common;
+
+use common::*;
+use usb_bus_mock::MockBus;
+use usb_device::bus::UsbBusAllocator;
+use usbd_ctaphid::{constants::MESSAGE_SIZE, CtapHid};
+
+#[test]
+fn largest_messages() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, mut responder) = claim();
+    let mut ctaphid = CtapHid::new(&allocator, requester, 0, &mut rng()).implements_ctap2();
+    let host = Host::new(&bus);
+    let channel = init_channel(&host, &mut ctaphid);
+    assert_eq!(MESSAGE_SIZE, 7609);
+
+    let data: Vec<u8> = (0..MESSAGE_SIZE).map(|i| (i % 251) as u8).collect();
+    host.send_message(&mut ctaphid, channel, PING, &data);
+    assert_eq!(host.receive_message(&mut ctaphid), (channel, PING, data.clone()));
+
+    host.send_message(&mut ctaphid, channel, CBOR, &data);
+    let (_, message) = responder.take_request().unwrap();
+    assert_eq!(&message[..], &data[..]);
+    let response: Vec<u8> = data.iter().rev().cloned().collect();
+    responder.respond(&Ok(heapless::Vec::from_slice(&response).unwrap())).ok().unwrap();
+    ctaphid.check_for_app_response();
+    assert_eq!(host.receive_message(&mut ctaphid), (channel, CBOR, response));
+
+    // does not fit the framing
+    host.send(&mut ctaphid, &init_packet(channel, CBOR, MESSAGE_SIZE as u16 + 1, &data[..57]));
+    let (response_channel, command, data) = host.receive_message(&mut ctaphid);
+    assert_eq!((response_channel, command), (channel, ERROR));
+    assert_eq!(data, [ctap_types::Error::InvalidLength as u8]);
+    assert!(responder.take_request().is_none());
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use common::*;
+use usb_bus_mock::MockBus;
+use usb_device::bus::UsbBusAllocator;
+use usbd_ctaphid::CtapHid;
+
+#[test]
+fn lock_and_unknown_channels() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, _responder) = claim();
+    let mut ctaphid = CtapHid::new(&allocator, requester, 0, &mut rng()).implements_ctap2();
+    let host = Host::new(&bus);
+    let first = init_channel(&host, &mut ctaphid);
+    let second = init_channel(&host, &mut ctaphid);
+
+    // channels must be allocated first
+    let unknown = first ^ second ^ 0x5a5a_5a5a;
+    host.send_message(&mut ctaphid, unknown, PING, b"hi");
+    let (channel, command, data) = host.receive_message(&mut ctaphid);
+    assert_eq!((channel, command), (unknown, ERROR));
+    assert_eq!(data, [ctap_types::Error::InvalidChannel as u8]);
+
+    // at most 10 seconds
+    host.send_message(&mut ctaphid, first, LOCK, &[11]);
+    let (channel, command, data) = host.receive_message(&mut ctaphid);
+    assert_eq!((channel, command), (first, ERROR));
+    assert_eq!(data, [ctap_types::Error::InvalidParameter as u8]);
+
+    host.send_message(&mut ctaphid, first, LOCK, &[2]);
+    assert_eq!(host.receive_message(&mut ctaphid), (first, LOCK, vec![]));
+
+    // other channels, including the broadcast channel, are busy
+    for channel in [second, BROADCAST] {
+        host.send_message(&mut ctaphid, channel, PING, b"hi");
+        let (response_channel, command, data) = host.receive_message(&mut ctaphid);
+        assert_eq!((response_channel, command), (channel, ERROR));
+        assert_eq!(data, [ctap_types::Error::ChannelBusy as u8]);
+    }
+    host.send_message(&mut ctaphid, first, PING, b"mine");
+    assert_eq!(host.receive_message(&mut ctaphid), (first, PING, b"mine".to_vec()));
+
+    // until the lock expires
+    ctaphid.check_timeout(1000);
+    ctaphid.check_timeout(2000);
+    host.send_message(&mut ctaphid, second, PING, b"hi");
+    assert_eq!(host.receive_message(&mut ctaphid), (second, PING, b"hi".to_vec()));
+
+    // or is released
+    host.send_message(&mut ctaphid, first, LOCK, &[10]);
+    assert_eq!(host.receive_message(&mut ctaphid), (first, LOCK, vec![]));
+    host.send_message(&mut ctaphid, first, LOCK, &[0]);
+    assert_eq!(host.receive_message(&mut ctaphid), (first, LOCK, vec![]));
+    host.send_message(&mut ctaphid, second, PING, b"hi");
+    assert_eq!(host.receive_message(&mut ctaphid), (second, PING, b"hi".to_vec()));
+
+    // INIT on an allocated channel keeps it
+    let nonce = [1; 8];
+    host.send_message(&mut ctaphid, second, INIT, &nonce);
+    let (channel, command, data) = host.receive_message(&mut ctaphid);
+    assert_eq!((channel, command), (second, INIT));
+    assert_eq!(u32::from_be_bytes([data[8], data[9], data[10], data[11]]), second);
+
+    // the least recently used channels are dropped when the table is full
+    ctaphid.check_timeout(3000);
+    host.send_message(&mut ctaphid, second, PING, b"hi");
+    assert_eq!(host.receive_message(&mut ctaphid), (second, PING, b"hi".to_vec()));
+    for _ in 0..usbd_ctaphid::constants::MAX_CHANNELS - 1 {
+        init_channel(&host, &mut ctaphid);
+    }
+    host.send_message(&mut ctaphid, second, PING, b"hi");
+    assert_eq!(host.receive_message(&mut ctaphid), (second, PING, b"hi".to_vec()));
+    host.send_message(&mut ctaphid, first, PING, b"hi");
+    let (_, command, data) = host.receive_message(&mut ctaphid);
+    assert_eq!(command, ERROR);
+    assert_eq!(data, [ctap_types::Error::InvalidChannel as u8]);
+}
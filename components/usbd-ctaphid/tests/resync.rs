@@ -0,0 +1,41 @@
+mod common;
+
+use common::*;
+use usb_bus_mock::MockBus;
+use usb_device::bus::UsbBusAllocator;
+use usbd_ctaphid::CtapHid;
+
+#[test]
+fn init_on_allocated_channel() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, _responder) = claim();
+    let mut ctaphid = CtapHid::new(&allocator, requester, 0, &mut rng())
+        .implements_ctap2();
+    let host = Host::new(&bus);
+    let channel = init_channel(&host, &mut ctaphid);
+
+    // resynchronization: answered on the channel, which is kept
+    let nonce = [9, 8, 7, 6, 5, 4, 3, 2];
+    host.send_message(&mut ctaphid, channel, INIT, &nonce);
+    let (response_channel, command, data) = host.receive_message(&mut ctaphid);
+    assert_eq!((response_channel, command), (channel, INIT));
+    assert_eq!(data.len(), 17);
+    assert_eq!(&data[..8], &nonce);
+    assert_eq!(&data[8..12], &channel.to_be_bytes());
+    assert_eq!(data[12], 2);
+    assert_eq!(data[16], 0x04);
+
+    host.send_message(&mut ctaphid, channel, PING, b"still there");
+    assert_eq!(host.receive_message(&mut ctaphid), (channel, PING, b"still there".to_vec()));
+
+    // an INIT without an 8 byte nonce is ignored
+    host.send_message(&mut ctaphid, BROADCAST, INIT, &nonce[..4]);
+    assert!(host.nothing_received());
+
+    // and the reserved channel 0 is refused
+    host.send_message(&mut ctaphid, 0, INIT, &nonce);
+    let (response_channel, command, data) = host.receive_message(&mut ctaphid);
+    assert_eq!((response_channel, command), (0, ERROR));
+    assert_eq!(data, [ctap_types::Error::InvalidChannel as u8]);
+}
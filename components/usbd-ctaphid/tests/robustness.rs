@@ -0,0 +1,67 @@
+mod common;
+
+use common::*;
+use usb_bus_mock::MockBus;
+use usb_device::bus::UsbBusAllocator;
+use usbd_ctaphid::CtapHid;
+
+#[test]
+fn uptime_wrap_and_stalls() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, _responder) = claim();
+    // the u32 milliseconds wrap after 49.7 days
+    let start = u32::MAX - 250;
+    let mut ctaphid = CtapHid::new(&allocator, requester, start, &mut rng()).implements_ctap2();
+    let host = Host::new(&bus);
+    let channel = init_channel(&host, &mut ctaphid);
+    let other = init_channel(&host, &mut ctaphid);
+
+    // a lock and a transaction timing out across the wraparound
+    host.send_message(&mut ctaphid, channel, LOCK, &[1]);
+    assert_eq!(host.receive_message(&mut ctaphid), (channel, LOCK, vec![]));
+    host.send(&mut ctaphid, &init_packet(channel, PING, 100, &[0xA1; 57]));
+    for elapsed in &[150u32, 300, 450] {
+        ctaphid.check_timeout(start.wrapping_add(*elapsed));
+        assert!(host.nothing_received());
+    }
+    ctaphid.check_timeout(start.wrapping_add(600));
+    let (response_channel, command, data) = host.receive_message(&mut ctaphid);
+    assert_eq!((response_channel, command), (channel, ERROR));
+    assert_eq!(data, [ctap_types::Error::Timeout as u8]);
+
+    host.send_message(&mut ctaphid, other, PING, b"hi");
+    let (response_channel, command, data) = host.receive_message(&mut ctaphid);
+    assert_eq!((response_channel, command), (other, ERROR));
+    assert_eq!(data, [ctap_types::Error::ChannelBusy as u8]);
+    for elapsed in &[750u32, 900, 1000] {
+        ctaphid.check_timeout(start.wrapping_add(*elapsed));
+    }
+    host.send_message(&mut ctaphid, other, PING, b"hi");
+    assert_eq!(host.receive_message(&mut ctaphid), (other, PING, b"hi".to_vec()));
+
+    // the initialization packet can't be written
+    host.stall(true);
+    host.send_message(&mut ctaphid, channel, PING, b"stalled");
+    assert!(host.nothing_received());
+    host.stall(false);
+    ctaphid.check_for_app_response();
+    assert!(host.nothing_received());
+    host.send_message(&mut ctaphid, channel, PING, b"again");
+    assert_eq!(host.receive_message(&mut ctaphid), (channel, PING, b"again".to_vec()));
+
+    // a continuation packet can't be written
+    let data: Vec<u8> = (0..200).collect();
+    host.send_message(&mut ctaphid, channel, PING, &data);
+    host.stall(true);
+    let first = host.receive(&mut ctaphid).unwrap();
+    assert_eq!(&first[..4], &channel.to_be_bytes());
+    assert!(host.nothing_received());
+    host.stall(false);
+    ctaphid.check_for_app_response();
+    assert!(host.nothing_received());
+
+    // and we're ready for the next request
+    host.send_message(&mut ctaphid, channel, PING, &data);
+    assert_eq!(host.receive_message(&mut ctaphid), (channel, PING, data));
+}
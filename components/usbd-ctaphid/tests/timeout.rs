@@ -0,0 +1,33 @@
+mod common;
+
+use common::*;
+use usb_bus_mock::MockBus;
+use usb_device::bus::UsbBusAllocator;
+use usbd_ctaphid::CtapHid;
+
+#[test]
+fn missing_continuation_times_out() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, mut responder) = claim();
+    let mut ctaphid = CtapHid::new(&allocator, requester, 0, &mut rng()).implements_ctap2();
+    let host = Host::new(&bus);
+    let channel = init_channel(&host, &mut ctaphid);
+
+    // announce 100 bytes, but only send the initialization packet
+    host.send(&mut ctaphid, &init_packet(channel, CBOR, 100, &[0xA1; 57]));
+
+    for milliseconds in &[150, 300, 450] {
+        ctaphid.check_timeout(*milliseconds);
+        assert!(host.nothing_received());
+    }
+    ctaphid.check_timeout(600);
+    let (response_channel, command, data) = host.receive_message(&mut ctaphid);
+    assert_eq!((response_channel, command), (channel, ERROR));
+    assert_eq!(data, [ctap_types::Error::Timeout as u8]);
+    assert!(responder.take_request().is_none());
+
+    // the channel is usable again
+    host.send_message(&mut ctaphid, channel, PING, b"again");
+    assert_eq!(host.receive_message(&mut ctaphid), (channel, PING, b"again".to_vec()));
+}
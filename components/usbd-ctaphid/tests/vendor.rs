@@ -0,0 +1,48 @@
+mod common;
+
+use core::convert::TryFrom;
+
+use common::*;
+use ctaphid_dispatch::{app, command::Command};
+use usb_bus_mock::MockBus;
+use usb_device::bus::UsbBusAllocator;
+use usbd_ctaphid::CtapHid;
+
+#[test]
+fn vendor_commands() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (requester, mut responder) = claim();
+    let mut ctaphid = CtapHid::new(&allocator, requester, 0, &mut rng()).implements_ctap2();
+    let host = Host::new(&bus);
+    let channel = init_channel(&host, &mut ctaphid);
+
+    // the whole vendor range goes to the apps
+    for code in [0x40u8, 0x51, 0x7F] {
+        host.send_message(&mut ctaphid, channel, 0x80 | code, &[code]);
+        let (command, message) = responder.take_request().unwrap();
+        assert_eq!(command, Command::try_from(code).unwrap());
+        assert_eq!(&message[..], &[code]);
+        responder.respond(&Ok(heapless::Vec::from_slice(&[code, code]).unwrap())).ok().unwrap();
+        ctaphid.check_for_app_response();
+        assert_eq!(host.receive_message(&mut ctaphid), (channel, 0x80 | code, vec![code, code]));
+    }
+
+    // no app registered for the vendor command
+    host.send_message(&mut ctaphid, channel, 0x80 | 0x60, &[]);
+    assert!(responder.take_request().is_some());
+    responder.respond(&Err(app::Error::InvalidCommand)).ok().unwrap();
+    ctaphid.check_for_app_response();
+    let (response_channel, command, data) = host.receive_message(&mut ctaphid);
+    assert_eq!((response_channel, command), (channel, ERROR));
+    assert_eq!(data, [ctap_types::Error::InvalidCommand as u8]);
+
+    // commands outside of the vendor range that CTAPHID does not define
+    for code in [0x20u8, 0x3F] {
+        host.send_message(&mut ctaphid, channel, 0x80 | code, &[]);
+        let (response_channel, command, data) = host.receive_message(&mut ctaphid);
+        assert_eq!((response_channel, command), (channel, ERROR));
+        assert_eq!(data, [ctap_types::Error::InvalidCommand as u8]);
+        assert!(responder.take_request().is_none());
+    }
+}
@@ -0,0 +1,339 @@
+use interchange::Responder;
+
+use usb_device::{
+    bus::{InterfaceNumber, UsbBus, UsbBusAllocator},
+    class::{ControlIn, ControlOut, UsbClass},
+    control,
+    descriptor::DescriptorWriter,
+    endpoint::{EndpointAddress, EndpointIn},
+    Result as UsbResult, UsbError,
+};
+
+use crate::{
+    constants::{INTERRUPT_POLL_MILLISECONDS, REPORT_SIZE},
+    keymap::{self, Layout},
+    types::{Error, KeyboardInterchange, Request, Text},
+};
+
+const HID_INTERFACE_CLASS: u8 = 0x03;
+const INTERFACE_SUBCLASS_BOOT: u8 = 0x1;
+const INTERFACE_PROTOCOL_KEYBOARD: u8 = 0x1;
+
+const HID_DESCRIPTOR: u8 = 0x21;
+const HID_REPORT_DESCRIPTOR: u8 = 0x22;
+
+// cf. hid1_11.pdf, Appendix B.1
+const BOOT_KEYBOARD_REPORT_DESCRIPTOR_LENGTH: usize = 63;
+const BOOT_KEYBOARD_REPORT_DESCRIPTOR: [u8; BOOT_KEYBOARD_REPORT_DESCRIPTOR_LENGTH] = [
+    0x05, 0x01,        // Usage Page (Generic Desktop)
+    0x09, 0x06,        // Usage (Keyboard)
+    0xA1, 0x01,        // Collection (Application)
+
+        // modifier byte
+        0x05, 0x07,        // Usage Page (Key Codes)
+        0x19, 0xE0,        // Usage Minimum (224)
+        0x29, 0xE7,        // Usage Maximum (231)
+        0x15, 0x00,        // Logical Minimum (0)
+        0x25, 0x01,        // Logical Maximum (1)
+        0x75, 0x01,        // Report Size (1)
+        0x95, 0x08,        // Report Count (8)
+        0x81, 0x02,        // Input (Data, Variable, Absolute)
+
+        // reserved byte
+        0x95, 0x01,        // Report Count (1)
+        0x75, 0x08,        // Report Size (8)
+        0x81, 0x01,        // Input (Constant)
+
+        // LEDs, and padding
+        0x95, 0x05,        // Report Count (5)
+        0x75, 0x01,        // Report Size (1)
+        0x05, 0x08,        // Usage Page (LEDs)
+        0x19, 0x01,        // Usage Minimum (1)
+        0x29, 0x05,        // Usage Maximum (5)
+        0x91, 0x02,        // Output (Data, Variable, Absolute)
+        0x95, 0x01,        // Report Count (1)
+        0x75, 0x03,        // Report Size (3)
+        0x91, 0x01,        // Output (Constant)
+
+        // keys
+        0x95, 0x06,        // Report Count (6)
+        0x75, 0x08,        // Report Size (8)
+        0x15, 0x00,        // Logical Minimum (0)
+        0x25, 0x65,        // Logical Maximum (101)
+        0x05, 0x07,        // Usage Page (Key Codes)
+        0x19, 0x00,        // Usage Minimum (0)
+        0x29, 0x65,        // Usage Maximum (101)
+        0x81, 0x00,        // Input (Data, Array)
+
+    // EndCollection
+    0xC0,
+];
+
+// see hid1_11.pdf, section 7.2, p. 50
+#[derive(Copy,Clone,Eq,Debug,PartialEq)]
+pub enum ClassRequests {
+    GetReport = 0x1,
+    GetIdle = 0x2,
+    GetProtocol = 0x3,
+    SetReport = 0x9,
+    SetIdle = 0xA,
+    SetProtocol = 0xB,
+}
+
+type Report = [u8; REPORT_SIZE];
+
+// Progress through the text of a request.
+struct Typing {
+    layout: Layout,
+    text: Text,
+    // byte offset of the next character
+    position: usize,
+    // next key of the character (dead keys are followed by a space)
+    key: usize,
+    released: bool,
+}
+
+impl Typing {
+    fn new(request: Request) -> Self {
+        Self { layout: request.layout, text: request.text, position: 0, key: 0, released: true }
+    }
+
+    // Each key is pressed, and released (so repeated characters are typed).
+    fn next_report(&mut self) -> Option<Report> {
+        if !self.released {
+            self.released = true;
+            return Some([0; REPORT_SIZE]);
+        }
+        let c = self.text[self.position..].chars().next()?;
+        // checked before typing
+        let keys = keymap::keys(self.layout, c).unwrap();
+        let key = keys[self.key];
+        self.key += 1;
+        if self.key == keys.len() {
+            self.key = 0;
+            self.position += c.len_utf8();
+        }
+        self.released = false;
+
+        let mut report = [0; REPORT_SIZE];
+        report[0] = key.modifiers;
+        report[2] = key.code;
+        Some(report)
+    }
+}
+
+/// HID boot keyboard, typing the text of the apps' requests.
+pub struct Keyboard<'alloc, Bus: UsbBus> {
+    interface: InterfaceNumber,
+    write_endpoint: EndpointIn<'alloc, Bus>,
+    interchange: Responder<KeyboardInterchange>,
+    typing: Option<Typing>,
+    // report not yet written, as the endpoint was busy
+    pending: Option<Report>,
+    // last report written, for GET_REPORT
+    report: Report,
+    // boot (0) or report (1) protocol, our reports are the same for both
+    protocol: u8,
+    idle: u8,
+}
+
+impl<'alloc, Bus> Keyboard<'alloc, Bus>
+where
+    Bus: UsbBus
+{
+    pub fn new(allocate: &'alloc UsbBusAllocator<Bus>, interchange: Responder<KeyboardInterchange>) -> Self {
+        // 8 bytes, interrupt endpoint polled every 10 milliseconds
+        let write_endpoint = allocate.interrupt(REPORT_SIZE as u16, INTERRUPT_POLL_MILLISECONDS);
+
+        Self {
+            interface: allocate.interface(),
+            write_endpoint,
+            interchange,
+            typing: None,
+            pending: None,
+            report: [0; REPORT_SIZE],
+            protocol: 1,
+            idle: 0,
+        }
+    }
+
+    /// Take a request from an app (if any) and start typing.
+    /// Should be called before managing Bus.
+    pub fn check_for_app_request(&mut self) {
+        self.poll();
+    }
+
+    fn check_for_request(&mut self) {
+        if self.interchange.state() == interchange::State::Canceled {
+            info!("app canceled typing");
+            self.interchange.acknowledge_cancel().ok();
+            if self.typing.take().is_some() {
+                // don't leave a key pressed
+                self.pending = Some([0; REPORT_SIZE]);
+            }
+        }
+
+        if self.typing.is_some() {
+            return;
+        }
+        if let Some(request) = self.interchange.take_request() {
+            let supported = request.text.chars()
+                .all(|c| keymap::keys(request.layout, c).is_some());
+            if supported {
+                info!("typing {} bytes", request.text.len());
+                self.typing = Some(Typing::new(request));
+            } else {
+                info!("unsupported character");
+                self.interchange.respond(&Err(Error::UnsupportedCharacter)).ok();
+            }
+        }
+    }
+
+    // called from poll, and when a report has been sent
+    fn maybe_write_report(&mut self) {
+        if self.pending.is_none() {
+            self.pending = self.typing.as_mut().and_then(|typing| typing.next_report());
+        }
+
+        if let Some(report) = self.pending {
+            match self.write_endpoint.write(&report) {
+                Ok(_) => {
+                    self.report = report;
+                    self.pending = None;
+                }
+                // fine, try again when the host took the last report
+                Err(UsbError::WouldBlock) => {}
+                Err(_error) => {
+                    info!("dropping report, write failed: {:?}", _error);
+                    self.pending = None;
+                }
+            }
+        }
+
+        if self.pending.is_none() && self.typing.is_some() {
+            let done = match self.typing.as_ref() {
+                Some(typing) => typing.released && typing.position == typing.text.len(),
+                None => false,
+            };
+            if done {
+                info!("typing done");
+                self.typing = None;
+                self.interchange.respond(&Ok(())).ok();
+            }
+        }
+    }
+}
+
+impl<'alloc, Bus> UsbClass<Bus> for Keyboard<'alloc, Bus>
+where
+    Bus: UsbBus
+{
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> UsbResult<()> {
+        writer.interface(
+            self.interface,
+            HID_INTERFACE_CLASS,
+            INTERFACE_SUBCLASS_BOOT,
+            INTERFACE_PROTOCOL_KEYBOARD,
+        )?;
+
+        // little-endian integers
+        writer.write(HID_DESCRIPTOR, &[
+            0x11, 0x01, // bcdHID (le)
+            0x00, // country code: universal
+            0x01, // number of HID report descriptors
+            HID_REPORT_DESCRIPTOR, // 1st HID report descriptor type
+            BOOT_KEYBOARD_REPORT_DESCRIPTOR_LENGTH as u8, 0x00, // 1st HID report descriptor length in bytes as u16-le
+        ])?;
+
+        writer.endpoint(&self.write_endpoint)?;
+
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        if self.typing.take().is_some() {
+            self.interchange.respond(&Err(Error::Interrupted)).ok();
+        }
+        self.pending = None;
+        self.report = [0; REPORT_SIZE];
+        self.protocol = 1;
+        self.idle = 0;
+    }
+
+    fn poll(&mut self) {
+        self.check_for_request();
+        self.maybe_write_report();
+    }
+
+    fn endpoint_in_complete(&mut self, addr: EndpointAddress) {
+        if addr == self.write_endpoint.address() {
+            self.maybe_write_report();
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<Bus>) {
+        let req = xfer.request();
+
+        if req.request_type == control::RequestType::Class
+            && req.recipient == control::Recipient::Interface
+            && req.index == u8::from(self.interface) as u16
+        {
+            match req.request {
+                // duration = upper byte of wValue
+                r if r == ClassRequests::SetIdle as u8 => {
+                    self.idle = (req.value >> 8) as u8;
+                    xfer.accept().ok();
+                },
+                r if r == ClassRequests::SetProtocol as u8 => {
+                    self.protocol = req.value as u8;
+                    xfer.accept().ok();
+                },
+                // the LEDs (caps lock etc.), which we don't have
+                r if r == ClassRequests::SetReport as u8 => {
+                    xfer.accept().ok();
+                },
+                _ => {
+                    xfer.reject().ok();
+                },
+            };
+        }
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<Bus>) {
+        let req = xfer.request();
+
+        if req.recipient != control::Recipient::Interface
+            || req.index != u8::from(self.interface) as u16
+        {
+            return;
+        }
+
+        match req.request_type {
+            control::RequestType::Standard => {
+                if req.request == control::Request::GET_DESCRIPTOR
+                    && (req.value >> 8) as u8 == HID_REPORT_DESCRIPTOR
+                {
+                    xfer.accept_with_static(&BOOT_KEYBOARD_REPORT_DESCRIPTOR).ok();
+                }
+            },
+            control::RequestType::Class => {
+                match req.request {
+                    r if r == ClassRequests::GetReport as u8 => {
+                        let report = self.report;
+                        xfer.accept_with(&report).ok();
+                    },
+                    r if r == ClassRequests::GetIdle as u8 => {
+                        xfer.accept_with(&[self.idle]).ok();
+                    },
+                    r if r == ClassRequests::GetProtocol as u8 => {
+                        xfer.accept_with(&[self.protocol]).ok();
+                    },
+                    _ => {
+                        xfer.reject().ok();
+                    },
+                }
+            },
+            _ => {},
+        }
+    }
+}
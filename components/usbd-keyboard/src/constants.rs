@@ -0,0 +1,7 @@
+pub const INTERRUPT_POLL_MILLISECONDS: u8 = 10;
+
+/// Modifier byte, reserved byte, and up to six keys.
+pub const REPORT_SIZE: usize = 8;
+
+/// Maximum length of the text of a request, in bytes.
+pub const TEXT_SIZE: usize = 64;
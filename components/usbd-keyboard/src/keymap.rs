@@ -0,0 +1,177 @@
+//! Keys to press for a character, depending on the keyboard layout of the host.
+//!
+//! The keyboard reports positions of keys (HID usage IDs, named after the US
+//! layout), the host maps them to characters with its configured layout.
+//! Cf. HID Usage Tables 1.12, Sec. 10.
+
+/// Modifier bits of the first report byte.
+pub const LEFT_SHIFT: u8 = 0x02;
+/// AltGr on layouts such as DE.
+pub const RIGHT_ALT: u8 = 0x40;
+
+const ENTER: u8 = 0x28;
+const TAB: u8 = 0x2b;
+const SPACE: u8 = 0x2c;
+/// The key left of Enter on ISO keyboards.
+const NON_US_HASH: u8 = 0x32;
+/// The key right of the left Shift on ISO keyboards.
+const NON_US_BACKSLASH: u8 = 0x64;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Layout {
+    /// US QWERTY
+    Us,
+    /// German QWERTZ
+    De,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::Us
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Key {
+    pub modifiers: u8,
+    pub code: u8,
+}
+
+const fn plain(code: u8) -> Key {
+    Key { modifiers: 0, code }
+}
+
+const fn shift(code: u8) -> Key {
+    Key { modifiers: LEFT_SHIFT, code }
+}
+
+const fn alt_gr(code: u8) -> Key {
+    Key { modifiers: RIGHT_ALT, code }
+}
+
+/// Up to two keys per character: dead keys are followed by a space.
+pub type Keys = heapless::Vec<Key, 2>;
+
+/// The keys to press one after the other to type the character, if any.
+pub fn keys(layout: Layout, c: char) -> Option<Keys> {
+    let (key, dead) = match layout {
+        Layout::Us => (us(c)?, false),
+        Layout::De => de(c)?,
+    };
+    let mut keys = Keys::new();
+    keys.push(key).ok();
+    if dead {
+        keys.push(plain(SPACE)).ok();
+    }
+    Some(keys)
+}
+
+// letters and digits of the US layout, and white space
+fn common(c: char) -> Option<Key> {
+    Some(match c {
+        'a'..='z' => plain(0x04 + (c as u8 - b'a')),
+        'A'..='Z' => shift(0x04 + (c as u8 - b'A')),
+        '1'..='9' => plain(0x1e + (c as u8 - b'1')),
+        '0' => plain(0x27),
+        '\n' => plain(ENTER),
+        '\t' => plain(TAB),
+        ' ' => plain(SPACE),
+        _ => return None,
+    })
+}
+
+fn us(c: char) -> Option<Key> {
+    Some(match c {
+        '!' => shift(0x1e),
+        '@' => shift(0x1f),
+        '#' => shift(0x20),
+        '$' => shift(0x21),
+        '%' => shift(0x22),
+        '^' => shift(0x23),
+        '&' => shift(0x24),
+        '*' => shift(0x25),
+        '(' => shift(0x26),
+        ')' => shift(0x27),
+        '-' => plain(0x2d),
+        '_' => shift(0x2d),
+        '=' => plain(0x2e),
+        '+' => shift(0x2e),
+        '[' => plain(0x2f),
+        '{' => shift(0x2f),
+        ']' => plain(0x30),
+        '}' => shift(0x30),
+        '\\' => plain(0x31),
+        '|' => shift(0x31),
+        ';' => plain(0x33),
+        ':' => shift(0x33),
+        '\'' => plain(0x34),
+        '"' => shift(0x34),
+        '`' => plain(0x35),
+        '~' => shift(0x35),
+        ',' => plain(0x36),
+        '<' => shift(0x36),
+        '.' => plain(0x37),
+        '>' => shift(0x37),
+        '/' => plain(0x38),
+        '?' => shift(0x38),
+        c => common(c)?,
+    })
+}
+
+// returns whether the key is a dead key
+fn de(c: char) -> Option<(Key, bool)> {
+    let key = match c {
+        'z' => plain(0x1c),
+        'Z' => shift(0x1c),
+        'y' => plain(0x1d),
+        'Y' => shift(0x1d),
+        'ü' => plain(0x2f),
+        'Ü' => shift(0x2f),
+        'ö' => plain(0x33),
+        'Ö' => shift(0x33),
+        'ä' => plain(0x34),
+        'Ä' => shift(0x34),
+        'ß' => plain(0x2d),
+        '!' => shift(0x1e),
+        '"' => shift(0x1f),
+        '§' => shift(0x20),
+        '$' => shift(0x21),
+        '%' => shift(0x22),
+        '&' => shift(0x23),
+        '/' => shift(0x24),
+        '(' => shift(0x25),
+        ')' => shift(0x26),
+        '=' => shift(0x27),
+        '?' => shift(0x2d),
+        '+' => plain(0x30),
+        '*' => shift(0x30),
+        '#' => plain(NON_US_HASH),
+        '\'' => shift(NON_US_HASH),
+        '°' => shift(0x35),
+        ',' => plain(0x36),
+        ';' => shift(0x36),
+        '.' => plain(0x37),
+        ':' => shift(0x37),
+        '-' => plain(0x38),
+        '_' => shift(0x38),
+        '<' => plain(NON_US_BACKSLASH),
+        '>' => shift(NON_US_BACKSLASH),
+        '|' => alt_gr(NON_US_BACKSLASH),
+        '²' => alt_gr(0x1f),
+        '³' => alt_gr(0x20),
+        '{' => alt_gr(0x24),
+        '[' => alt_gr(0x25),
+        ']' => alt_gr(0x26),
+        '}' => alt_gr(0x27),
+        '\\' => alt_gr(0x2d),
+        '~' => alt_gr(0x30),
+        '@' => alt_gr(0x14),
+        '€' => alt_gr(0x08),
+        'µ' => alt_gr(0x10),
+        '^' => return Some((plain(0x35), true)),
+        '´' => return Some((plain(0x2e), true)),
+        '`' => return Some((shift(0x2e), true)),
+        c => common(c)?,
+    };
+    Some((key, false))
+}
@@ -0,0 +1,23 @@
+#![no_std]
+
+/*!
+usbd-keyboard
+
+HID boot keyboard, typing strings on behalf of apps, e.g. OTP codes.
+
+Apps send a `types::Request` with the text and the keyboard layout of the host
+via the `types::KeyboardInterchange`.  The keyboard presses and releases a key
+per character, and responds once the host has received all reports.
+
+See "Device Class Definition for HID 1.11", Appendix B.1 for the boot keyboard.
+*/
+
+#[macro_use]
+extern crate delog;
+generate_macros!();
+
+pub mod class;
+pub use class::Keyboard;
+pub mod constants;
+pub mod keymap;
+pub mod types;
@@ -0,0 +1,24 @@
+use crate::{constants::TEXT_SIZE, keymap::Layout};
+
+pub type Text = heapless::String<TEXT_SIZE>;
+
+/// Text to type, for a host with the given keyboard layout.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Request {
+    pub layout: Layout,
+    pub text: Text,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// The layout has no keys for a character of the text, nothing was typed.
+    UnsupportedCharacter,
+    /// A bus reset interrupted typing.
+    Interrupted,
+}
+
+pub type Response = Result<(), Error>;
+
+interchange::interchange! {
+    KeyboardInterchange: (Request, Response)
+}
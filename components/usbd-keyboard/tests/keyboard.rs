@@ -0,0 +1,128 @@
+//! The test plays the host of the keyboard on an in-memory USB bus.
+
+use interchange::{Interchange, Requester};
+use usb_bus_mock::MockBus;
+use usb_device::{
+    bus::UsbBusAllocator,
+    class::UsbClass,
+    device::{UsbDeviceBuilder, UsbVidPid},
+    endpoint::EndpointType,
+    UsbDirection,
+};
+use usbd_keyboard::{
+    keymap::{self, Key, Layout, LEFT_SHIFT, RIGHT_ALT},
+    types::{Error, KeyboardInterchange, Request},
+    Keyboard,
+};
+
+const RELEASED: [u8; 8] = [0; 8];
+
+// HID class requests (bmRequestType, bRequest, wValue, wIndex), the interface is 0
+const GET_REPORT_DESCRIPTOR: (u8, u8, u16, u16) = (0x81, 0x06, 0x2200, 0);
+const GET_PROTOCOL: (u8, u8, u16, u16) = (0xA1, 0x03, 0, 0);
+const SET_BOOT_PROTOCOL: (u8, u8, u16, u16) = (0x21, 0x0B, 0, 0);
+const SET_IDLE: (u8, u8, u16, u16) = (0x21, 0x0A, 0, 0);
+const SET_LEDS: (u8, u8, u16, u16) = (0x21, 0x09, 0x0200, 0);
+
+fn report(modifiers: u8, code: u8) -> Vec<u8> {
+    vec![modifiers, 0, code, 0, 0, 0, 0, 0]
+}
+
+fn request(layout: Layout, text: &str) -> Request {
+    Request { layout, text: text.into() }
+}
+
+/// Takes the reports until the keyboard stops writing.
+fn receive_reports(bus: &MockBus, keyboard: &mut Keyboard<'_, MockBus>) -> Vec<Vec<u8>> {
+    let interrupt_in = bus.endpoint(EndpointType::Interrupt, UsbDirection::In);
+    let mut reports = Vec::new();
+    while let Some(report) = bus.receive(interrupt_in) {
+        keyboard.endpoint_in_complete(interrupt_in);
+        reports.push(report);
+    }
+    reports
+}
+
+fn type_text(
+    bus: &MockBus,
+    keyboard: &mut Keyboard<'_, MockBus>,
+    requester: &mut Requester<KeyboardInterchange>,
+    request: Request,
+) -> Vec<Vec<u8>> {
+    requester.request(&request).unwrap();
+    keyboard.check_for_app_request();
+    let reports = receive_reports(bus, keyboard);
+    assert_eq!(requester.take_response(), Some(Ok(())));
+    reports
+}
+
+#[test]
+fn keyboard() {
+    let bus = MockBus::new();
+    let allocator = UsbBusAllocator::new(bus.clone());
+    let (mut requester, responder) = KeyboardInterchange::claim().unwrap();
+    let mut keyboard = Keyboard::new(&allocator, responder);
+    let mut device = UsbDeviceBuilder::new(&allocator, UsbVidPid(0x1209, 0x0001))
+        .max_packet_size_0(64)
+        .build();
+
+    // the host reads the boot keyboard report descriptor, and configures the keyboard
+    let descriptor = bus.control_in(&mut device, &mut [&mut keyboard], GET_REPORT_DESCRIPTOR, 255).unwrap();
+    assert_eq!(descriptor.len(), 63);
+    assert_eq!(&descriptor[..4], &[0x05, 0x01, 0x09, 0x06]);
+    assert!(bus.control_out(&mut device, &mut [&mut keyboard], SET_IDLE, &[]));
+    assert!(bus.control_out(&mut device, &mut [&mut keyboard], SET_BOOT_PROTOCOL, &[]));
+    assert_eq!(bus.control_in(&mut device, &mut [&mut keyboard], GET_PROTOCOL, 1), Some(vec![0]));
+    // the LEDs are ignored
+    assert!(bus.control_out(&mut device, &mut [&mut keyboard], SET_LEDS, &[0x01]));
+
+    // each key is released, so repeated characters are typed
+    let reports = type_text(&bus, &mut keyboard, &mut requester, request(Layout::Us, "Aa1\n"));
+    assert_eq!(reports, vec![
+        report(LEFT_SHIFT, 0x04), RELEASED.to_vec(),
+        report(0, 0x04), RELEASED.to_vec(),
+        report(0, 0x1e), RELEASED.to_vec(),
+        report(0, 0x28), RELEASED.to_vec(),
+    ]);
+
+    // QWERTZ, shifted, and AltGr
+    let reports = type_text(&bus, &mut keyboard, &mut requester, request(Layout::De, "zY@"));
+    assert_eq!(reports, vec![
+        report(0, 0x1c), RELEASED.to_vec(),
+        report(LEFT_SHIFT, 0x1d), RELEASED.to_vec(),
+        report(RIGHT_ALT, 0x14), RELEASED.to_vec(),
+    ]);
+
+    // nothing is typed if a character is not on the layout
+    requester.request(&request(Layout::Us, "12ä")).unwrap();
+    keyboard.check_for_app_request();
+    assert!(receive_reports(&bus, &mut keyboard).is_empty());
+    assert_eq!(requester.take_response(), Some(Err(Error::UnsupportedCharacter)));
+
+    // the empty text is typed at once
+    let reports = type_text(&bus, &mut keyboard, &mut requester, request(Layout::Us, ""));
+    assert!(reports.is_empty());
+
+    // a bus reset interrupts typing
+    requester.request(&request(Layout::Us, "123456")).unwrap();
+    keyboard.check_for_app_request();
+    keyboard.reset();
+    assert_eq!(requester.take_response(), Some(Err(Error::Interrupted)));
+}
+
+#[test]
+fn keymaps() {
+    // dead keys are followed by a space
+    let keys = keymap::keys(Layout::De, '^').unwrap();
+    assert_eq!(&keys[..], &[Key { modifiers: 0, code: 0x35 }, Key { modifiers: 0, code: 0x2c }]);
+    assert_eq!(keymap::keys(Layout::Us, '^').unwrap().len(), 1);
+
+    // digits and letters but z, y are at the same position
+    for c in "0123456789abcdefghijklmnopqrstuvwxABCDEFGHIJKLMNOPQRSTUVWX".chars() {
+        assert_eq!(keymap::keys(Layout::Us, c), keymap::keys(Layout::De, c));
+    }
+    assert_ne!(keymap::keys(Layout::Us, 'z'), keymap::keys(Layout::De, 'z'));
+
+    assert!(keymap::keys(Layout::Us, '€').is_none());
+    assert!(keymap::keys(Layout::De, '€').is_some());
+}
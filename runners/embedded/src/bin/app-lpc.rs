@@ -21,6 +21,7 @@ mod app {
         apps: ERL::types::Apps,
         apdu_dispatch: ERL::types::ApduDispatch,
         ctaphid_dispatch: ERL::types::CtaphidDispatch,
+        escape_dispatch: ERL::types::EscapeDispatch,
         usb_classes: Option<ERL::types::usbnfc::UsbClasses>,
         contactless: Option<ERL::types::Iso14443>,
         boot_mode: BootMode,
@@ -167,12 +168,13 @@ mod app {
         };
         // out: { iso14443, contactless_responder }
 
+        /* -> initializer::initialize_flash() */
+        let mut rng = hal.rng.enabled(syscon);
+
         /* -> initializer::initialize_interfaces() */
-        let usbnfcinit = ERL::init_usb_nfc(usbd_ref, nfc_dev);
+        let usbnfcinit = ERL::init_usb_nfc(usbd_ref, nfc_dev, &mut rng);
         // out: { apdu_dispatch, ctaphid_dispatch }
 
-        /* -> initializer::initialize_flash() */
-        let mut rng = hal.rng.enabled(syscon);
         let prince = hal.prince.enabled(&mut rng);
         prince.disable_all_region_2();
         let flash_gordon = lpc55_hal::FlashGordon::new(hal.flash.enabled(syscon));
@@ -203,6 +205,7 @@ mod app {
             &mut trussed_service,
             &store,
             bootmode == BootMode::NFCPassive,
+            usbnfcinit.keyboard,
         );
 
         // compose LateResources
@@ -212,6 +215,7 @@ mod app {
                 apps,
                 apdu_dispatch: usbnfcinit.apdu_dispatch,
                 ctaphid_dispatch: usbnfcinit.ctaphid_dispatch,
+                escape_dispatch: usbnfcinit.escape_dispatch,
                 usb_classes: usbnfcinit.usb_classes,
                 contactless: usbnfcinit.iso14443,
                 boot_mode: bootmode,
@@ -223,20 +227,69 @@ mod app {
         )
     }
 
-    #[idle()]
-    fn idle(_ctx: idle::Context) -> ! {
-        /*
-           Note: ARM SysTick stops in WFI. This is unfortunate as
-           - RTIC uses SysTick for its schedule() feature
-           - we would really like to use WFI in order to save power
-           In the future, we might even consider entering "System OFF".
-           In short, don't expect schedule() to work.
-        */
+    #[idle(shared = [apps, apdu_dispatch, ctaphid_dispatch, escape_dispatch, usb_classes, contactless])]
+    fn idle(ctx: idle::Context) -> ! {
+        let idle::SharedResources {
+            mut apps,
+            mut apdu_dispatch,
+            mut ctaphid_dispatch,
+            mut escape_dispatch,
+            mut usb_classes,
+            mut contactless,
+        } = ctx.shared;
+
+        trace!("idle");
+        // the dispatchers are polled continuously, so no WFI, cf. app-nrf
+        // (note that ARM SysTick stops in WFI, so RTIC's schedule() would not work either)
+
         loop {
-            trace!("idle");
             Delogger::flush();
-            cortex_m::asm::wfi();
+
+            let ccid_session_reset = usb_classes.lock(|usb_classes| {
+                ERL::runtime::ccid_session_reset(usb_classes)
+            });
+            let nfc_session_reset = contactless.lock(|contactless| {
+                ERL::runtime::nfc_session_reset(contactless)
+            });
+
+            let (usb_activity, _nfc_activity) = apps.lock(|apps| {
+                apdu_dispatch.lock(|apdu_dispatch| {
+                    ctaphid_dispatch.lock(|ctaphid_dispatch| {
+                        escape_dispatch.lock(|escape_dispatch| {
+                            ERL::runtime::poll_dispatchers(
+                                apdu_dispatch,
+                                ctaphid_dispatch,
+                                escape_dispatch,
+                                apps,
+                                ccid_session_reset || nfc_session_reset,
+                            )
+                        })
+                    })
+                })
+            });
+            apps.lock(|apps| ERL::runtime::poll_touch(apps));
+            if usb_activity {
+                /*trace!("app->usb");*/
+                rtic::pend(lpc55_hal::raw::Interrupt::USB1);
+            }
         }
         // loop {}
     }
+
+    #[task(priority = 3, binds = USB1, shared = [usb_classes])]
+    fn task_usb(ctx: task_usb::Context) {
+        // trace!("irq USB");
+        let mut usb_classes = ctx.shared.usb_classes;
+
+        usb_classes.lock(|usb_classes| {
+            if let Some(usb_classes) = usb_classes.as_mut() {
+                usb_classes.poll();
+
+                // the bulk packet size follows the speed negotiated at the last bus reset,
+                // e.g. full speed behind a USB 1.1 hub
+                #[cfg(all(feature = "highspeed", not(feature = "usbfs-peripheral")))]
+                usb_classes.ccid.set_bus_speed(ERL::soc::usb_bus_speed());
+            }
+        });
+    }
 }
@@ -26,6 +26,7 @@ mod app {
         apps: ERL::types::Apps,
         apdu_dispatch: ERL::types::ApduDispatch,
         ctaphid_dispatch: ERL::types::CtaphidDispatch,
+        escape_dispatch: ERL::types::EscapeDispatch,
         usb_classes: Option<ERL::types::usbnfc::UsbClasses>,
         contactless: Option<ERL::types::Iso14443>,
         /* NRF specific elements */
@@ -114,7 +115,10 @@ mod app {
 
         let store: ERL::types::RunnerStore = ERL::init_store(internal_flash, extflash);
 
-        let usbnfcinit = ERL::init_usb_nfc(usbd_ref, None);
+        let dev_rng = Rng::new(ctx.device.RNG);
+        let mut chacha_rng = chacha20::ChaCha8Rng::from_rng(dev_rng).unwrap();
+
+        let usbnfcinit = ERL::init_usb_nfc(usbd_ref, None, &mut chacha_rng);
         /* TODO: set up fingerprint device */
         /* TODO: set up SE050 device */
 
@@ -176,9 +180,6 @@ mod app {
 
         /* TODO: set up display */
 
-        let dev_rng = Rng::new(ctx.device.RNG);
-        let chacha_rng = chacha20::ChaCha8Rng::from_rng(dev_rng).unwrap();
-
         #[cfg(feature = "board-nk3am")]
         let ui = ERL::soc::board::init_ui(
             board_gpio.rgb_led,
@@ -196,7 +197,12 @@ mod app {
 
         let mut trussed_service = trussed::service::Service::new(platform);
 
-        let apps = ERL::init_apps(&mut trussed_service, &store, !powered_by_usb);
+        let apps = ERL::init_apps(
+            &mut trussed_service,
+            &store,
+            !powered_by_usb,
+            usbnfcinit.keyboard,
+        );
 
         let rtc_mono = RtcMonotonic::new(ctx.device.RTC0);
 
@@ -209,6 +215,7 @@ mod app {
                 apps,
                 apdu_dispatch: usbnfcinit.apdu_dispatch,
                 ctaphid_dispatch: usbnfcinit.ctaphid_dispatch,
+                escape_dispatch: usbnfcinit.escape_dispatch,
                 usb_classes: usbnfcinit.usb_classes,
                 contactless: usbnfcinit.iso14443,
             },
@@ -220,12 +227,13 @@ mod app {
         )
     }
 
-    #[idle(shared = [apps, apdu_dispatch, ctaphid_dispatch, usb_classes, contactless])]
+    #[idle(shared = [apps, apdu_dispatch, ctaphid_dispatch, escape_dispatch, usb_classes, contactless])]
     fn idle(ctx: idle::Context) -> ! {
         let idle::SharedResources {
             mut apps,
             mut apdu_dispatch,
             mut ctaphid_dispatch,
+            mut escape_dispatch,
             mut usb_classes,
             mut contactless,
         } = ctx.shared;
@@ -237,13 +245,29 @@ mod app {
         loop {
             Delogger::flush();
 
+            let ccid_session_reset = usb_classes.lock(|usb_classes| {
+                ERL::runtime::ccid_session_reset(usb_classes)
+            });
+            let nfc_session_reset = contactless.lock(|contactless| {
+                ERL::runtime::nfc_session_reset(contactless)
+            });
+
             let (usb_activity, _nfc_activity) = apps.lock(|apps| {
                 apdu_dispatch.lock(|apdu_dispatch| {
                     ctaphid_dispatch.lock(|ctaphid_dispatch| {
-                        ERL::runtime::poll_dispatchers(apdu_dispatch, ctaphid_dispatch, apps)
+                        escape_dispatch.lock(|escape_dispatch| {
+                            ERL::runtime::poll_dispatchers(
+                                apdu_dispatch,
+                                ctaphid_dispatch,
+                                escape_dispatch,
+                                apps,
+                                ccid_session_reset || nfc_session_reset,
+                            )
+                        })
                     })
                 })
             });
+            apps.lock(|apps| ERL::runtime::poll_touch(apps));
             if usb_activity {
                 /*trace!("app->usb");*/
                 rtic::pend(nrf52840_pac::Interrupt::USBD);
@@ -0,0 +1,42 @@
+use usbd_ccid::escape::{App, Error, Message};
+
+use crate::soc::types::Soc as SocT;
+use crate::types::{build_constants, Soc};
+
+// same command bytes as the CTAPHID vendor commands of the admin app
+const VERSION: u8 = 0x61;
+const UUID: u8 = 0x62;
+
+/// Answers PC_to_RDR_Escape queries for the firmware version and device UUID,
+/// so tools can identify the device without selecting an app.
+#[derive(Default)]
+pub struct DeviceInfo {}
+
+impl App for DeviceInfo {
+    fn commands(&self) -> &'static [u8] {
+        &[VERSION, UUID]
+    }
+
+    fn call(&mut self, command: u8, request: &[u8], response: &mut Message) -> Result<(), Error> {
+        if !request.is_empty() {
+            return Err(Error::InvalidLength);
+        }
+        match command {
+            VERSION => {
+                response
+                    .extend_from_slice(&build_constants::CARGO_PKG_VERSION.to_be_bytes())
+                    .ok();
+            }
+            UUID => {
+                let uuid = <SocT as Soc>::device_uuid();
+                // not read from the chip (yet)
+                if uuid.iter().all(|&byte| byte == 0) {
+                    return Err(Error::NoResponse);
+                }
+                response.extend_from_slice(uuid).ok();
+            }
+            _ => return Err(Error::InvalidCommand),
+        }
+        Ok(())
+    }
+}
@@ -9,6 +9,7 @@ use usb_device::device::{UsbDeviceBuilder, UsbVidPid};
 extern crate delog;
 delog::generate_macros!();
 
+pub mod escape;
 pub mod runtime;
 pub mod traits;
 pub mod types;
@@ -104,9 +105,10 @@ pub fn init_store(
     types::RunnerStore::init_raw(ifs, efs, vfs)
 }
 
-pub fn init_usb_nfc(
+pub fn init_usb_nfc<R: rand_core::CryptoRng + rand_core::RngCore>(
     usbbus_opt: Option<&'static usb_device::bus::UsbBusAllocator<<SocT as Soc>::UsbBus>>,
     nfcdev_opt: Option<<SocT as Soc>::NfcDevice>,
+    rng: &mut R,
 ) -> types::usbnfc::UsbNfcInit {
     let config = <SocT as Soc>::INTERFACE_CONFIG;
 
@@ -114,25 +116,46 @@ pub fn init_usb_nfc(
     let (ccid_rq, ccid_rp) = apdu_dispatch::interchanges::Contact::claim().unwrap();
     let (nfc_rq, nfc_rp) = apdu_dispatch::interchanges::Contactless::claim().unwrap();
     let (ctaphid_rq, ctaphid_rp) = ctaphid_dispatch::types::HidInterchange::claim().unwrap();
+    let (keyboard_rq, keyboard_rp) = usbd_keyboard::types::KeyboardInterchange::claim().unwrap();
+    let (escape_rq, escape_rp) = usbd_ccid::escape::EscapeInterchange::claim().unwrap();
 
     /* initialize dispatchers */
     let apdu_dispatch = apdu_dispatch::dispatch::ApduDispatch::new(ccid_rp, nfc_rp);
     let ctaphid_dispatch = ctaphid_dispatch::dispatch::Dispatch::new(ctaphid_rp);
+    let escape_dispatch = usbd_ccid::escape::Dispatch::new(escape_rp);
 
     /* populate requesters (if bus options are provided) */
     let mut usb_classes = None;
+    let mut keyboard_requester = None;
 
     if let Some(usbbus) = usbbus_opt {
         /* Class #1: CCID */
-        let ccid = usbd_ccid::Ccid::new(usbbus, ccid_rq, Some(config.card_issuer));
+        let atr = usbd_ccid::Atr::new()
+            .with_protocol(1)
+            .with_card_issuers_data(config.card_issuer);
+        // time extensions that keep up with the BWT, so e.g. pcscd does not time out
+        let mut ccid = usbd_ccid::Ccid::new(usbbus, ccid_rq, atr)
+            .with_escape_channel(escape_rq)
+            .with_time_extension_policy(usbd_ccid::time_extension::Policy::adaptive());
+        if config.spare_endpoint == types::SpareEndpoint::CcidInterrupt {
+            ccid = ccid.with_interrupt_endpoint(usbbus);
+        }
 
         /* Class #2: CTAPHID */
-        let ctaphid = usbd_ctaphid::CtapHid::new(usbbus, ctaphid_rq, 0u32)
+        let ctaphid = usbd_ctaphid::CtapHid::new(usbbus, ctaphid_rq, 0u32, rng)
             .implements_ctap1()
             .implements_ctap2()
             .implements_wink();
 
-        /* Class #3: Serial */
+        /* Class #3: Keyboard */
+        let keyboard = if config.spare_endpoint == types::SpareEndpoint::Keyboard {
+            keyboard_requester = Some(keyboard_rq);
+            Some(usbd_keyboard::Keyboard::new(usbbus, keyboard_rp))
+        } else {
+            None
+        };
+
+        /* Class #4: Serial */
         let serial = usbd_serial::SerialPort::new(usbbus);
 
         let vidpid = UsbVidPid(config.usb_id_vendor, config.usb_id_product);
@@ -146,7 +169,7 @@ pub fn init_usb_nfc(
 			.build();
 
         usb_classes = Some(types::usbnfc::UsbClasses::new(
-            usbdev, ccid, ctaphid, serial,
+            usbdev, ccid, ctaphid, keyboard, serial,
         ));
     }
 
@@ -170,7 +193,9 @@ pub fn init_usb_nfc(
         usb_classes,
         apdu_dispatch,
         ctaphid_dispatch,
+        escape_dispatch,
         iso14443,
+        keyboard: keyboard_requester,
     }
 }
 
@@ -179,6 +204,7 @@ pub fn init_apps(
     trussed: &mut types::Trussed,
     store: &types::RunnerStore,
     on_nfc_power: bool,
+    keyboard: Option<interchange::Requester<usbd_keyboard::types::KeyboardInterchange>>,
 ) -> types::Apps {
     let store_2 = store.clone();
     let int_flash_ref = unsafe { types::INTERNAL_STORAGE.as_mut().unwrap() };
@@ -192,7 +218,7 @@ pub fn init_apps(
         uuid,
         rebooter,
     };
-    types::Apps::new(trussed, pnp)
+    types::Apps::new(trussed, keyboard, pnp)
 }
 
 #[cfg(not(feature = "provisioner-app"))]
@@ -200,8 +226,9 @@ pub fn init_apps(
     trussed: &mut types::Trussed,
     _store: &types::RunnerStore,
     _on_nfc_power: bool,
+    keyboard: Option<interchange::Requester<usbd_keyboard::types::KeyboardInterchange>>,
 ) -> types::Apps {
-    types::Apps::new(trussed)
+    types::Apps::new(trussed, keyboard)
 }
 
 #[inline(never)]
@@ -1,41 +1,41 @@
 use crate::soc::types::Soc as SocT;
 use crate::types::*;
+use crate::ui::{UserPresence, UserPresenceStatus};
 
-// Assuming there will only be one way to
-// get user presence, this should be fine.
-// Used for Ctaphid.keepalive message status.
-// ...
-// ...
-// I am pretty sure this does not belong here,
-// anyways better than having this both UIs:
-// dummy_ui.rs, trussed_ui.rs
-// so how about a `base_ui.rs` ?
-// -> also the whole RGB stuff and its "ecosystem" is widely hw-independant and could fit there....
-static mut WAITING: bool = false;
-pub struct UserPresenceStatus {}
-impl UserPresenceStatus {
-    pub(crate) fn set_waiting(waiting: bool) {
-        unsafe { WAITING = waiting };
-    }
-    pub fn waiting() -> bool {
-        unsafe { WAITING }
-    }
+fn user_presence_status() -> &'static UserPresenceStatus {
+    <<SocT as Soc>::TrussedUI as UserPresence>::user_presence_status()
 }
 
 pub fn poll_dispatchers(
     apdu_dispatch: &mut ApduDispatch,
     ctaphid_dispatch: &mut CtaphidDispatch,
+    escape_dispatch: &mut EscapeDispatch,
     apps: &mut Apps,
+    session_reset: bool,
 ) -> (bool, bool) {
+    if session_reset {
+        // the card was powered off or on, or left the field: like a real card,
+        // forget about the selected app and PIN verification before the next command
+        apps.reset_apdu_session();
+    }
+
     let apdu_poll = apps.apdu_dispatch(|apps| apdu_dispatch.poll(apps));
     let ctaphid_poll = apps.ctaphid_dispatch(|apps| ctaphid_dispatch.poll(apps));
+    let escape_poll = apps.escape_dispatch(|apps| escape_dispatch.poll(apps));
 
     (
-        apdu_poll == Some(apdu_dispatch::dispatch::Interface::Contact) || ctaphid_poll,
+        apdu_poll == Some(apdu_dispatch::dispatch::Interface::Contact) || ctaphid_poll || escape_poll,
         apdu_poll == Some(apdu_dispatch::dispatch::Interface::Contactless),
     )
 }
 
+/// Passes a touch outside of a user presence check on to the apps, e.g. to type a password.
+pub fn poll_touch(apps: &mut Apps) {
+    if <<SocT as Soc>::TrussedUI as UserPresence>::touches().take() {
+        apps.touched();
+    }
+}
+
 /* ************************************************************************ */
 
 pub fn poll_usb<FA, FB, TA, TB, E>(
@@ -57,7 +57,27 @@ pub fn poll_usb<FA, FB, TA, TB, E>(
     usb_classes.poll();
 
     maybe_spawn_ccid(usb_classes.ccid.did_start_processing(), ccid_spawner);
-    maybe_spawn_ctaphid(usb_classes.ctaphid.did_start_processing(), ctaphid_spawner);
+
+    let ctaphid_status = usb_classes.ctaphid.did_start_processing();
+    if let usbd_ctaphid::types::Status::ReceivedData(_) = ctaphid_status {
+        user_presence_status().set_cancelled(false);
+    }
+    if usb_classes.ctaphid.did_cancel_processing() {
+        user_presence_status().set_cancelled(true);
+    }
+    maybe_spawn_ctaphid(ctaphid_status, ctaphid_spawner);
+}
+
+pub fn ccid_session_reset(usb_classes: &mut Option<usbnfc::UsbClasses>) -> bool {
+    usb_classes
+        .as_mut()
+        .map_or(false, |usb_classes| usb_classes.ccid.did_reset_session(0))
+}
+
+pub fn nfc_session_reset(contactless: &mut Option<Iso14443>) -> bool {
+    contactless
+        .as_mut()
+        .map_or(false, |contactless| contactless.did_reset_session())
 }
 
 pub fn poll_nfc<F, T, E>(contactless: &mut Option<Iso14443>, nfc_spawner: F)
@@ -100,7 +120,7 @@ where
     maybe_spawn_ctaphid(
         usb_classes
             .ctaphid
-            .send_keepalive(UserPresenceStatus::waiting()),
+            .send_keepalive(user_presence_status().keepalive_status()),
         ctaphid_spawner,
     );
 }
@@ -86,6 +86,16 @@ pub type UsbPeripheralType = lpc55_hal::peripherals::usbfs::Usbfs;
 #[cfg(not(feature = "usbfs-peripheral"))]
 pub type UsbPeripheralType = lpc55_hal::peripherals::usbhs::Usbhs;
 
+/// The speed USBHS enumerated at, as negotiated at the last bus reset.
+#[cfg(all(feature = "highspeed", not(feature = "usbfs-peripheral")))]
+pub fn usb_bus_speed() -> usbd_ccid::constants::UsbSpeed {
+    let usb = unsafe { lpc55_hal::raw::Peripherals::steal().USB1 };
+    match usb.devcmdstat.read().speed().bits() {
+        0b10 => usbd_ccid::constants::UsbSpeed::HighSpeed,
+        _ => usbd_ccid::constants::UsbSpeed::FullSpeed,
+    }
+}
+
 type UsbBusType = usb_device::bus::UsbBusAllocator<<types::Soc as crate::types::Soc>::UsbBus>;
 type DelayTimer = Timer<lpc55_hal::peripherals::ctimer::Ctimer0<Enabled>>;
 
@@ -4,10 +4,10 @@
 use core::time::Duration;
 
 use crate::traits::{
-    buttons::{Edge, Press},
+    buttons::{Button, Edge, Press},
     rgb_led::RgbLed,
 };
-use crate::ui::Status;
+use crate::ui::{Status, Touches, UserPresence, UserPresenceStatus};
 use lpc55_hal::{peripherals::rtc::Rtc, typestates::init_state};
 use trussed::platform::{consent, reboot};
 
@@ -25,19 +25,8 @@ fn sin(x: f32) -> f32 {
     res
 }
 
-// Assuming there will only be one way to
-// get user presence, this should be fine.
-// Used for Ctaphid.keepalive message status.
-static mut WAITING: bool = false;
-pub struct UserPresenceStatus {}
-impl UserPresenceStatus {
-    pub(crate) fn set_waiting(waiting: bool) {
-        unsafe { WAITING = waiting };
-    }
-    pub fn waiting() -> bool {
-        unsafe { WAITING }
-    }
-}
+static USER_PRESENCE: UserPresenceStatus = UserPresenceStatus::new();
+static TOUCHES: Touches = Touches::new();
 
 pub struct UserInterface<BUTTONS, RGB>
 where
@@ -85,6 +74,14 @@ where
         ui.refresh_ui(uptime);
         ui
     }
+    fn cancel_user_presence(&mut self) -> consent::Level {
+        info!("user presence check cancelled");
+        let uptime = self.rtc.uptime();
+        self.status.update(trussed::platform::ui::Status::Idle, uptime);
+        self.refresh_ui(uptime);
+        consent::Level::None
+    }
+
     fn refresh_ui(&mut self, uptime: Duration) {
         if let Some(rgb) = &mut self.rgb {
             self.status.refresh(uptime);
@@ -94,21 +91,41 @@ where
     }
 }
 
+impl<BUTTONS, RGB> UserPresence for UserInterface<BUTTONS, RGB>
+where
+    BUTTONS: Press + Edge,
+    RGB: RgbLed,
+{
+    fn user_presence_status() -> &'static UserPresenceStatus {
+        &USER_PRESENCE
+    }
+
+    fn touches() -> &'static Touches {
+        &TOUCHES
+    }
+}
+
 impl<BUTTONS, RGB> trussed::platform::UserInterface for UserInterface<BUTTONS, RGB>
 where
     BUTTONS: Press + Edge,
     RGB: RgbLed,
 {
     fn check_user_presence(&mut self) -> consent::Level {
-        match &mut self.buttons {
+        // the host cancelled the request, stop asking for a press
+        if USER_PRESENCE.cancelled() {
+            return self.cancel_user_presence();
+        }
+
+        let level = match &mut self.buttons {
             Some(buttons) => {
                 // important to read state before checking for edge,
                 // since reading an edge could clear the state.
                 let state = buttons.state();
-                UserPresenceStatus::set_waiting(true);
+                USER_PRESENCE.set_waiting(true);
                 let press_result = buttons.wait_for_any_new_press();
-                UserPresenceStatus::set_waiting(false);
+                USER_PRESENCE.set_waiting(false);
                 if press_result.is_ok() {
+                    TOUCHES.consume();
                     if state.a && state.b {
                         consent::Level::Strong
                     } else {
@@ -123,7 +140,13 @@ where
                 // in passive NFC mode, which means user tapped to indicate presence.
                 consent::Level::Normal
             }
+        };
+
+        // each call polls the buttons once, the cancellation may arrive in between
+        if matches!(level, consent::Level::None) && USER_PRESENCE.cancelled() {
+            return self.cancel_user_presence();
         }
+        level
     }
 
     fn set_status(&mut self, status: trussed::platform::ui::Status) {
@@ -133,6 +156,9 @@ where
     }
 
     fn refresh(&mut self) {
+        if let Some(buttons) = self.buttons.as_mut() {
+            TOUCHES.track_button(buttons.is_pressed(Button::A));
+        }
         let uptime = self.uptime();
         self.refresh_ui(uptime);
     }
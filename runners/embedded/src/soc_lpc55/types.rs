@@ -39,6 +39,14 @@ const INTERFACE_CONFIG: crate::types::Config = crate::types::Config {
     usb_serial: "00000000-0000-0000-00000000",
     usb_id_vendor: crate::types::build_constants::USB_ID_VENDOR,
     usb_id_product: crate::types::build_constants::USB_ID_PRODUCT,
+    // USBHS has one endpoint more than USBFS, for the CCID interrupt endpoint or the keyboard
+    spare_endpoint: if cfg!(feature = "usbfs-peripheral") {
+        crate::types::SpareEndpoint::None
+    } else if cfg!(feature = "keyboard") {
+        crate::types::SpareEndpoint::Keyboard
+    } else {
+        crate::types::SpareEndpoint::CcidInterrupt
+    },
 };
 
 pub struct Soc {}
@@ -1,5 +1,11 @@
 use trussed::platform::{consent, reboot, ui};
 
+use crate::ui::{Touches, UserPresence, UserPresenceStatus};
+
+// never waiting, there is nothing to press
+static USER_PRESENCE: UserPresenceStatus = UserPresenceStatus::new();
+static TOUCHES: Touches = Touches::new();
+
 pub struct DummyUI {}
 
 impl DummyUI {
@@ -8,6 +14,16 @@ impl DummyUI {
     }
 }
 
+impl UserPresence for DummyUI {
+    fn user_presence_status() -> &'static UserPresenceStatus {
+        &USER_PRESENCE
+    }
+
+    fn touches() -> &'static Touches {
+        &TOUCHES
+    }
+}
+
 impl trussed::platform::UserInterface for DummyUI {
     fn check_user_presence(&mut self) -> consent::Level {
         consent::Level::None
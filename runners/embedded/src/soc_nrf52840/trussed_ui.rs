@@ -9,14 +9,15 @@ use crate::traits::{
 };
 use trussed::platform::{consent, ui};
 
-use crate::runtime::UserPresenceStatus;
-
-use crate::ui::Status;
+use crate::ui::{Status, Touches, UserPresence, UserPresenceStatus};
 
 use embedded_time::duration::*;
 use rtic::Monotonic;
 type RtcMonotonic = crate::soc::rtic_monotonic::RtcMonotonic;
 
+static USER_PRESENCE: UserPresenceStatus = UserPresenceStatus::new();
+static TOUCHES: Touches = Touches::new();
+
 pub struct UserInterface<BUTTONS, RGB>
 where
     BUTTONS: Press,
@@ -73,6 +74,20 @@ where
     }
 }
 
+impl<BUTTONS, RGB> UserPresence for UserInterface<BUTTONS, RGB>
+where
+    BUTTONS: Press,
+    RGB: RgbLed,
+{
+    fn user_presence_status() -> &'static UserPresenceStatus {
+        &USER_PRESENCE
+    }
+
+    fn touches() -> &'static Touches {
+        &TOUCHES
+    }
+}
+
 impl<BUTTONS, RGB> trussed::platform::UserInterface for UserInterface<BUTTONS, RGB>
 where
     BUTTONS: Press,
@@ -97,6 +112,7 @@ where
         let mut next_check = start_time + 25u128;
 
         self.set_status(ui::Status::WaitingForUserPresence);
+        USER_PRESENCE.set_waiting(true);
 
         loop {
             let cur_time = self.uptime().as_millis();
@@ -105,15 +121,19 @@ where
             if cur_time > timeout_at {
                 break;
             }
+            // the host cancelled the request
+            if USER_PRESENCE.cancelled() {
+                info!("user presence check cancelled");
+                self.set_status(ui::Status::Idle);
+                break;
+            }
             // loop until next check shall be done
             if cur_time < next_check {
                 continue;
             }
 
             if let Some(button) = self.buttons.as_mut() {
-                UserPresenceStatus::set_waiting(true);
                 is_pressed = button.is_pressed(Button::A);
-                UserPresenceStatus::set_waiting(false);
             }
 
             if is_pressed {
@@ -130,6 +150,9 @@ where
             }
         }
 
+        USER_PRESENCE.set_waiting(false);
+        TOUCHES.consume();
+
         // consent, if we've counted 3 "presses"
         if counter >= threshold {
             consent::Level::Normal
@@ -145,6 +168,9 @@ where
     }
 
     fn refresh(&mut self) {
+        if let Some(buttons) = self.buttons.as_mut() {
+            TOUCHES.track_button(buttons.is_pressed(Button::A));
+        }
         let uptime = self.uptime();
         self.refresh_ui(uptime);
     }
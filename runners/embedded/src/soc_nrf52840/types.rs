@@ -18,6 +18,11 @@ const INTERFACE_CONFIG: crate::types::Config = crate::types::Config {
     usb_serial: "00000000-0000-0000-00000000",
     usb_id_vendor: crate::types::build_constants::USB_ID_VENDOR,
     usb_id_product: crate::types::build_constants::USB_ID_PRODUCT,
+    spare_endpoint: if cfg!(feature = "keyboard") {
+        crate::types::SpareEndpoint::Keyboard
+    } else {
+        crate::types::SpareEndpoint::CcidInterrupt
+    },
 };
 
 /* the base address of the internal filesystem is compile-time configurable
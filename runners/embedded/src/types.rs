@@ -7,6 +7,7 @@ pub use apdu_dispatch::{
 use core::convert::TryInto;
 use core::time::Duration;
 pub use ctaphid_dispatch::app::App as CtaphidApp;
+pub use usbd_ccid::escape::App as EscapeApp;
 use interchange::Interchange;
 use littlefs2::{const_ram_storage, fs::Allocation, fs::Filesystem};
 use trussed::types::{LfsResult, LfsStorage};
@@ -31,6 +32,22 @@ pub struct Config {
     // pub usb_release: u16 --> taken from build_constants::USB_RELEASE
     pub usb_id_vendor: u16,
     pub usb_id_product: u16,
+    pub spare_endpoint: SpareEndpoint,
+}
+
+/// What the interrupt IN endpoint left next to CCID, CTAPHID and serial is used for.
+///
+/// The CCID interrupt endpoint and the keyboard are mutually exclusive, as the
+/// USBHS peripheral of the LPC55 has a single endpoint to spare; the `keyboard`
+/// feature picks the keyboard on every SoC.  Without the CCID interrupt endpoint,
+/// hosts poll the slot status instead of being notified.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpareEndpoint {
+    None,
+    /// Slot change notifications, cf. `usbd_ccid::Ccid::with_interrupt_endpoint`.
+    CcidInterrupt,
+    /// A HID keyboard, for apps to type e.g. passwords.
+    Keyboard,
 }
 
 pub trait Soc {
@@ -40,7 +57,7 @@ pub trait Soc {
     type UsbBus;
     type NfcDevice;
     type Rng;
-    type TrussedUI;
+    type TrussedUI: crate::ui::UserPresence;
     type Reboot;
     type UUID;
 
@@ -102,6 +119,7 @@ pub type Iso14443 = nfc_device::Iso14443<<SocT as Soc>::NfcDevice>;
 
 pub type ApduDispatch = apdu_dispatch::dispatch::ApduDispatch;
 pub type CtaphidDispatch = ctaphid_dispatch::dispatch::Dispatch;
+pub type EscapeDispatch = usbd_ccid::escape::Dispatch;
 
 #[cfg(feature = "admin-app")]
 pub type AdminApp = admin_app::App<TrussedClient, <SocT as Soc>::Reboot>;
@@ -111,6 +129,8 @@ pub type OathApp = oath_authenticator::Authenticator<TrussedClient>;
 pub type FidoApp = fido_authenticator::Authenticator<fido_authenticator::Conforming, TrussedClient>;
 #[cfg(feature = "ndef-app")]
 pub type NdefApp = ndef_app::App<'static>;
+#[cfg(feature = "static-password-app")]
+pub type StaticPasswordApp = static_password_app::App<TrussedClient>;
 #[cfg(feature = "provisioner-app")]
 pub type ProvisionerApp =
     provisioner_app::Provisioner<RunnerStore, <SocT as Soc>::InternalFlashStorage, TrussedClient>;
@@ -179,6 +199,16 @@ impl TrussedApp for FidoApp {
     }
 }
 
+#[cfg(feature = "static-password-app")]
+impl TrussedApp for StaticPasswordApp {
+    const CLIENT_ID: &'static [u8] = b"password\0";
+
+    type NonPortable = Option<interchange::Requester<usbd_keyboard::types::KeyboardInterchange>>;
+    fn with_client(trussed: TrussedClient, keyboard: Self::NonPortable) -> Self {
+        Self::new(trussed, keyboard)
+    }
+}
+
 pub struct ProvisionerNonPortable {
     pub store: RunnerStore,
     pub stolen_filesystem: &'static mut <SocT as Soc>::InternalFlashStorage,
@@ -222,13 +252,18 @@ pub struct Apps {
     pub oath: OathApp,
     #[cfg(feature = "ndef-app")]
     pub ndef: NdefApp,
+    #[cfg(feature = "static-password-app")]
+    pub static_password: StaticPasswordApp,
     #[cfg(feature = "provisioner-app")]
     pub provisioner: ProvisionerApp,
+    pub device_info: crate::escape::DeviceInfo,
+    apdu_session: apdu_session::Session,
 }
 
 impl Apps {
     pub fn new(
         trussed: &mut trussed::Service<RunnerPlatform>,
+        _keyboard: Option<interchange::Requester<usbd_keyboard::types::KeyboardInterchange>>,
         #[cfg(feature = "provisioner-app")] provisioner: ProvisionerNonPortable,
     ) -> Self {
         #[cfg(feature = "admin-app")]
@@ -239,8 +274,11 @@ impl Apps {
         let oath = OathApp::with(trussed, ());
         #[cfg(feature = "ndef-app")]
         let ndef = NdefApp::new();
+        #[cfg(feature = "static-password-app")]
+        let static_password = StaticPasswordApp::with(trussed, _keyboard);
         #[cfg(feature = "provisioner-app")]
         let provisioner = ProvisionerApp::with(trussed, provisioner);
+        let device_info = Default::default();
 
         Self {
             #[cfg(feature = "admin-app")]
@@ -251,8 +289,12 @@ impl Apps {
             oath,
             #[cfg(feature = "ndef-app")]
             ndef,
+            #[cfg(feature = "static-password-app")]
+            static_password,
             #[cfg(feature = "provisioner-app")]
             provisioner,
+            device_info,
+            apdu_session: Default::default(),
         }
     }
 
@@ -260,7 +302,23 @@ impl Apps {
     where
         F: FnOnce(&mut [&mut dyn ApduApp<ApduCommandSize, ApduResponseSize>]) -> T,
     {
-        f(&mut [
+        self.apdu_apps(|session, apps| session.dispatch(apps, f))
+    }
+
+    /// Deselects the selected APDU app, once the card was powered off or on,
+    /// or left the field.
+    pub fn reset_apdu_session(&mut self) {
+        self.apdu_apps(|session, apps| session.reset(apps))
+    }
+
+    fn apdu_apps<F, T>(&mut self, f: F) -> T
+    where
+        F: FnOnce(
+            &mut apdu_session::Session,
+            &mut [&mut dyn ApduApp<ApduCommandSize, ApduResponseSize>],
+        ) -> T,
+    {
+        f(&mut self.apdu_session, &mut [
             #[cfg(feature = "ndef-app")]
             &mut self.ndef,
             #[cfg(feature = "oath-authenticator")]
@@ -269,11 +327,19 @@ impl Apps {
             &mut self.fido,
             #[cfg(feature = "admin-app")]
             &mut self.admin,
+            #[cfg(feature = "static-password-app")]
+            &mut self.static_password,
             #[cfg(feature = "provisioner-app")]
             &mut self.provisioner,
         ])
     }
 
+    /// The user touched the device outside of a user presence check.
+    pub fn touched(&mut self) {
+        #[cfg(feature = "static-password-app")]
+        self.static_password.touched();
+    }
+
     pub fn ctaphid_dispatch<F, T>(&mut self, f: F) -> T
     where
         F: FnOnce(&mut [&mut dyn CtaphidApp]) -> T,
@@ -285,6 +351,13 @@ impl Apps {
             &mut self.admin,
         ])
     }
+
+    pub fn escape_dispatch<F, T>(&mut self, f: F) -> T
+    where
+        F: FnOnce(&mut [&mut dyn EscapeApp]) -> T,
+    {
+        f(&mut [&mut self.device_info])
+    }
 }
 
 #[derive(Debug)]
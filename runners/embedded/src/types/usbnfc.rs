@@ -8,7 +8,7 @@ pub type CcidClass = usbd_ccid::Ccid<
     { apdu_dispatch::interchanges::SIZE },
 >;
 pub type CtapHidClass = usbd_ctaphid::CtapHid<'static, <SocT as Soc>::UsbBus>;
-// pub type KeyboardClass = usbd_hid::hid_class::HIDClass<'static, <SocT as Soc>::UsbBus>;
+pub type KeyboardClass = usbd_keyboard::Keyboard<'static, <SocT as Soc>::UsbBus>;
 pub type SerialClass = usbd_serial::SerialPort<'static, <SocT as Soc>::UsbBus>;
 
 type Usbd = usb_device::device::UsbDevice<'static, <SocT as Soc>::UsbBus>;
@@ -17,24 +17,44 @@ pub struct UsbClasses {
     pub usbd: Usbd,
     pub ccid: CcidClass,
     pub ctaphid: CtapHidClass,
-    // pub keyboard: KeyboardClass,
+    pub keyboard: Option<KeyboardClass>,
     pub serial: SerialClass,
 }
 
 impl UsbClasses {
-    pub fn new(usbd: Usbd, ccid: CcidClass, ctaphid: CtapHidClass, serial: SerialClass) -> Self {
+    pub fn new(
+        usbd: Usbd,
+        ccid: CcidClass,
+        ctaphid: CtapHidClass,
+        keyboard: Option<KeyboardClass>,
+        serial: SerialClass,
+    ) -> Self {
         Self {
             usbd,
             ccid,
             ctaphid,
+            keyboard,
             serial,
         }
     }
     pub fn poll(&mut self) {
         self.ctaphid.check_for_app_response();
         self.ccid.check_for_app_response();
-        self.usbd
-            .poll(&mut [&mut self.ccid, &mut self.ctaphid, &mut self.serial]);
+        match self.keyboard.as_mut() {
+            Some(keyboard) => {
+                keyboard.check_for_app_request();
+                self.usbd.poll(&mut [
+                    &mut self.ccid,
+                    &mut self.ctaphid,
+                    keyboard,
+                    &mut self.serial,
+                ]);
+            }
+            None => {
+                self.usbd
+                    .poll(&mut [&mut self.ccid, &mut self.ctaphid, &mut self.serial]);
+            }
+        }
     }
 }
 
@@ -42,5 +62,8 @@ pub struct UsbNfcInit {
     pub usb_classes: Option<UsbClasses>,
     pub apdu_dispatch: apdu_dispatch::dispatch::ApduDispatch,
     pub ctaphid_dispatch: ctaphid_dispatch::dispatch::Dispatch,
+    pub escape_dispatch: usbd_ccid::escape::Dispatch,
     pub iso14443: Option<super::Iso14443>,
+    // for apps to type text, if the keyboard is configured
+    pub keyboard: Option<interchange::Requester<usbd_keyboard::types::KeyboardInterchange>>,
 }
@@ -1,4 +1,8 @@
-use core::{ops::Range, time::Duration};
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
 
 use trussed::platform::ui;
 
@@ -113,3 +117,45 @@ impl LedMode {
         }
     }
 }
+
+/// Presses of the button outside of user presence checks, for the apps,
+/// e.g. to type a password on touch.
+pub struct Touches {
+    // the button was pressed at the last refresh, or consumed by a user presence check
+    pressed: AtomicBool,
+    // a press outside of a user presence check, until taken
+    touched: AtomicBool,
+}
+
+impl Touches {
+    pub const fn new() -> Self {
+        Self {
+            pressed: AtomicBool::new(false),
+            touched: AtomicBool::new(false),
+        }
+    }
+
+    /// The press ending a user presence check is not a touch, even if the button is still held.
+    pub(crate) fn consume(&self) {
+        self.pressed.store(true, Ordering::Relaxed);
+    }
+
+    /// Follows the button outside of user presence checks, a new press is a touch.
+    pub(crate) fn track_button(&self, pressed: bool) {
+        let was_pressed = self.pressed.swap(pressed, Ordering::Relaxed);
+        if pressed && !was_pressed {
+            self.touched.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Whether the user touched the device since the last call.
+    pub fn take(&self) -> bool {
+        self.touched.swap(false, Ordering::Relaxed)
+    }
+}
+
+impl Default for Touches {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -459,6 +459,7 @@ impl Initializer {
         &mut self,
         clock_stage: &mut stages::Clock,
         basic_stage: &mut stages::Basic,
+        flash_stage: &mut stages::Flash,
         _usbhs: hal::peripherals::usbhs::Usbhs<Unknown>,
         _usbfs: hal::peripherals::usbfs::Usbfs<Unknown>,
     ) -> stages::Usb {
@@ -472,6 +473,13 @@ impl Initializer {
         let (ctaphid_requester, ctaphid_responder) = ctaphid_dispatch::types::HidInterchange::claim()
             .expect("could not setup HidInterchange");
 
+        let (escape_requester, escape_responder) = usbd_ccid::escape::EscapeInterchange::claim()
+            .expect("could not setup EscapeInterchange");
+
+        #[cfg(feature = "keyboard")]
+        let (keyboard_requester, keyboard_responder) = usbd_keyboard::types::KeyboardInterchange::claim()
+            .expect("could not setup KeyboardInterchange");
+
         info!("usb class start {} ms", basic_stage.perf_timer.elapsed().0/1000);
 
         let mut usb_classes: Option<types::UsbClasses> = None;
@@ -510,11 +518,22 @@ impl Initializer {
 
             // our USB classes (must be allocated in order that they're passed in `.poll(...)` later!)
             //
-            // NB: Card issuer's data can be at most 13 bytes (otherwise the constructor panics).
+            // NB: Card issuer's data can be at most 13 bytes (otherwise the ATR builder panics).
             // So for instance "Hacker Solo 2" would work, but "Solo 2 (custom)" would not.
-            let ccid = usbd_ccid::Ccid::new(usb_bus, contact_requester, Some(b"Nitrokey 3"));
+            let atr = usbd_ccid::Atr::new()
+                .with_protocol(1)
+                .with_card_issuers_data(b"Nitrokey 3");
+            // time extensions that keep up with the BWT, so e.g. pcscd does not time out
+            let ccid = usbd_ccid::Ccid::new(usb_bus, contact_requester, atr)
+                .with_escape_channel(escape_requester)
+                .with_time_extension_policy(usbd_ccid::time_extension::Policy::adaptive());
+            // USBHS has one endpoint more than USBFS, enough for either the CCID interrupt
+            // endpoint or the keyboard
+            #[cfg(not(any(feature = "usbfs-peripheral", feature = "keyboard")))]
+            let ccid = ccid.with_interrupt_endpoint(usb_bus);
             let current_time = basic_stage.perf_timer.elapsed().0/1000;
-            let mut ctaphid = usbd_ctaphid::CtapHid::new(usb_bus, ctaphid_requester, current_time)
+            let mut ctaphid = usbd_ctaphid::CtapHid::new(
+                usb_bus, ctaphid_requester, current_time, flash_stage.rng.as_mut().unwrap())
                 .implements_ctap1()
                 .implements_ctap2()
                 .implements_wink();
@@ -525,6 +544,9 @@ impl Initializer {
                 build: crate::build_constants::CARGO_PKG_VERSION_MINOR.to_be_bytes()[1],
             });
 
+            #[cfg(feature = "keyboard")]
+            let keyboard = usbd_keyboard::Keyboard::new(usb_bus, keyboard_responder);
+
             let serial = usbd_serial::SerialPort::new(usb_bus);
 
             // Only 16 bits, so take the upper bits of our semver
@@ -546,9 +568,19 @@ impl Initializer {
                 .composite_with_iads()
                 .build();
 
-            usb_classes = Some(types::UsbClasses::new(usbd, ccid, ctaphid, /*keyboard,*/ serial));
+            usb_classes = Some(types::UsbClasses::new(
+                usbd,
+                ccid,
+                ctaphid,
+                #[cfg(feature = "keyboard")]
+                keyboard,
+                serial,
+            ));
 
         }
+        // nothing to type with
+        #[cfg(feature = "keyboard")]
+        let keyboard_requester = usb_classes.as_ref().and(Some(keyboard_requester));
 
         // Cancel any possible outstanding use in delay timing
         basic_stage.delay_timer.cancel().ok();
@@ -557,6 +589,9 @@ impl Initializer {
             usb_classes,
             contact_responder: Some(contact_responder),
             ctaphid_responder: Some(ctaphid_responder),
+            escape_responder: Some(escape_responder),
+            #[cfg(feature = "keyboard")]
+            keyboard_requester,
         }
     }
 
@@ -570,10 +605,14 @@ impl Initializer {
         let ctaphid_dispatch = types::CtaphidDispatch::new(
             usb_stage.ctaphid_responder.take().unwrap()
         );
+        let escape_dispatch = types::EscapeDispatch::new(
+            usb_stage.escape_responder.take().unwrap()
+        );
 
         stages::Interfaces {
             apdu_dispatch,
             ctaphid_dispatch,
+            escape_dispatch,
         }
     }
 
@@ -911,18 +950,20 @@ impl Initializer {
             pint
         );
 
+        // the RNG is needed for the CTAPHID channel IDs
+        let mut flash_stage = self.initialize_flash(
+            rng,
+            prince,
+            flash,
+        );
         let mut usb_stage = self.initialize_usb(
             &mut clock_stage,
             &mut basic_stage,
+            &mut flash_stage,
             usbhs,
             usbfs
         );
         let interfaces_stage = self.initialize_interfaces(&mut nfc_stage, &mut usb_stage);
-        let mut flash_stage = self.initialize_flash(
-            rng,
-            prince,
-            flash,
-        );
         let mut filesystem_stage = self.initialize_filesystem(
             &mut clock_stage,
             &mut basic_stage,
@@ -59,12 +59,17 @@ pub struct Usb {
 
     pub contact_responder: Option<interchange::Responder<apdu_dispatch::interchanges::Contact>>,
     pub ctaphid_responder: Option<interchange::Responder<ctaphid_dispatch::types::HidInterchange>>,
+    pub escape_responder: Option<interchange::Responder<usbd_ccid::escape::EscapeInterchange>>,
+    /// for the static password app, if there is a keyboard
+    #[cfg(feature = "keyboard")]
+    pub keyboard_requester: Option<interchange::Requester<usbd_keyboard::types::KeyboardInterchange>>,
 }
 
-/// Initialized apdu + ctaphid dispatches
+/// Initialized apdu + ctaphid + escape dispatches
 pub struct Interfaces {
     pub apdu_dispatch: types::ApduDispatch,
     pub ctaphid_dispatch: types::CtaphidDispatch,
+    pub escape_dispatch: types::EscapeDispatch,
 }
 
 /// Initialized flash driver, prince, RNG.
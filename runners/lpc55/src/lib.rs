@@ -16,6 +16,10 @@ use admin_app::Reboot;
 extern crate delog;
 generate_macros!();
 
+#[cfg(all(feature = "keyboard", feature = "usbfs-peripheral"))]
+compile_error!("USBFS has no endpoint to spare for the keyboard");
+
+pub mod escape;
 pub mod types;
 pub mod initializer;
 
@@ -61,6 +65,7 @@ pub fn init_board(
     // types::Authenticator,
     types::ApduDispatch,
     types::CtaphidDispatch,
+    types::EscapeDispatch,
     types::Trussed,
 
     types::Apps,
@@ -138,6 +143,8 @@ pub fn init_board(
 
     let apps = types::Apps::new(
         &mut everything.trussed,
+        #[cfg(feature = "keyboard")]
+        everything.usb.keyboard_requester.take(),
         #[cfg(feature = "provisioner-app")]
         {
             types::ProvisionerNonPortable {
@@ -153,6 +160,7 @@ pub fn init_board(
     (
         everything.interfaces.apdu_dispatch,
         everything.interfaces.ctaphid_dispatch,
+        everything.interfaces.escape_dispatch,
         everything.trussed,
 
         apps,
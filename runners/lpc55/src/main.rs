@@ -32,6 +32,18 @@ pub fn msp() -> u32 {
   r
 }
 
+/// Tells the user interface whether the host cancelled the CTAPHID request being processed,
+/// so it stops waiting for user presence.
+fn track_ctaphid_cancel(ctaphid: &mut runner::types::CtapHidClass, status: &usbd_ctaphid::types::Status) {
+    let user_presence = runner::types::UserInterface::user_presence_status();
+    if let usbd_ctaphid::types::Status::ReceivedData(_) = status {
+        user_presence.set_cancelled(false);
+    }
+    if ctaphid.did_cancel_processing() {
+        user_presence.set_cancelled(true);
+    }
+}
+
 #[rtic::app(device = runner::hal::raw, peripherals = true, monotonic = board::shared::Monotonic)]
 const APP: () = {
 
@@ -42,6 +54,9 @@ const APP: () = {
         /// Dispatches CTAPHID messages to apps.
         ctaphid_dispatch: runner::types::CtaphidDispatch,
 
+        /// Dispatches CCID escape commands to apps.
+        escape_dispatch: runner::types::EscapeDispatch,
+
         /// The Trussed service, used by all applications.
         trussed: runner::types::Trussed,
 
@@ -89,6 +104,7 @@ const APP: () = {
         let (
             apdu_dispatch,
             ctaphid_dispatch,
+            escape_dispatch,
             trussed,
 
             apps,
@@ -111,6 +127,7 @@ const APP: () = {
         init::LateResources {
             apdu_dispatch,
             ctaphid_dispatch,
+            escape_dispatch,
             trussed,
 
             apps,
@@ -125,14 +142,16 @@ const APP: () = {
         }
     }
 
-    #[idle(resources = [apdu_dispatch, ctaphid_dispatch, apps, perf_timer, usb_classes], schedule = [ccid_wait_extension, ctaphid_keepalive])]
+    #[idle(resources = [apdu_dispatch, ctaphid_dispatch, escape_dispatch, apps, perf_timer, usb_classes, contactless], schedule = [ccid_wait_extension, ctaphid_keepalive])]
     fn idle(c: idle::Context) -> ! {
         let idle::Resources {
             apdu_dispatch,
             ctaphid_dispatch,
+            escape_dispatch,
             apps,
             mut perf_timer,
             mut usb_classes,
+            mut contactless,
         }
             = c.resources;
 
@@ -152,6 +171,23 @@ const APP: () = {
                 runner::Delogger::flush();
             }
 
+            // the card was powered off or on, or left the field: like a real card,
+            // forget about the selected app and PIN verification before the next command
+            let ccid_session_reset = usb_classes.lock(|usb_classes_maybe| {
+                usb_classes_maybe.as_mut().map_or(false, |usb_classes| usb_classes.ccid.did_reset_session(0))
+            });
+            let nfc_session_reset = contactless.lock(|contactless_maybe| {
+                contactless_maybe.as_mut().map_or(false, |contactless| contactless.did_reset_session())
+            });
+            if ccid_session_reset || nfc_session_reset {
+                apps.reset_apdu_session();
+            }
+
+            // e.g. to type the static password
+            if runner::types::UserInterface::touches().take() {
+                apps.touched();
+            }
+
             match apps.apdu_dispatch(|apps| apdu_dispatch.poll(apps)) {
 
                 Some(apdu_dispatch::dispatch::Interface::Contact) => {
@@ -167,6 +203,10 @@ const APP: () = {
                 rtic::pend(USB_INTERRUPT);
             }
 
+            if apps.escape_dispatch(|apps| escape_dispatch.poll(apps)) {
+                rtic::pend(USB_INTERRUPT);
+            }
+
             usb_classes.lock(|usb_classes_maybe|{
                 if usb_classes_maybe.is_some() {
 
@@ -185,7 +225,9 @@ const APP: () = {
                         _ => {}
                     }
 
-                    match usb_classes.ctaphid.did_start_processing() {
+                    let ctaphid_status = usb_classes.ctaphid.did_start_processing();
+                    track_ctaphid_cancel(&mut usb_classes.ctaphid, &ctaphid_status);
+                    match ctaphid_status {
                         usbd_ctaphid::types::Status::ReceivedData(milliseconds) => {
                             schedule.ctaphid_keepalive(
                                 // Instant::now() + (CLOCK_FREQ/1_000 * milliseconds.0).cycles()
@@ -223,6 +265,14 @@ const APP: () = {
         // }
         usb_classes.poll();
 
+        // the bulk packet size follows the speed negotiated at the last bus reset,
+        // e.g. full speed behind a USB 1.1 hub
+        #[cfg(feature = "highspeed")]
+        usb_classes.ccid.set_bus_speed(match usb.devcmdstat.read().speed().bits() {
+            0b10 => usbd_ccid::constants::UsbSpeed::HighSpeed,
+            _ => usbd_ccid::constants::UsbSpeed::FullSpeed,
+        });
+
         match usb_classes.ccid.did_start_processing() {
             usbd_ccid::types::Status::ReceivedData(milliseconds) => {
                 // if remaining < 60_000 {
@@ -235,7 +285,9 @@ const APP: () = {
             }
             _ => {}
         }
-        match usb_classes.ctaphid.did_start_processing() {
+        let ctaphid_status = usb_classes.ctaphid.did_start_processing();
+        track_ctaphid_cancel(&mut usb_classes.ctaphid, &ctaphid_status);
+        match ctaphid_status {
             usbd_ctaphid::types::Status::ReceivedData(milliseconds) => {
                 // if remaining < 60_000 {
                 //     debug_now!("scheduling CTAPHID wait extension");
@@ -303,9 +355,8 @@ const APP: () = {
     fn ctaphid_keepalive(c: ctaphid_keepalive::Context) {
         debug_now!("CTAPHID keepalive");
         debug_now!("remaining stack size: {} bytes", msp() - 0x2000_0000);
-        let status = c.resources.usb_classes.as_mut().unwrap().ctaphid.send_keepalive(
-            board::trussed::UserPresenceStatus::waiting()
-        );
+        let keepalive_status = runner::types::UserInterface::user_presence_status().keepalive_status();
+        let status = c.resources.usb_classes.as_mut().unwrap().ctaphid.send_keepalive(keepalive_status);
         match status {
             usbd_ctaphid::types::Status::ReceivedData(milliseconds) => {
                 c.schedule.ctaphid_keepalive(
@@ -194,11 +194,12 @@ impl<'a, SPI: Transfer<u8>> Transfer<u8> for SpiMut<'a, SPI> {
 
 pub type ThreeButtons = board::ThreeButtons;
 pub type RgbLed = board::RgbLed;
+pub type UserInterface = board::trussed::UserInterface<ThreeButtons, RgbLed>;
 
 platform!(Board,
     R: hal::peripherals::rng::Rng<hal::Enabled>,
     S: Store,
-    UI: board::trussed::UserInterface<ThreeButtons, RgbLed>,
+    UI: UserInterface,
 );
 
 #[derive(Default)]
@@ -220,6 +221,7 @@ pub type ExternalInterrupt = hal::Pint<hal::typestates::init_state::Enabled>;
 
 pub type ApduDispatch = apdu_dispatch::dispatch::ApduDispatch;
 pub type CtaphidDispatch = ctaphid_dispatch::dispatch::Dispatch;
+pub type EscapeDispatch = usbd_ccid::escape::Dispatch;
 
 #[cfg(feature = "admin-app")]
 pub type AdminApp = admin_app::App<TrussedClient, Reboot>;
@@ -231,11 +233,14 @@ pub type FidoApp = fido_authenticator::Authenticator<fido_authenticator::Conform
 pub type FidoConfig = fido_authenticator::Config;
 #[cfg(feature = "ndef-app")]
 pub type NdefApp = ndef_app::App<'static>;
+#[cfg(feature = "static-password-app")]
+pub type StaticPasswordApp = static_password_app::App<TrussedClient>;
 #[cfg(feature = "provisioner-app")]
 pub type ProvisionerApp = provisioner_app::Provisioner<Store, FlashStorage, TrussedClient>;
 
 use apdu_dispatch::{App as ApduApp, command::SIZE as CommandSize, response::SIZE as ResponseSize};
 use ctaphid_dispatch::app::{App as CtaphidApp};
+use usbd_ccid::escape::App as EscapeApp;
 
 pub type DynamicClockController = board::clock_controller::DynamicClockController;
 pub type NfcWaitExtender = timer::Timer<ctimer::Ctimer0<hal::typestates::init_state::Enabled>>;
@@ -313,6 +318,16 @@ impl TrussedApp for FidoApp {
     }
 }
 
+#[cfg(feature = "static-password-app")]
+impl TrussedApp for StaticPasswordApp {
+    const CLIENT_ID: &'static [u8] = b"password\0";
+
+    type NonPortable = Option<interchange::Requester<usbd_keyboard::types::KeyboardInterchange>>;
+    fn with_client(trussed: TrussedClient, keyboard: Self::NonPortable) -> Self {
+        Self::new(trussed, keyboard)
+    }
+}
+
 pub struct ProvisionerNonPortable {
     pub store: Store,
     pub stolen_filesystem: &'static mut FlashStorage,
@@ -341,13 +356,19 @@ pub struct Apps {
     pub oath: OathApp,
     #[cfg(feature = "ndef-app")]
     pub ndef: NdefApp,
+    #[cfg(feature = "static-password-app")]
+    pub static_password: StaticPasswordApp,
     #[cfg(feature = "provisioner-app")]
     pub provisioner: ProvisionerApp,
+    pub device_info: crate::escape::DeviceInfo,
+    apdu_session: apdu_session::Session,
 }
 
 impl Apps {
     pub fn new(
         trussed: &mut trussed::Service<crate::Board>,
+        #[cfg(feature = "keyboard")]
+        keyboard: Option<interchange::Requester<usbd_keyboard::types::KeyboardInterchange>>,
         #[cfg(feature = "provisioner-app")]
         provisioner: ProvisionerNonPortable
     ) -> Self {
@@ -359,6 +380,10 @@ impl Apps {
         let oath = OathApp::with(trussed, ());
         #[cfg(feature = "ndef-app")]
         let ndef = NdefApp::new();
+        #[cfg(all(feature = "static-password-app", feature = "keyboard"))]
+        let static_password = StaticPasswordApp::with(trussed, keyboard);
+        #[cfg(all(feature = "static-password-app", not(feature = "keyboard")))]
+        let static_password = StaticPasswordApp::with(trussed, None);
         #[cfg(feature = "provisioner-app")]
         let provisioner = ProvisionerApp::with(trussed, provisioner);
 
@@ -371,8 +396,12 @@ impl Apps {
             oath,
             #[cfg(feature = "ndef-app")]
             ndef,
+            #[cfg(feature = "static-password-app")]
+            static_password,
             #[cfg(feature = "provisioner-app")]
             provisioner,
+            device_info: Default::default(),
+            apdu_session: Default::default(),
         }
     }
 
@@ -383,7 +412,22 @@ impl Apps {
                 ApduApp<CommandSize, ResponseSize>
             ]) -> T
     {
-        f(&mut [
+        self.apdu_apps(|session, apps| session.dispatch(apps, f))
+    }
+
+    /// Deselects the selected APDU app, once the card was powered off or on,
+    /// or left the field.
+    pub fn reset_apdu_session(&mut self) {
+        self.apdu_apps(|session, apps| session.reset(apps))
+    }
+
+    fn apdu_apps<F, T>(&mut self, f: F) -> T
+    where
+        F: FnOnce(&mut apdu_session::Session, &mut [&mut dyn
+                ApduApp<CommandSize, ResponseSize>
+            ]) -> T
+    {
+        f(&mut self.apdu_session, &mut [
             #[cfg(feature = "ndef-app")]
             &mut self.ndef,
             #[cfg(feature = "oath-authenticator")]
@@ -392,11 +436,19 @@ impl Apps {
             &mut self.fido,
             #[cfg(feature = "admin-app")]
             &mut self.admin,
+            #[cfg(feature = "static-password-app")]
+            &mut self.static_password,
             #[cfg(feature = "provisioner-app")]
             &mut self.provisioner,
         ])
     }
 
+    /// The user touched the device outside of a user presence check.
+    pub fn touched(&mut self) {
+        #[cfg(feature = "static-password-app")]
+        self.static_password.touched();
+    }
+
     #[inline(never)]
     pub fn ctaphid_dispatch<F, T>(&mut self, f: F) -> T
     where
@@ -409,4 +461,12 @@ impl Apps {
             &mut self.admin,
         ])
     }
+
+    #[inline(never)]
+    pub fn escape_dispatch<F, T>(&mut self, f: F) -> T
+    where
+        F: FnOnce(&mut [&mut dyn EscapeApp ]) -> T
+    {
+        f(&mut [&mut self.device_info])
+    }
 }
@@ -13,7 +13,8 @@ pub type CcidClass = usbd_ccid::Ccid<
     {apdu_dispatch::interchanges::SIZE},
 >;
 pub type CtapHidClass = usbd_ctaphid::CtapHid<'static, UsbBus<EnabledUsbPeripheral>>;
-// pub type KeyboardClass = usbd_hid::hid_class::HIDClass<'static, UsbBus<EnabledUsbPeripheral>>;
+#[cfg(feature = "keyboard")]
+pub type KeyboardClass = usbd_keyboard::Keyboard<'static, UsbBus<EnabledUsbPeripheral>>;
 pub type SerialClass = usbd_serial::SerialPort<'static, UsbBus<EnabledUsbPeripheral>>;
 
 type Usbd = usb_device::device::UsbDevice<'static, UsbBus<EnabledUsbPeripheral>>;
@@ -22,20 +23,38 @@ pub struct UsbClasses {
     pub usbd: Usbd,
     pub ccid: CcidClass,
     pub ctaphid: CtapHidClass,
-    // pub keyboard: KeyboardClass,
+    #[cfg(feature = "keyboard")]
+    pub keyboard: KeyboardClass,
     pub serial: SerialClass,
 }
 
 impl UsbClasses {
-    pub fn new(usbd: Usbd, ccid: CcidClass, ctaphid: CtapHidClass, serial: SerialClass) -> Self {
-        Self{ usbd, ccid, ctaphid, serial }
+    pub fn new(
+        usbd: Usbd,
+        ccid: CcidClass,
+        ctaphid: CtapHidClass,
+        #[cfg(feature = "keyboard")] keyboard: KeyboardClass,
+        serial: SerialClass,
+    ) -> Self {
+        Self{
+            usbd,
+            ccid,
+            ctaphid,
+            #[cfg(feature = "keyboard")]
+            keyboard,
+            serial,
+        }
     }
     pub fn poll(&mut self) {
         self.ctaphid.check_for_app_response();
         self.ccid.check_for_app_response();
+        #[cfg(feature = "keyboard")]
+        self.keyboard.check_for_app_request();
         self.usbd.poll(&mut [
             &mut self.ccid,
             &mut self.ctaphid,
+            #[cfg(feature = "keyboard")]
+            &mut self.keyboard,
             &mut self.serial,
         ]);
     }
@@ -97,6 +97,11 @@ class CCID:
 
         return atr
 
+    def exchange_raw(self, msg):
+        self.dev.write(self.bulkout, msg, self.timeout)
+        self.increment_seq()
+        return self.ccid_get_result()[2]
+
     def power_off(self):
         msg = ccid_compose(0x63, self.seq, rsv=1)  # Vcc=5V
         self.dev.write(self.bulkout, msg, self.timeout)
@@ -136,3 +141,75 @@ def test_connect(ccid):
     assert 0x80 == atr[0]
     l = atr[1]
     assert [0x3B, 0x8C, 0x80, 0x01] == list(atr[10 : 10 + l])
+
+
+def assert_still_responsive(ccid):
+    msg = ccid.exchange_raw(ccid_compose(0x65, ccid.seq))
+    assert 0x81 == msg[0]
+    assert 0 == msg[7] >> 6
+
+
+def test_short_packet(ccid):
+    """Packets shorter than the header are answered if bSeq is there."""
+
+    seq = ccid.seq
+    msg = ccid.exchange_raw(ccid_compose(0x65, seq)[:8])
+    # RDR_to_PC_SlotStatus, failed, bad dwLength
+    assert [0x81, seq, 1, 1] == [msg[0], msg[6], msg[7] >> 6, msg[8]]
+
+    assert_still_responsive(ccid)
+
+
+def test_missing_data(ccid):
+    """XfrBlock announcing more data than sent."""
+
+    msg = ccid_compose(0x6F, ccid.seq, data=b"\x00\xa4\x04\x00")
+    msg = msg[:1] + struct.pack("<i", 40) + msg[5:]
+    msg = ccid.exchange_raw(msg)
+    # RDR_to_PC_DataBlock, failed, bad dwLength
+    assert [0x80, 1, 1] == [msg[0], msg[7] >> 6, msg[8]]
+
+    assert_still_responsive(ccid)
+
+
+def test_invalid_level_parameter(ccid):
+    msg = ccid.exchange_raw(ccid_compose(0x6F, ccid.seq, param=0x1234, data=b"\x00\xa4\x04\x00"))
+    # RDR_to_PC_DataBlock, failed, bad wLevelParameter
+    assert [0x80, 1, 8] == [msg[0], msg[7] >> 6, msg[8]]
+
+    assert_still_responsive(ccid)
+
+
+def test_unexpected_chain_parameter(ccid):
+    """Continuation of a command chain that was never started."""
+
+    msg = ccid.exchange_raw(ccid_compose(0x6F, ccid.seq, param=3, data=b"\x00\xa4\x04\x00"))
+    assert [0x80, 1, 8] == [msg[0], msg[7] >> 6, msg[8]]
+
+    assert_still_responsive(ccid)
+
+
+def test_nonexistent_slot(ccid):
+    msg = ccid.exchange_raw(ccid_compose(0x65, ccid.seq, slot=7))
+    # failed, no ICC present, bad bSlot
+    assert [0x81, 1, 2, 5] == [msg[0], msg[7] >> 6, msg[7] & 3, msg[8]]
+
+    assert_still_responsive(ccid)
+
+
+def test_unknown_command(ccid):
+    msg = ccid.exchange_raw(ccid_compose(0x99, ccid.seq))
+    # failed, CMD_NOT_SUPPORTED
+    assert [0x81, 1, 0] == [msg[0], msg[7] >> 6, msg[8]]
+
+    assert_still_responsive(ccid)
+
+
+def test_power_off_deactivates(ccid):
+    ccid.power_off()
+    msg = ccid.exchange_raw(ccid_compose(0x65, ccid.seq))
+    # present, inactive
+    assert [0x81, 0, 1] == [msg[0], msg[7] >> 6, msg[7] & 3]
+
+    ccid.power_on()
+    assert_still_responsive(ccid)